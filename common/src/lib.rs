@@ -11,6 +11,54 @@ pub const MACHINE_KEY_EXPR: &str = "m";
 pub const LIVELINESS_KEY_EXPR: &str = "l";
 pub const COMMAND_KEY_EXPR: &str = "c";
 pub const BOOTID_KEY_EXPR: &str = "b";
+pub const CONNECTIONS_KEY_EXPR: &str = "n";
+pub const METRICS_KEY_EXPR: &str = "s";
+pub const PROCESSES_KEY_EXPR: &str = "p";
+pub const UNITS_KEY_EXPR: &str = "u";
+pub const KMSG_KEY_EXPR: &str = "k";
+pub const EVENTS_KEY_EXPR: &str = "e";
+pub const HISTORY_KEY_EXPR: &str = "h";
+pub const HEALTH_KEY_EXPR: &str = "w";
+pub const VERSION_KEY_EXPR: &str = "v";
+
+// Series pwservice can keep a history of, last chunk of the history key expression.
+pub const METRICS_SERIES: &str = "metrics";
+pub const PROCESSES_SERIES: &str = "processes";
+pub const EVENTS_SERIES: &str = "events";
+
+// Commands, last chunk of the command key expression.
+pub const OPEN_FILES_COMMAND: &str = "fds";
+pub const KMSG_COMMAND: &str = "kmsg";
+pub const STOP_STREAM_COMMAND: &str = "stop";
+pub const SIGNAL_COMMAND: &str = "signal";
+pub const RENICE_COMMAND: &str = "renice";
+pub const AFFINITY_COMMAND: &str = "affinity";
+pub const DETAIL_COMMAND: &str = "detail";
+
+/// Version of the messages in pw.messages.proto and of the key expressions, the fleet can not be upgraded at
+/// once so pwservice and pwclient of different versions must work together where they can.
+///
+/// Compatible changes increase the version:
+/// - New messages, fields, enum values, oneof variants, key expressions, commands and history series.
+///   Older readers skip fields they do not know, newer readers get the default value (0, "" or not set) for
+///   fields an older writer does not send.
+///
+/// Incompatible changes increase the version and set PROTOCOL_COMPATIBLE to it:
+/// - Changing the number, type or meaning of a field, or reusing the number of a removed field (reserve it).
+/// - Changing the layout of a key expression or what is published on it.
+///
+/// Services from before there were protocol versions send no protocol, they are version 1. Version 2 added the
+/// protocol itself, version 3 the build of the service (VersionInfo, the version key expression).
+///
+/// Liveliness tokens carry no protocol, neither as data, which they can not have, nor in their key expression.
+/// A version chunk (e.g. "pw/<group>/l/<id>/v3") would change the layout of the key expression, and clients
+/// from before it, subscribed to "pw/*/l/*", would see every upgraded machine as offline. A token only says
+/// whether a machine is online, which every version reads the same way, the protocol of the machine is in its
+/// machine record.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Readers need at least this version to read what we send, the version of the last incompatible change.
+pub const PROTOCOL_COMPATIBLE: u32 = 1;
 
 pub mod pw {
     pub mod messages {
@@ -30,6 +78,95 @@ pub fn deserialize_machine(buf: &[u8]) -> Result<pw::messages::Machine, prost::D
     pw::messages::Machine::decode(buf)
 }
 
+pub fn serialize<M: Message>(message: &M) -> Vec<u8> {
+    message.encode_to_vec()
+}
+
+pub fn deserialize<M: Message + Default>(buf: &[u8]) -> Result<M, prost::DecodeError> {
+    M::decode(buf)
+}
+
+/// The protocol we speak.
+pub fn protocol() -> pw::messages::Protocol {
+    pw::messages::Protocol {
+        version: PROTOCOL_VERSION,
+        compatible: PROTOCOL_COMPATIBLE,
+    }
+}
+
+/// The protocol of a sample from its attachment, version 1 if it has none or it is not a protocol.
+pub fn peer_protocol(attachment: Option<&[u8]>) -> pw::messages::Protocol {
+    attachment
+        .and_then(|attachment| deserialize::<pw::messages::Protocol>(attachment).ok())
+        .filter(|protocol| protocol.version > 0)
+        .unwrap_or(pw::messages::Protocol {
+            version: 1,
+            compatible: 1,
+        })
+}
+
+/// How a peer's protocol relates to ours.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compatibility {
+    Same,
+    /// Fields added after its version are missing (default values).
+    Older,
+    /// Fields added after our version are skipped.
+    Newer,
+    /// Its messages can not be read, or it can not read ours.
+    Incompatible,
+}
+
+pub fn compatibility(peer: &pw::messages::Protocol) -> Compatibility {
+    if peer.version < PROTOCOL_COMPATIBLE || peer.compatible > PROTOCOL_VERSION {
+        Compatibility::Incompatible
+    } else if peer.version < PROTOCOL_VERSION {
+        Compatibility::Older
+    } else if peer.version > PROTOCOL_VERSION {
+        Compatibility::Newer
+    } else {
+        Compatibility::Same
+    }
+}
+
+/// What a peer's protocol means for what we show, None if it is ours.
+pub fn stringify_compatibility(peer: &pw::messages::Protocol) -> Option<String> {
+    let what = match compatibility(peer) {
+        Compatibility::Same => return None,
+        Compatibility::Older => "older, values it does not send are empty",
+        Compatibility::Newer => "newer, values added since are not shown",
+        Compatibility::Incompatible => "incompatible, nothing it sends is shown",
+    };
+
+    Some(format!(
+        "protocol {} {} (this build speaks {})",
+        peer.version, what, PROTOCOL_VERSION
+    ))
+}
+
+/// "pwservice v0.1.0 release (Git SHA: 1a2b3c4, dirty: false, build time: 2024-11-09T14:30:05Z, target:
+/// armv7-unknown-linux-gnueabihf, features: systemd, protocol: 2)".
+pub fn stringify_version_info(info: &pw::messages::VersionInfo) -> String {
+    let features = if info.features.is_empty() {
+        "none".to_string()
+    } else {
+        info.features.join(",")
+    };
+
+    format!(
+        "{} v{} {} (Git SHA: {}, dirty: {}, build time: {}, target: {}, features: {}, protocol: {})",
+        info.name,
+        info.version,
+        if info.debug { "debug" } else { "release" },
+        info.git_sha,
+        info.git_dirty,
+        info.build_timestamp,
+        info.target_triple,
+        features,
+        info.protocol.map_or(1, |protocol| protocol.version)
+    )
+}
+
 pub fn stringify_duration(seconds: u64) -> String {
     let then = std::time::UNIX_EPOCH + Duration::from_secs(seconds);
     let datetime = DateTime::<Utc>::from(then);
@@ -38,16 +175,904 @@ pub fn stringify_duration(seconds: u64) -> String {
 }
 
 pub fn stringify_message(machine: &pw::messages::Machine) -> Vec<(&str, String)> {
-    vec![
+    let mut info = vec![
         ("booted", stringify_duration(machine.boottime)),
         ("hostname", machine.hostname.clone()),
-        ("kernel", machine.version.clone()),
+        ("kernel", kernel_release(machine)),
         ("CPU", machine.cpu_model_name.clone()),
         (
             "RAM",
             format!("{} MB", (machine.physical_mem_total_kb / 1000)),
         ),
-    ]
+    ];
+
+    // Hardware inventory, only what the machine reported.
+    if machine.cpu_threads > 0 {
+        info.push((
+            "cores",
+            format!(
+                "{} cores, {} threads",
+                machine.cpu_cores, machine.cpu_threads
+            ),
+        ));
+    }
+
+    let dmi = format!("{} {}", machine.dmi_vendor, machine.dmi_product);
+    let os_release = machine.os_release.clone().unwrap_or_default();
+    let optional = [
+        ("os", os_version(machine).unwrap_or_default()),
+        ("os build", os_release.build_id),
+        ("fingerprint", os_release.fingerprint),
+        ("arch", machine.architecture.clone()),
+        ("model", machine.device_tree_model.clone()),
+        ("model", dmi.trim().to_string()),
+        ("hardware", machine.hardware.clone()),
+        ("revision", machine.revision.clone()),
+        ("serial", machine.serial.clone()),
+    ];
+    for (key, value) in optional {
+        if !value.is_empty() {
+            info.push((key, value));
+        }
+    }
+
+    if machine.storage_total_bytes > 0 {
+        info.push(("storage", stringify_bytes(machine.storage_total_bytes)));
+    }
+
+    if let Some(service) = &machine.service {
+        info.push(("service", stringify_version_info(service)));
+    }
+
+    // Services from before there were protocol versions do not send it.
+    let protocol = machine.protocol.unwrap_or(peer_protocol(None));
+    if let Some(compatibility) = stringify_compatibility(&protocol) {
+        info.push(("protocol", compatibility));
+    }
+
+    info
+}
+
+/// Distribution and version of machine, e.g. "Raspbian GNU/Linux 11 (bullseye)" or "debian 12".
+pub fn os_version(machine: &pw::messages::Machine) -> Option<String> {
+    let release = machine.os_release.as_ref()?;
+
+    if !release.pretty_name.is_empty() {
+        return Some(release.pretty_name.clone());
+    }
+
+    let version = format!("{} {}", release.id, release.version_id);
+    let version = version.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Kernel release with architecture, e.g. "6.1.21-v7+ armv7l", the full /proc/version from older services.
+/// The architecture is not repeated if the release ends with it, e.g. "6.1.0-13-amd64".
+pub fn kernel_release(machine: &pw::messages::Machine) -> String {
+    match &machine.kernel {
+        Some(kernel) if !kernel.release.is_empty() => {
+            // Services from before the kernel had an architecture only have the one of the machine.
+            let arch = if kernel.arch.is_empty() {
+                &machine.architecture
+            } else {
+                &kernel.arch
+            };
+            if kernel.release.ends_with(arch.as_str()) {
+                kernel.release.clone()
+            } else {
+                format!("{} {}", kernel.release, arch).trim().to_string()
+            }
+        }
+        _ => machine.version.clone(),
+    }
+}
+
+pub fn stringify_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} {}", bytes, UNITS[0]);
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn percentage(part: u64, total: u64) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+
+    part * 100 / total
+}
+
+pub fn stringify_filesystem(filesystem: &pw::messages::Filesystem) -> String {
+    let used = filesystem.total_bytes.saturating_sub(filesystem.free_bytes);
+    let used_inodes = filesystem
+        .total_inodes
+        .saturating_sub(filesystem.free_inodes);
+
+    format!(
+        "{} ({} {}{}): {} of {} used ({}%), {}% inodes used",
+        filesystem.mount_point,
+        filesystem.device,
+        filesystem.fs_type,
+        if filesystem.read_only { ", ro" } else { "" },
+        stringify_bytes(used),
+        stringify_bytes(filesystem.total_bytes),
+        percentage(used, filesystem.total_bytes),
+        percentage(used_inodes, filesystem.total_inodes)
+    )
+}
+
+pub fn stringify_block_device(device: &pw::messages::BlockDevice) -> String {
+    format!(
+        "{}: read {}/s, write {}/s, {:.0}% busy, {} written since boot",
+        device.name,
+        stringify_bytes(device.read_bytes_per_second),
+        stringify_bytes(device.written_bytes_per_second),
+        device.utilization,
+        stringify_bytes(device.written_bytes)
+    )
+}
+
+pub fn stringify_uptime(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = (seconds % 86400) / 3600;
+    let minutes = (seconds % 3600) / 60;
+
+    if days > 0 {
+        return format!("{}d {:02}:{:02}", days, hours, minutes);
+    }
+
+    format!("{:02}:{:02}", hours, minutes)
+}
+
+pub fn stringify_load_average(load: &pw::messages::LoadAverage) -> String {
+    format!(
+        "load {:.2} {:.2} {:.2}, {}/{} tasks running",
+        load.one, load.five, load.fifteen, load.running, load.total
+    )
+}
+
+/// Percentage of memory available, None if the machine did not report its memory.
+pub fn memory_available_percent(metrics: &pw::messages::Metrics) -> Option<f64> {
+    let memory = metrics
+        .memory
+        .as_ref()
+        .filter(|memory| memory.total_kb > 0)?;
+
+    Some(memory.available_kb as f64 * 100.0 / memory.total_kb as f64)
+}
+
+pub fn stringify_memory(memory: &pw::messages::Memory) -> String {
+    let mut line = format!(
+        "memory {} of {} available ({}%)",
+        stringify_bytes(memory.available_kb * 1024),
+        stringify_bytes(memory.total_kb * 1024),
+        percentage(memory.available_kb, memory.total_kb)
+    );
+
+    if memory.swap_total_kb > 0 {
+        line = format!(
+            "{}, swap {} of {} free",
+            line,
+            stringify_bytes(memory.swap_free_kb * 1024),
+            stringify_bytes(memory.swap_total_kb * 1024)
+        );
+    }
+
+    line
+}
+
+/// Pressure of all resources the kernel reports, as "some" average over 10 seconds.
+pub fn stringify_pressure(metrics: &pw::messages::Metrics) -> Option<String> {
+    let resources = [
+        ("cpu", &metrics.cpu_pressure),
+        ("memory", &metrics.memory_pressure),
+        ("io", &metrics.io_pressure),
+    ];
+
+    let values: Vec<String> = resources
+        .iter()
+        .filter_map(|(name, pressure)| {
+            pressure
+                .as_ref()
+                .map(|pressure| format!("{} {:.1}%", name, pressure.some_avg10))
+        })
+        .collect();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(format!("pressure {}", values.join(", ")))
+}
+
+/// Raspberry Pi firmware get_throttled flags that are active right now, higher bits are history since boot.
+const RPI_THROTTLED_FLAGS: [(u32, &str); 4] = [
+    (0x1, "under-voltage"),
+    (0x2, "frequency capped"),
+    (0x4, "throttled"),
+    (0x8, "soft temperature limit"),
+];
+
+fn celsius(millidegrees: i32) -> f64 {
+    millidegrees as f64 / 1000.0
+}
+
+/// Highest temperature of all thermal zones in degree Celsius.
+pub fn max_temperature(metrics: &pw::messages::Metrics) -> Option<f64> {
+    metrics
+        .thermal_zones
+        .iter()
+        .map(|zone| celsius(zone.temperature))
+        .reduce(f64::max)
+}
+
+/// Why the machine is (probably) thermally throttled, empty if it is not.
+pub fn throttling_reasons(metrics: &pw::messages::Metrics) -> Vec<String> {
+    let mut reasons = vec![];
+
+    for (flag, reason) in RPI_THROTTLED_FLAGS {
+        if metrics.throttled & flag != 0 {
+            reasons.push(reason.to_string());
+        }
+    }
+
+    for zone in &metrics.thermal_zones {
+        if zone.passive_trip > 0 && zone.temperature >= zone.passive_trip {
+            reasons.push(format!(
+                "{} above passive trip point {:.1}°C",
+                zone.name,
+                celsius(zone.passive_trip)
+            ));
+        }
+    }
+
+    let limited = metrics
+        .cpu_frequencies
+        .iter()
+        .find(|cpu| cpu.max_khz > 0 && cpu.max_khz < cpu.hardware_max_khz);
+    if let Some(cpu) = limited {
+        reasons.push(format!(
+            "cpu{} limited to {} MHz of {} MHz",
+            cpu.cpu,
+            cpu.max_khz / 1000,
+            cpu.hardware_max_khz / 1000
+        ));
+    }
+
+    reasons
+}
+
+pub fn stringify_sensor(sensor: &pw::messages::Sensor) -> String {
+    use pw::messages::sensor::Sensorkind;
+
+    let value = match sensor.kind() {
+        Sensorkind::Temperature => format!("{:.1}°C", sensor.value),
+        Sensorkind::Voltage => format!("{:.2} V", sensor.value),
+        Sensorkind::Current => format!("{:.3} A", sensor.value),
+        Sensorkind::Power => format!("{:.2} W", sensor.value),
+        Sensorkind::Fan => format!("{:.0} RPM", sensor.value),
+    };
+
+    format!("{} {}: {}", sensor.chip, sensor.label, value)
+}
+
+pub fn stringify_thermal(metrics: &pw::messages::Metrics) -> Vec<String> {
+    let mut lines = vec![];
+
+    for zone in &metrics.thermal_zones {
+        let mut line = format!("{}: {:.1}°C", zone.name, celsius(zone.temperature));
+        if zone.passive_trip > 0 {
+            line = format!("{}, passive {:.1}°C", line, celsius(zone.passive_trip));
+        }
+        if zone.critical_trip > 0 {
+            line = format!("{}, critical {:.1}°C", line, celsius(zone.critical_trip));
+        }
+        lines.push(line);
+    }
+
+    if !metrics.cpu_frequencies.is_empty() {
+        let current: Vec<String> = metrics
+            .cpu_frequencies
+            .iter()
+            .map(|cpu| (cpu.current_khz / 1000).to_string())
+            .collect();
+        lines.push(format!("cpu frequency {} MHz", current.join("/")));
+    }
+
+    for sensor in &metrics.sensors {
+        lines.push(stringify_sensor(sensor));
+    }
+
+    let reasons = throttling_reasons(metrics);
+    if !reasons.is_empty() {
+        lines.push(format!("THROTTLING: {}", reasons.join(", ")));
+    }
+
+    lines
+}
+
+pub fn stringify_metrics(metrics: &pw::messages::Metrics) -> Vec<String> {
+    let mut lines = vec![];
+
+    let mut summary = format!("up {}", stringify_uptime(metrics.uptime));
+    if let Some(load) = &metrics.load_average {
+        summary = format!("{}, {}", summary, stringify_load_average(load));
+    }
+    lines.push(summary);
+
+    if let Some(memory) = &metrics.memory {
+        lines.push(stringify_memory(memory));
+    }
+
+    if let Some(pressure) = stringify_pressure(metrics) {
+        lines.push(pressure);
+    }
+
+    lines.append(&mut stringify_thermal(metrics));
+
+    for filesystem in &metrics.filesystems {
+        lines.push(stringify_filesystem(filesystem));
+    }
+
+    for device in &metrics.block_devices {
+        lines.push(stringify_block_device(device));
+    }
+
+    for cgroup in &metrics.cgroups {
+        if cgroup_under_pressure(cgroup) {
+            lines.push(stringify_cgroup(cgroup));
+        }
+    }
+
+    lines
+}
+
+pub fn stringify_limit(limit: u64) -> String {
+    if limit == u64::MAX {
+        return "unlimited".to_string();
+    }
+
+    limit.to_string()
+}
+
+pub fn stringify_socket(socket: &pw::messages::Socket) -> String {
+    let proto = socket.proto().as_str_name();
+    let state = socket.state().as_str_name();
+
+    if socket.proto() == pw::messages::socket::Sockproto::Unix {
+        return format!("{} {} ({})", proto, socket.local_address, state);
+    }
+
+    format!(
+        "{} {} -> {} ({})",
+        proto, socket.local_address, socket.remote_address, state
+    )
+}
+
+pub fn stringify_connection(connection: &pw::messages::Connection) -> String {
+    let socket = connection.socket.clone().unwrap_or_default();
+
+    let owner = if connection.pid == 0 {
+        "-".to_string()
+    } else {
+        format!("{}/{}", connection.pid, connection.process_name)
+    };
+
+    format!(
+        "{:<5} {:<24} {:<24} {:<11} {}",
+        socket.proto().as_str_name(),
+        socket.local_address,
+        socket.remote_address,
+        socket.state().as_str_name(),
+        owner
+    )
+}
+
+/// "docker 3f2a4b1c9d8e" with the id shortened the way docker ps does.
+pub fn stringify_container(container: &pw::messages::Container) -> String {
+    let id = container.id.get(..12).unwrap_or(&container.id);
+
+    format!("{} {}", container.runtime, id)
+}
+
+/// Container, unit or path of a cgroup, whatever is most recognisable.
+pub fn cgroup_name(cgroup: &pw::messages::CgroupUsage) -> String {
+    match &cgroup.container {
+        Some(container) => stringify_container(container),
+        None if !cgroup.unit.is_empty() => cgroup.unit.clone(),
+        None => cgroup.path.clone(),
+    }
+}
+
+pub fn stringify_cgroup(cgroup: &pw::messages::CgroupUsage) -> String {
+    let mut memory = stringify_bytes(cgroup.memory_current);
+    if cgroup.memory_max != u64::MAX {
+        memory = format!(
+            "{} of {} ({}%)",
+            memory,
+            stringify_bytes(cgroup.memory_max),
+            percentage(cgroup.memory_current, cgroup.memory_max)
+        );
+    }
+
+    let mut line = format!(
+        "{}: cpu {:.1}%, memory {}, io {}/s read, {}/s written",
+        cgroup_name(cgroup),
+        cgroup.cpu_percent,
+        memory,
+        stringify_bytes(cgroup.io_read_bytes_per_second),
+        stringify_bytes(cgroup.io_written_bytes_per_second)
+    );
+
+    if cgroup.oom > 0 || cgroup.oom_kill > 0 {
+        line = format!(
+            "{}, OOM {} times, {} killed",
+            line, cgroup.oom, cgroup.oom_kill
+        );
+    }
+
+    line
+}
+
+/// Cgroups worth a look: close to their memory limit (90%) or the OOM killer was invoked.
+pub fn cgroup_under_pressure(cgroup: &pw::messages::CgroupUsage) -> bool {
+    let limited = cgroup.memory_max != u64::MAX && cgroup.memory_max > 0;
+    let near_limit = limited && percentage(cgroup.memory_current, cgroup.memory_max) >= 90;
+
+    near_limit || cgroup.oom > 0
+}
+
+pub fn stringify_process(process: &pw::messages::Process) -> String {
+    let command = if process.cmdline.is_empty() {
+        format!("[{}]", process.name)
+    } else {
+        process.cmdline.clone()
+    };
+
+    // Not known in the first list a process is in.
+    let rates = process.rates.map_or(
+        format!("{:>9} {:>9} {:>7} {:>7} {:>7}", "-", "-", "-", "-", "-"),
+        |rates| {
+            format!(
+                "{:>9} {:>9} {:>7.0} {:>7.0} {:>7.0}",
+                stringify_bytes(rates.read_bytes as u64),
+                stringify_bytes(rates.write_bytes as u64),
+                rates.read_syscalls + rates.write_syscalls,
+                rates.voluntary_ctxt_switches + rates.nonvoluntary_ctxt_switches,
+                rates.major_faults
+            )
+        },
+    );
+
+    format!(
+        "{:>7} {:>7} {:>5} {:>5.1} {:>9} {} {}",
+        process.pid,
+        process.ppid,
+        process.uid,
+        process.cpu_percent,
+        stringify_bytes(process.rss_kb * 1024),
+        rates,
+        command
+    )
+}
+
+/// "pwservice cpu 1.2% (budget 5%), rss 8.4 MB, scan 12 ms, next in 10 s, 20 of 180/250 processes".
+pub fn stringify_overhead(processes: &pw::messages::Processes) -> Option<String> {
+    let overhead = processes.overhead.as_ref()?;
+
+    let budget = if overhead.budget_percent > 0.0 {
+        format!(" (budget {}%)", overhead.budget_percent)
+    } else {
+        String::new()
+    };
+
+    Some(format!(
+        "pwservice cpu {:.1}%{}, rss {}, scan {} ms, next in {} s, {} of {}/{} processes",
+        overhead.cpu_percent,
+        budget,
+        stringify_bytes(overhead.rss_kb * 1024),
+        overhead.scan_msec,
+        overhead.interval_msec / 1000,
+        processes.processes.len(),
+        processes.matched,
+        processes.scanned
+    ))
+}
+
+/// Units needing attention: failed, or restarted by systemd.
+pub fn unit_failing(unit: &pw::messages::Unit) -> bool {
+    unit.active_state == "failed" || unit.restarts > 0
+}
+
+pub fn stringify_unit(unit: &pw::messages::Unit) -> String {
+    let mut line = format!(
+        "{:<32} {:<10} {:<12}",
+        unit.name, unit.active_state, unit.sub_state
+    );
+
+    if unit.main_pid != 0 {
+        line = format!("{} pid {}", line, unit.main_pid);
+    }
+
+    if unit.restarts > 0 {
+        line = format!("{} restarted {} times", line, unit.restarts);
+    }
+
+    if !unit.result.is_empty() && unit.result != "success" {
+        line = format!(
+            "{} last failure {} (status {}) {}",
+            line,
+            unit.result,
+            unit.exit_status,
+            stringify_duration(unit.state_change_time)
+        );
+    }
+
+    line.trim_end().to_string()
+}
+
+/// syslog priorities, index is the value.
+pub const KMSG_LEVELS: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// syslog facilities, index is the value.
+const KMSG_FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// Level by name ("err") or number ("3").
+pub fn kmsg_level(name: &str) -> Option<u32> {
+    if let Ok(level) = name.parse::<u32>() {
+        return (level < KMSG_LEVELS.len() as u32).then_some(level);
+    }
+
+    KMSG_LEVELS
+        .iter()
+        .position(|level| *level == name)
+        .map(|level| level as u32)
+}
+
+/// "2024-11-09 14:30:20.094112 kern.err    usb 1-1.3: device descriptor read/64, error -71"
+pub fn stringify_kmsg_record(record: &pw::messages::KmsgRecord) -> String {
+    let name = |names: &[&str], value: u32| {
+        names
+            .get(value as usize)
+            .map_or(value.to_string(), |name| name.to_string())
+    };
+
+    let facility = name(&KMSG_FACILITIES, record.facility);
+    let level = name(&KMSG_LEVELS, record.priority);
+
+    format!(
+        "{}.{:06} {:<12} {}",
+        stringify_duration(record.time_usec / 1_000_000),
+        record.time_usec % 1_000_000,
+        format!("{}.{}", facility, level),
+        record.message
+    )
+}
+
+/// One line alert, "2024-11-09 14:30:20 OOM kill: pid 822 (my app) anon-rss 536.9 MB, ...".
+pub fn stringify_kernel_event(event: &pw::messages::KernelEvent) -> String {
+    use pw::messages::kernel_event::Event;
+
+    let what = match &event.event {
+        Some(Event::OomKill(oom_kill)) => {
+            let mut what = format!(
+                "OOM kill: pid {} ({}) anon-rss {}, file-rss {}, total-vm {}",
+                oom_kill.pid,
+                oom_kill.name,
+                stringify_bytes(oom_kill.anon_rss_kb * 1024),
+                stringify_bytes(oom_kill.file_rss_kb * 1024),
+                stringify_bytes(oom_kill.total_vm_kb * 1024)
+            );
+            if !oom_kill.memcg.is_empty() {
+                what.push_str(&format!(", limit of {}", oom_kill.memcg));
+            }
+            if !oom_kill.trigger.is_empty() && oom_kill.trigger != oom_kill.name {
+                what.push_str(&format!(", invoked by {}", oom_kill.trigger));
+            }
+            what
+        }
+        Some(Event::Segfault(segfault)) => format!(
+            "segfault: pid {} ({}) at {:#x} ip {:#x} in {}",
+            segfault.pid, segfault.name, segfault.address, segfault.ip, segfault.object
+        ),
+        Some(Event::HungTask(hung_task)) => format!(
+            "hung task: pid {} ({}) blocked for more than {} s",
+            hung_task.pid, hung_task.name, hung_task.blocked_seconds
+        ),
+        Some(Event::ProcessWatch(watch)) => {
+            use pw::messages::process_watch::Kind;
+
+            let what = match Kind::try_from(watch.what) {
+                Ok(Kind::Missing) => "missing",
+                Ok(Kind::Recovered) => "recovered",
+                Ok(Kind::Restarted) => "restarted",
+                Err(_) => "changed",
+            };
+            format!(
+                "watchdog: {} {}, {} of {} running",
+                watch.label, what, watch.count, watch.min
+            )
+        }
+        None => event.message.clone(),
+    };
+
+    format!(
+        "{} {}",
+        stringify_duration(event.time_usec / 1_000_000),
+        what
+    )
+}
+
+/// "ok" or "failing", then one line per required process, e.g. "nginx: 2 of 1 running (pids 812 813), restarts 1
+/// since 2024-11-09 14:30:05".
+pub fn stringify_health(health: &pw::messages::Health) -> Vec<String> {
+    use pw::messages::health::Status;
+
+    let state = match Status::try_from(health.state) {
+        Ok(Status::Ok) => "ok",
+        Ok(Status::Failing) => "failing",
+        Err(_) => "unknown",
+    };
+
+    let mut lines = vec![format!(
+        "{} {}",
+        stringify_duration(health.time_usec / 1_000_000),
+        state
+    )];
+
+    for process in &health.processes {
+        let pids: Vec<String> = process.pids.iter().map(|pid| pid.to_string()).collect();
+        let mut line = format!(
+            "{}: {} of {} running",
+            process.label,
+            process.pids.len(),
+            process.min
+        );
+        if !pids.is_empty() {
+            line.push_str(&format!(" (pids {})", pids.join(" ")));
+        }
+        line.push_str(&format!(
+            ", restarts {} since {}",
+            process.restarts,
+            stringify_duration(process.since_usec / 1_000_000)
+        ));
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// "pid 822: stopped, nice 10, cpus 0,1", after a signal, renice or affinity command.
+pub fn stringify_process_control(control: &pw::messages::ProcessControl) -> String {
+    let state = match control.state.as_str() {
+        "" => return format!("pid {}: gone", control.pid),
+        "R" => "running",
+        "S" => "sleeping",
+        "D" => "disk sleep",
+        "T" => "stopped",
+        "t" => "tracing stop",
+        "Z" => "zombie",
+        "I" => "idle",
+        state => state,
+    };
+
+    let cpus: Vec<String> = control.cpus.iter().map(|cpu| cpu.to_string()).collect();
+
+    format!(
+        "pid {}: {}, nice {}, cpus {}",
+        control.pid,
+        state,
+        control.nice,
+        cpus.join(",")
+    )
+}
+
+pub fn stringify_open_files(open_files: &pw::messages::OpenFiles) -> Vec<String> {
+    let mut lines = vec![format!(
+        "pid {}: {} open files, limit {} (hard {})",
+        open_files.pid,
+        open_files.fds.len(),
+        stringify_limit(open_files.soft_limit),
+        stringify_limit(open_files.hard_limit)
+    )];
+
+    for fd in &open_files.fds {
+        match &fd.socket {
+            Some(socket) => lines.push(format!("{:>5} {}", fd.fd, stringify_socket(socket))),
+            None => lines.push(format!("{:>5} {}", fd.fd, fd.target)),
+        }
+    }
+
+    lines
+}
+
+/// Capability names without the "CAP_" prefix, index is the capability number.
+const CAPABILITIES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// "none", "all", the names of the capabilities in the set, e.g. "net_bind_service,net_raw", or the
+/// ones missing if it is almost all of them (root in a container), e.g. "all except sys_resource".
+pub fn stringify_capabilities(set: u64) -> String {
+    let names = |set: u64| {
+        (0..64)
+            .filter(|bit| set & (1 << bit) != 0)
+            .map(|bit| {
+                CAPABILITIES
+                    .get(bit)
+                    .map_or(format!("cap_{}", bit), |name| name.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    let all = (1u64 << CAPABILITIES.len()) - 1;
+    let missing = all & !set;
+
+    match missing.count_ones() {
+        _ if set == 0 => "none".to_string(),
+        0 => "all".to_string(),
+        1..=8 => format!("all except {}", names(missing)),
+        _ => names(set),
+    }
+}
+
+pub fn stringify_process_detail(detail: &pw::messages::ProcessDetail) -> Vec<String> {
+    let mut lines = vec![];
+
+    if let Some(process) = &detail.process {
+        lines.push(format!(
+            "pid {} ({}), ppid {}, uid {}, gid {}",
+            process.pid, process.name, process.ppid, process.uid, process.gid
+        ));
+        lines.push(format!("cmdline: {}", process.cmdline));
+        lines.push(format!("cwd: {}", process.cwd));
+    }
+
+    lines.push(format!("exe: {}", detail.exe));
+    lines.push(format!("root: {}", detail.root));
+    lines.push(format!(
+        "started: {}",
+        stringify_duration(detail.start_time)
+    ));
+
+    if let Some(status) = &detail.status {
+        lines.push(format!(
+            "state: {}, {} threads, context switches {} voluntary, {} involuntary",
+            status.state,
+            status.threads,
+            status.voluntary_ctxt_switches,
+            status.nonvoluntary_ctxt_switches
+        ));
+        lines.push(format!(
+            "memory: rss {}, size {}, swap {}",
+            stringify_bytes(status.vm_rss_kb * 1024),
+            stringify_bytes(status.vm_size_kb * 1024),
+            stringify_bytes(status.vm_swap_kb * 1024)
+        ));
+        lines.push(format!(
+            "effective uid {}, gid {}, umask {:04o}",
+            status.euid, status.egid, status.umask
+        ));
+        lines.push(format!(
+            "capabilities: effective {}, permitted {}, inheritable {}, bounding {}, ambient {}",
+            stringify_capabilities(status.cap_eff),
+            stringify_capabilities(status.cap_prm),
+            stringify_capabilities(status.cap_inh),
+            stringify_capabilities(status.cap_bnd),
+            stringify_capabilities(status.cap_amb)
+        ));
+
+        let seccomp = match status.seccomp {
+            0 => "disabled",
+            1 => "strict",
+            2 => "filter",
+            _ => "unknown",
+        };
+        lines.push(format!(
+            "seccomp: {}, no_new_privs: {}",
+            seccomp, status.no_new_privs
+        ));
+    }
+
+    lines.push("limits:".to_string());
+    for limit in &detail.limits {
+        lines.push(format!(
+            "  {:<26} {:>20} {:>20} {}",
+            limit.name,
+            stringify_limit(limit.soft),
+            stringify_limit(limit.hard),
+            limit.units
+        ));
+    }
+
+    lines.push("environment:".to_string());
+    for variable in &detail.environ {
+        if variable.redacted {
+            lines.push(format!("  {}=<redacted>", variable.name));
+        } else {
+            lines.push(format!("  {}={}", variable.name, variable.value));
+        }
+    }
+
+    lines
 }
 
 #[cfg(test)]
@@ -97,6 +1122,7 @@ mod tests {
             cpu_model_name: "ARMv7 Processor rev 4 (v7l)".to_string(),
             physical_mem_total_kb: 943032,
             network_interface: Some( NetworkInterface { mac: 202481586980485, name: "eth0".to_string(), proto: Dhcp.into(), ipv4: 3232246377, subnet_mask: 24, broadcast: 3232246527, gateway: 3232246273 }),
+            ..Default::default()
         };
 
         let expected = vec![
@@ -105,8 +1131,693 @@ mod tests {
             ( "kernel", "Linux version 6.6.51+rpt-rpi-v7 (serge@raspberrypi.com) (gcc-12 (Raspbian 12.2.0-14+rpi1) 12.2.0, GNU ld (GNU Binutils for Raspbian) 2.40) #1 SMP Raspbian 1:6.6.51-1+rpt3 (2024-10-08)".to_string() ),
             ( "CPU", "ARMv7 Processor rev 4 (v7l)".to_string() ),
             ( "RAM", "943 MB".to_string() ),
+            ( "protocol", "protocol 1 older, values it does not send are empty (this build speaks 3)".to_string() ),
         ];
 
         assert_eq!(stringify_message(&input), expected);
     }
+
+    #[test]
+    fn stringify_inventory() {
+        let input = pw::messages::Machine {
+            boottime: 1731155405,
+            hostname: "raspberrypi".to_string(),
+            cpu_model_name: "ARM Cortex-A72".to_string(),
+            physical_mem_total_kb: 3884000,
+            cpu_cores: 4,
+            cpu_threads: 4,
+            architecture: "aarch64".to_string(),
+            cpu_features: "fp asimd evtstrm crc32 cpuid".to_string(),
+            revision: "c03114".to_string(),
+            serial: "10000000a3b4c5d6".to_string(),
+            device_tree_model: "Raspberry Pi 4 Model B Rev 1.4".to_string(),
+            storage_total_bytes: 31914983424,
+            protocol: Some(protocol()),
+            ..Default::default()
+        };
+
+        let expected = vec![
+            ("booted", "2024-11-09 12:30:05".to_string()),
+            ("hostname", "raspberrypi".to_string()),
+            ("kernel", "".to_string()),
+            ("CPU", "ARM Cortex-A72".to_string()),
+            ("RAM", "3884 MB".to_string()),
+            ("cores", "4 cores, 4 threads".to_string()),
+            ("arch", "aarch64".to_string()),
+            ("model", "Raspberry Pi 4 Model B Rev 1.4".to_string()),
+            ("revision", "c03114".to_string()),
+            ("serial", "10000000a3b4c5d6".to_string()),
+            ("storage", "31.9 GB".to_string()),
+        ];
+
+        assert_eq!(stringify_message(&input), expected);
+    }
+
+    #[test]
+    fn stringify_os() {
+        let mut input = pw::messages::Machine {
+            version: "Linux version 6.1.21-v7+ (dom@buildbot) (gcc) #1642 SMP".to_string(),
+            architecture: "armv7l".to_string(),
+            os_release: Some(pw::messages::OsRelease {
+                id: "debian".to_string(),
+                version_id: "12".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(os_version(&input), Some("debian 12".to_string()));
+        assert_eq!(
+            kernel_release(&input),
+            "Linux version 6.1.21-v7+ (dom@buildbot) (gcc) #1642 SMP"
+        );
+
+        input.kernel = Some(pw::messages::Kernel {
+            release: "6.1.21-v7+".to_string(),
+            ..Default::default()
+        });
+        input.os_release = Some(pw::messages::OsRelease {
+            id: "android".to_string(),
+            pretty_name: "Android 10".to_string(),
+            fingerprint: "OnePlus/OnePlus6/OnePlus6:10".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(os_version(&input), Some("Android 10".to_string()));
+        assert_eq!(kernel_release(&input), "6.1.21-v7+ armv7l");
+
+        input.kernel = Some(pw::messages::Kernel {
+            release: "6.1.0-13-arm64".to_string(),
+            arch: "arm64".to_string(),
+            ..Default::default()
+        });
+        input.architecture = "aarch64".to_string();
+        assert_eq!(kernel_release(&input), "6.1.0-13-arm64");
+
+        let info = stringify_message(&input);
+        assert!(info.contains(&("os", "Android 10".to_string())));
+        assert!(info.contains(&("fingerprint", "OnePlus/OnePlus6/OnePlus6:10".to_string())));
+
+        input.os_release = Some(pw::messages::OsRelease::default());
+        assert_eq!(os_version(&input), None);
+    }
+
+    #[test]
+    fn stringify_process_ok() {
+        let mut process = pw::messages::Process {
+            pid: 822,
+            ppid: 1,
+            name: "my app".to_string(),
+            cmdline: "/usr/bin/my-app --verbose".to_string(),
+            uid: 1000,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stringify_process(&process),
+            "    822       1  1000   0.0       0 B         -         -       -       -       - /usr/bin/my-app --verbose"
+        );
+
+        process.cmdline.clear();
+        process.container = Some(pw::messages::Container {
+            runtime: "docker".to_string(),
+            id: "3f2a4b1c9d8e7f60a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2".to_string(),
+        });
+
+        assert_eq!(
+            stringify_process(&process),
+            "    822       1  1000   0.0       0 B         -         -       -       -       - [my app]"
+        );
+        assert_eq!(
+            stringify_container(process.container.as_ref().unwrap()),
+            "docker 3f2a4b1c9d8e"
+        );
+
+        process.cpu_percent = 12.34;
+        process.rss_kb = 24_576;
+        process.rates = Some(pw::messages::ProcessRates {
+            read_bytes: 40_960.0,
+            write_bytes: 2_500_000.0,
+            read_syscalls: 10.0,
+            write_syscalls: 610.0,
+            voluntary_ctxt_switches: 100.4,
+            nonvoluntary_ctxt_switches: 2.0,
+            minor_faults: 1000.0,
+            major_faults: 3.0,
+        });
+        assert_eq!(
+            stringify_process(&process),
+            "    822       1  1000  12.3   25.2 MB   41.0 kB    2.5 MB     620     102       3 [my app]"
+        );
+    }
+
+    #[test]
+    fn stringify_overhead_ok() {
+        let mut processes = pw::messages::Processes {
+            processes: vec![pw::messages::Process::default(); 20],
+            scanned: 250,
+            matched: 180,
+            overhead: Some(pw::messages::ServiceOverhead {
+                cpu_percent: 1.23,
+                rss_kb: 8192,
+                scan_msec: 12,
+                interval_msec: 20_000,
+                budget_percent: 5.0,
+            }),
+        };
+
+        assert_eq!(
+            stringify_overhead(&processes).unwrap(),
+            "pwservice cpu 1.2% (budget 5%), rss 8.4 MB, scan 12 ms, next in 20 s, 20 of 180/250 processes"
+        );
+
+        processes.overhead = None;
+        assert_eq!(stringify_overhead(&processes), None);
+    }
+
+    #[test]
+    fn stringify_cgroup_ok() {
+        let mut cgroup = pw::messages::CgroupUsage {
+            path: "/system.slice/ssh.service".to_string(),
+            unit: "ssh.service".to_string(),
+            cpu_percent: 1.25,
+            memory_current: 4317184,
+            memory_max: u64::MAX,
+            io_read_bytes_per_second: 2048,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stringify_cgroup(&cgroup),
+            "ssh.service: cpu 1.2%, memory 4.3 MB, io 2.0 kB/s read, 0 B/s written"
+        );
+        assert!(!cgroup_under_pressure(&cgroup));
+
+        cgroup.container = Some(pw::messages::Container {
+            runtime: "docker".to_string(),
+            id: "3f2a4b1c9d8e7f60a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2".to_string(),
+        });
+        cgroup.memory_current = 500_000_000;
+        cgroup.memory_max = 512_000_000;
+        cgroup.oom = 3;
+        cgroup.oom_kill = 2;
+
+        assert_eq!(
+            stringify_cgroup(&cgroup),
+            "docker 3f2a4b1c9d8e: cpu 1.2%, memory 500.0 MB of 512.0 MB (97%), io 2.0 kB/s read, 0 B/s written, OOM 3 times, 2 killed"
+        );
+        assert!(cgroup_under_pressure(&cgroup));
+
+        cgroup.container = None;
+        cgroup.unit.clear();
+        assert_eq!(cgroup_name(&cgroup), "/system.slice/ssh.service");
+    }
+
+    #[test]
+    fn stringify_unit_ok() {
+        let mut unit = pw::messages::Unit {
+            name: "ssh.service".to_string(),
+            active_state: "active".to_string(),
+            sub_state: "running".to_string(),
+            main_pid: 612,
+            result: "success".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stringify_unit(&unit),
+            "ssh.service                      active     running      pid 612"
+        );
+        assert!(!unit_failing(&unit));
+
+        unit.active_state = "failed".to_string();
+        unit.sub_state = "failed".to_string();
+        unit.main_pid = 0;
+        unit.restarts = 5;
+        unit.result = "exit-code".to_string();
+        unit.exit_status = 1;
+        unit.state_change_time = 1731155405;
+
+        assert_eq!(
+            stringify_unit(&unit),
+            "ssh.service                      failed     failed       restarted 5 times last failure exit-code (status 1) 2024-11-09 12:30:05"
+        );
+        assert!(unit_failing(&unit));
+    }
+
+    #[test]
+    fn stringify_kmsg() {
+        let record = pw::messages::KmsgRecord {
+            sequence: 1042,
+            priority: 3,
+            facility: 0,
+            monotonic_usec: 7215094112,
+            time_usec: 1_731_155_405_000_000 + 7215094112,
+            message: "usb 1-1.3: device descriptor read/64, error -71".to_string(),
+            properties: vec![],
+        };
+
+        assert_eq!(
+            stringify_kmsg_record(&record),
+            "2024-11-09 14:30:20.094112 kern.err     usb 1-1.3: device descriptor read/64, error -71"
+        );
+
+        assert_eq!(kmsg_level("warning"), Some(4));
+        assert_eq!(kmsg_level("7"), Some(7));
+        assert_eq!(kmsg_level("8"), None);
+        assert_eq!(kmsg_level("loud"), None);
+    }
+
+    #[test]
+    fn stringify_kernel_event_ok() {
+        use pw::messages::kernel_event::Event;
+
+        let mut event = pw::messages::KernelEvent {
+            time_usec: 1_731_162_605_000_000,
+            message: "Memory cgroup out of memory: Killed process 822 (my app)".to_string(),
+            event: Some(Event::OomKill(pw::messages::OomKill {
+                pid: 822,
+                name: "my app".to_string(),
+                anon_rss_kb: 524_288,
+                file_rss_kb: 1024,
+                total_vm_kb: 1_048_576,
+                trigger: "my app".to_string(),
+                memcg: "/system.slice/docker-3f2a4b1c9d8e.scope".to_string(),
+                ..Default::default()
+            })),
+        };
+
+        assert_eq!(
+            stringify_kernel_event(&event),
+            "2024-11-09 14:30:05 OOM kill: pid 822 (my app) anon-rss 536.9 MB, file-rss 1.0 MB, total-vm 1.1 GB, limit of /system.slice/docker-3f2a4b1c9d8e.scope"
+        );
+
+        event.event = Some(Event::Segfault(pw::messages::Segfault {
+            pid: 901,
+            name: "my-app".to_string(),
+            address: 0x10,
+            ip: 0x55d0_c1a2_b3c4,
+            object: "libc.so.6".to_string(),
+            ..Default::default()
+        }));
+
+        assert_eq!(
+            stringify_kernel_event(&event),
+            "2024-11-09 14:30:05 segfault: pid 901 (my-app) at 0x10 ip 0x55d0c1a2b3c4 in libc.so.6"
+        );
+
+        event.event = Some(Event::ProcessWatch(pw::messages::ProcessWatch {
+            label: "nginx".to_string(),
+            what: pw::messages::process_watch::Kind::Missing as i32,
+            count: 0,
+            min: 1,
+        }));
+
+        assert_eq!(
+            stringify_kernel_event(&event),
+            "2024-11-09 14:30:05 watchdog: nginx missing, 0 of 1 running"
+        );
+    }
+
+    #[test]
+    fn protocol_compatibility() {
+        let peer = |version, compatible| pw::messages::Protocol {
+            version,
+            compatible,
+        };
+
+        assert_eq!(compatibility(&protocol()), Compatibility::Same);
+        assert_eq!(peer_protocol(None), peer(1, 1));
+        assert_eq!(peer_protocol(Some(b"")), peer(1, 1));
+        assert_eq!(peer_protocol(Some(&serialize(&peer(7, 5)))), peer(7, 5));
+
+        assert_eq!(
+            compatibility(&peer(PROTOCOL_VERSION - 1, 1)),
+            Compatibility::Older
+        );
+        assert_eq!(
+            compatibility(&peer(PROTOCOL_VERSION + 1, PROTOCOL_COMPATIBLE)),
+            Compatibility::Newer
+        );
+        // A newer peer after an incompatible change.
+        assert_eq!(
+            compatibility(&peer(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 1)),
+            Compatibility::Incompatible
+        );
+
+        assert_eq!(stringify_compatibility(&protocol()), None);
+        assert_eq!(
+            stringify_compatibility(&peer(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 1)),
+            Some(format!(
+                "protocol {} incompatible, nothing it sends is shown (this build speaks {})",
+                PROTOCOL_VERSION + 1,
+                PROTOCOL_VERSION
+            ))
+        );
+    }
+
+    #[test]
+    fn stringify_version_info_ok() {
+        let mut info = pw::messages::VersionInfo {
+            name: "pwservice".to_string(),
+            version: "0.1.0".to_string(),
+            git_sha: "1a2b3c4d5e6f".to_string(),
+            git_dirty: true,
+            build_timestamp: "2024-11-09T14:30:05.094112Z".to_string(),
+            target_triple: "armv7-unknown-linux-gnueabihf".to_string(),
+            features: vec!["systemd".to_string()],
+            debug: false,
+            protocol: Some(protocol()),
+        };
+
+        assert_eq!(
+            stringify_version_info(&info),
+            format!("pwservice v0.1.0 release (Git SHA: 1a2b3c4d5e6f, dirty: true, build time: 2024-11-09T14:30:05.094112Z, target: armv7-unknown-linux-gnueabihf, features: systemd, protocol: {})", PROTOCOL_VERSION)
+        );
+
+        info.features.clear();
+        info.debug = true;
+        info.protocol = None;
+        assert!(stringify_version_info(&info).starts_with("pwservice v0.1.0 debug ("));
+        assert!(stringify_version_info(&info).ends_with("features: none, protocol: 1)"));
+    }
+
+    #[test]
+    fn stringify_health_ok() {
+        let health = pw::messages::Health {
+            state: pw::messages::health::Status::Failing as i32,
+            processes: vec![
+                pw::messages::RequiredProcess {
+                    label: "nginx".to_string(),
+                    min: 1,
+                    pids: vec![812, 813],
+                    restarts: 1,
+                    since_usec: 1_731_162_605_000_000,
+                },
+                pw::messages::RequiredProcess {
+                    label: "/usr/bin/python3 .*app.py".to_string(),
+                    min: 2,
+                    pids: vec![],
+                    restarts: 0,
+                    since_usec: 1_731_162_600_000_000,
+                },
+            ],
+            time_usec: 1_731_162_620_000_000,
+        };
+
+        assert_eq!(
+            stringify_health(&health),
+            vec![
+                "2024-11-09 14:30:20 failing",
+                "nginx: 2 of 1 running (pids 812 813), restarts 1 since 2024-11-09 14:30:05",
+                "/usr/bin/python3 .*app.py: 0 of 2 running, restarts 0 since 2024-11-09 14:30:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn stringify_process_control_ok() {
+        let mut control = pw::messages::ProcessControl {
+            pid: 822,
+            state: "T".to_string(),
+            nice: 10,
+            cpus: vec![0, 1],
+        };
+
+        assert_eq!(
+            stringify_process_control(&control),
+            "pid 822: stopped, nice 10, cpus 0,1"
+        );
+
+        control.state.clear();
+        assert_eq!(stringify_process_control(&control), "pid 822: gone");
+    }
+
+    #[test]
+    fn stringify_capabilities_ok() {
+        assert_eq!(stringify_capabilities(0), "none");
+        assert_eq!(stringify_capabilities(0x1ff_ffff_ffff), "all");
+        assert_eq!(stringify_capabilities(0x3000), "net_admin,net_raw");
+        assert_eq!(stringify_capabilities(1 << 50 | 1), "chown,cap_50");
+        assert_eq!(
+            stringify_capabilities(0x1ff_feff_ffff),
+            "all except sys_resource"
+        );
+    }
+
+    #[test]
+    fn stringify_process_detail_ok() {
+        let detail = pw::messages::ProcessDetail {
+            status: Some(pw::messages::ProcessStatus {
+                state: "S".to_string(),
+                threads: 3,
+                seccomp: 2,
+                umask: 0o022,
+                cap_bnd: 0x1ff_ffff_ffff,
+                ..Default::default()
+            }),
+            environ: vec![
+                pw::messages::EnvironmentVariable {
+                    name: "LANG".to_string(),
+                    value: "C.UTF-8".to_string(),
+                    redacted: false,
+                },
+                pw::messages::EnvironmentVariable {
+                    name: "DB_PASSWORD".to_string(),
+                    value: String::new(),
+                    redacted: true,
+                },
+            ],
+            start_time: 1_731_162_605,
+            ..Default::default()
+        };
+
+        let lines = stringify_process_detail(&detail);
+
+        assert!(lines.contains(&"started: 2024-11-09 14:30:05".to_string()));
+        assert!(lines.contains(&"effective uid 0, gid 0, umask 0022".to_string()));
+        assert!(lines.contains(&"seccomp: filter, no_new_privs: false".to_string()));
+        assert!(lines.contains(&"capabilities: effective none, permitted none, inheritable none, bounding all, ambient none".to_string()));
+        assert_eq!(lines.last().unwrap(), "  DB_PASSWORD=<redacted>");
+    }
+
+    #[test]
+    fn stringify_bytes_ok() {
+        assert_eq!(stringify_bytes(0), "0 B");
+        assert_eq!(stringify_bytes(999), "999 B");
+        assert_eq!(stringify_bytes(1500), "1.5 kB");
+        assert_eq!(stringify_bytes(14_500_000_000), "14.5 GB");
+    }
+
+    #[test]
+    fn stringify_metrics_ok() {
+        let input = pw::messages::Metrics {
+            filesystems: vec![pw::messages::Filesystem {
+                mount_point: "/".to_string(),
+                device: "/dev/root".to_string(),
+                fs_type: "ext4".to_string(),
+                read_only: false,
+                total_bytes: 14_500_000_000,
+                free_bytes: 11_400_000_000,
+                available_bytes: 10_700_000_000,
+                total_inodes: 1000,
+                free_inodes: 880,
+            }],
+            block_devices: vec![pw::messages::BlockDevice {
+                name: "mmcblk0".to_string(),
+                read_bytes: 409_979_904,
+                written_bytes: 846_548_992,
+                read_bytes_per_second: 0,
+                written_bytes_per_second: 51_200,
+                reads_per_second: 0.0,
+                writes_per_second: 4.5,
+                utilization: 2.4,
+            }],
+            uptime: 350735,
+            load_average: Some(pw::messages::LoadAverage {
+                one: 0.52,
+                five: 0.58,
+                fifteen: 0.59,
+                running: 2,
+                total: 437,
+            }),
+            cpu_pressure: Some(pw::messages::Pressure {
+                some_avg10: 12.5,
+                ..Default::default()
+            }),
+            memory_pressure: None,
+            io_pressure: Some(pw::messages::Pressure {
+                some_avg10: 3.2,
+                ..Default::default()
+            }),
+            memory: Some(pw::messages::Memory {
+                total_kb: 990_180,
+                available_kb: 94_004,
+                swap_total_kb: 102_396,
+                swap_free_kb: 102_396,
+            }),
+            ..Default::default()
+        };
+
+        let expected = vec![
+            "up 4d 01:25, load 0.52 0.58 0.59, 2/437 tasks running".to_string(),
+            "memory 96.3 MB of 1.0 GB available (9%), swap 104.9 MB of 104.9 MB free".to_string(),
+            "pressure cpu 12.5%, io 3.2%".to_string(),
+            "/ (/dev/root ext4): 3.1 GB of 14.5 GB used (21%), 12% inodes used".to_string(),
+            "mmcblk0: read 0 B/s, write 51.2 kB/s, 2% busy, 846.5 MB written since boot"
+                .to_string(),
+        ];
+
+        assert_eq!(stringify_metrics(&input), expected);
+        assert_eq!(
+            memory_available_percent(&input).map(|percent| percent.round()),
+            Some(9.0)
+        );
+        assert_eq!(
+            memory_available_percent(&pw::messages::Metrics::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn stringify_thermal_ok() {
+        let mut input = pw::messages::Metrics {
+            thermal_zones: vec![pw::messages::ThermalZone {
+                name: "cpu-thermal".to_string(),
+                temperature: 72530,
+                passive_trip: 85000,
+                critical_trip: 90000,
+            }],
+            cpu_frequencies: vec![
+                pw::messages::CpuFrequency {
+                    cpu: 0,
+                    current_khz: 1200000,
+                    min_khz: 600000,
+                    max_khz: 1500000,
+                    hardware_max_khz: 1500000,
+                },
+                pw::messages::CpuFrequency {
+                    cpu: 1,
+                    current_khz: 600000,
+                    min_khz: 600000,
+                    max_khz: 1500000,
+                    hardware_max_khz: 1500000,
+                },
+            ],
+            sensors: vec![pw::messages::Sensor {
+                chip: "ina219".to_string(),
+                label: "VBUS".to_string(),
+                kind: pw::messages::sensor::Sensorkind::Voltage.into(),
+                value: 5.08,
+            }],
+            ..Default::default()
+        };
+
+        let expected = vec![
+            "cpu-thermal: 72.5°C, passive 85.0°C, critical 90.0°C".to_string(),
+            "cpu frequency 1200/600 MHz".to_string(),
+            "ina219 VBUS: 5.08 V".to_string(),
+        ];
+
+        assert_eq!(stringify_thermal(&input), expected);
+        assert_eq!(max_temperature(&input), Some(72.53));
+        assert!(throttling_reasons(&input).is_empty());
+
+        input.thermal_zones[0].temperature = 86000;
+        input.cpu_frequencies[1].max_khz = 1000000;
+        input.throttled = 0x50005;
+
+        let expected = vec![
+            "under-voltage".to_string(),
+            "throttled".to_string(),
+            "cpu-thermal above passive trip point 85.0°C".to_string(),
+            "cpu1 limited to 1000 MHz of 1500 MHz".to_string(),
+        ];
+
+        assert_eq!(throttling_reasons(&input), expected);
+    }
+
+    #[test]
+    fn stringify_uptime_ok() {
+        assert_eq!(stringify_uptime(59), "00:00");
+        assert_eq!(stringify_uptime(3660), "01:01");
+        assert_eq!(stringify_uptime(350735), "4d 01:25");
+    }
+
+    #[test]
+    fn stringify_connection_ok() {
+        let mut input = pw::messages::Connection {
+            socket: Some(pw::messages::Socket {
+                proto: pw::messages::socket::Sockproto::Tcp6.into(),
+                inode: 11844,
+                local_address: "[::]:22".to_string(),
+                remote_address: "[::]:0".to_string(),
+                state: pw::messages::socket::Sockstate::Listen.into(),
+                uid: 0,
+            }),
+            pid: 0,
+            process_name: "".to_string(),
+        };
+
+        assert_eq!(
+            stringify_connection(&input),
+            "tcp6  [::]:22                  [::]:0                   listen      -"
+        );
+
+        input.pid = 512;
+        input.process_name = "sshd".to_string();
+
+        assert_eq!(
+            stringify_connection(&input),
+            "tcp6  [::]:22                  [::]:0                   listen      512/sshd"
+        );
+    }
+
+    #[test]
+    fn stringify_open_files_ok() {
+        let input = pw::messages::OpenFiles {
+            pid: 822,
+            soft_limit: 1024,
+            hard_limit: u64::MAX,
+            fds: vec![
+                pw::messages::FileDescriptor {
+                    fd: 0,
+                    target: "/dev/null".to_string(),
+                    socket: None,
+                },
+                pw::messages::FileDescriptor {
+                    fd: 3,
+                    target: "socket:[27410]".to_string(),
+                    socket: Some(pw::messages::Socket {
+                        proto: pw::messages::socket::Sockproto::Tcp.into(),
+                        inode: 27410,
+                        local_address: "192.168.42.105:22".to_string(),
+                        remote_address: "192.168.42.114:54242".to_string(),
+                        state: pw::messages::socket::Sockstate::Established.into(),
+                        uid: 0,
+                    }),
+                },
+                pw::messages::FileDescriptor {
+                    fd: 4,
+                    target: "socket:[12874]".to_string(),
+                    socket: Some(pw::messages::Socket {
+                        proto: pw::messages::socket::Sockproto::Unix.into(),
+                        inode: 12874,
+                        local_address: "/run/systemd/private".to_string(),
+                        state: pw::messages::socket::Sockstate::Listen.into(),
+                        ..Default::default()
+                    }),
+                },
+            ],
+        };
+
+        let expected = vec![
+            "pid 822: 3 open files, limit 1024 (hard unlimited)".to_string(),
+            "    0 /dev/null".to_string(),
+            "    3 tcp 192.168.42.105:22 -> 192.168.42.114:54242 (established)".to_string(),
+            "    4 unix /run/systemd/private (listen)".to_string(),
+        ];
+
+        assert_eq!(stringify_open_files(&input), expected);
+    }
 }
@@ -0,0 +1,167 @@
+use crate::protocol;
+use common::{
+    pw, serialize, stringify_kmsg_record, stringify_open_files, stringify_process_control,
+    stringify_process_detail, AFFINITY_COMMAND, BASE_KEY_EXPR, COMMAND_KEY_EXPR, DETAIL_COMMAND,
+    GROUP_KEY_EXPR, KMSG_COMMAND, KMSG_KEY_EXPR, OPEN_FILES_COMMAND, RENICE_COMMAND,
+    SIGNAL_COMMAND, STOP_STREAM_COMMAND,
+};
+use log::{debug, error, warn};
+use prost::Message;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Send command with request to machine id, returns the successfully decoded replies.
+async fn query<R: Message + Default>(
+    session: &zenoh::Session,
+    id: u64,
+    command: &str,
+    request: &impl Message,
+) -> Vec<R> {
+    let key_expr = format!(
+        "{}/{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, COMMAND_KEY_EXPR, id, command
+    );
+
+    debug!("Sending command on '{key_expr}'...");
+
+    let mut replies = vec![];
+    let receiver = session
+        .get(&key_expr)
+        .payload(serialize(request))
+        .await
+        .unwrap();
+
+    while let Ok(reply) = receiver.recv_async().await {
+        match reply.result() {
+            Ok(sample) => match protocol::decode::<R>(sample) {
+                Ok(message) => replies.push(message),
+                Err(err) => error!("Could not parse reply (ERROR: '{}')", err),
+            },
+            Err(err) => {
+                let payload = err
+                    .payload()
+                    .try_to_string()
+                    .unwrap_or_else(|e| e.to_string().into());
+                error!("Command '{}' failed (ERROR: '{}')", command, payload);
+            }
+        }
+    }
+
+    replies
+}
+
+pub async fn open_files(session: &zenoh::Session, id: u64, pid: i32) {
+    let request = pw::messages::ProcessRequest { pid };
+
+    let replies: Vec<pw::messages::OpenFiles> =
+        query(session, id, OPEN_FILES_COMMAND, &request).await;
+
+    for open_files in replies {
+        for line in stringify_open_files(&open_files) {
+            println!("{}", line);
+        }
+    }
+}
+
+pub async fn detail(session: &zenoh::Session, id: u64, pid: i32) {
+    let request = pw::messages::ProcessRequest { pid };
+
+    let replies: Vec<pw::messages::ProcessDetail> =
+        query(session, id, DETAIL_COMMAND, &request).await;
+
+    for detail in replies {
+        for line in stringify_process_detail(&detail) {
+            println!("{}", line);
+        }
+    }
+}
+
+async fn control(session: &zenoh::Session, id: u64, command: &str, request: &impl Message) {
+    let replies: Vec<pw::messages::ProcessControl> = query(session, id, command, request).await;
+
+    for control in replies {
+        println!("{}", stringify_process_control(&control));
+    }
+}
+
+pub async fn signal(
+    session: &zenoh::Session,
+    id: u64,
+    pid: i32,
+    signal: pw::messages::signal_request::Signo,
+) {
+    let request = pw::messages::SignalRequest {
+        pid,
+        signal: signal.into(),
+    };
+
+    control(session, id, SIGNAL_COMMAND, &request).await;
+}
+
+pub async fn renice(session: &zenoh::Session, id: u64, pid: i32, nice: i32) {
+    let request = pw::messages::ReniceRequest { pid, nice };
+
+    control(session, id, RENICE_COMMAND, &request).await;
+}
+
+pub async fn affinity(session: &zenoh::Session, id: u64, pid: i32, cpus: Vec<u32>) {
+    let request = pw::messages::AffinityRequest { pid, cpus };
+
+    control(session, id, AFFINITY_COMMAND, &request).await;
+}
+
+/// Stream the kernel log of machine id until CTRL-C.
+pub async fn kmsg(
+    session: &zenoh::Session,
+    id: u64,
+    level: Option<u32>,
+    pattern: Option<String>,
+    from_start: bool,
+) {
+    // Only needs to be unique among the clients streaming from this machine.
+    let stream = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos() as u64 ^ std::process::id() as u64)
+        .unwrap_or_default();
+
+    let key_expr = format!(
+        "{}/{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, KMSG_KEY_EXPR, id, stream
+    );
+
+    debug!("Declaring Kernel log Subscriber on '{key_expr}'...");
+
+    // Subscribe before starting, the records already in the ring buffer are sent right away.
+    let subscriber = session.declare_subscriber(&key_expr).await.unwrap();
+
+    let request = pw::messages::KmsgRequest {
+        stream,
+        level,
+        pattern: pattern.unwrap_or_default(),
+        from_start,
+    };
+
+    let started: Vec<pw::messages::StreamRequest> =
+        query(session, id, KMSG_COMMAND, &request).await;
+    if started.is_empty() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            sample = subscriber.recv_async() => {
+                let Ok(sample) = sample else {
+                    break;
+                };
+
+                match protocol::decode::<pw::messages::KmsgRecord>(&sample) {
+                    Ok(record) => println!("{}", stringify_kmsg_record(&record)),
+                    Err(err) => warn!("Could not parse message (ERROR: '{}')", err),
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let stop = pw::messages::StreamRequest { stream };
+    let _: Vec<pw::messages::StreamRequest> = query(session, id, STOP_STREAM_COMMAND, &stop).await;
+}
@@ -0,0 +1,84 @@
+use crate::protocol;
+use common::{pw, stringify_connection, BASE_KEY_EXPR, CONNECTIONS_KEY_EXPR, GROUP_KEY_EXPR};
+use log::{debug, warn};
+use pw::messages::socket::Sockstate;
+
+/// Which connections to show, empty filter shows everything.
+#[derive(Default, Debug)]
+pub struct Filter {
+    pub listening: bool,
+    pub established: bool,
+    pub port: Option<u16>,
+    pub process: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, connection: &pw::messages::Connection) -> bool {
+        let socket = connection.socket.clone().unwrap_or_default();
+
+        if self.listening && !self.established && socket.state() != Sockstate::Listen {
+            return false;
+        }
+
+        if self.established && !self.listening && socket.state() != Sockstate::Established {
+            return false;
+        }
+
+        if let Some(port) = self.port {
+            if port_of(&socket.local_address) != Some(port)
+                && port_of(&socket.remote_address) != Some(port)
+            {
+                return false;
+            }
+        }
+
+        if let Some(process) = &self.process {
+            if !connection.process_name.contains(process.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn port_of(address: &str) -> Option<u16> {
+    address.rsplit_once(':')?.1.parse().ok()
+}
+
+/// Print the latest connection table of machine id (all machines if None) matching filter.
+pub async fn show(session: &zenoh::Session, id: Option<u64>, filter: &Filter) {
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, CONNECTIONS_KEY_EXPR, id
+    );
+
+    debug!("Declaring Connections getter on '{key_expr}'...");
+
+    let getter = session.get(&key_expr).await.unwrap();
+    while let Ok(reply) = getter.recv_async().await {
+        match reply.result() {
+            Ok(sample) => match protocol::decode::<pw::messages::Connections>(sample) {
+                Ok(connections) => {
+                    println!("{}", sample.key_expr().as_str());
+                    for connection in &connections.connections {
+                        if filter.matches(connection) {
+                            println!("  {}", stringify_connection(connection));
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!("Could not parse message (ERROR: '{}')", err);
+                }
+            },
+            Err(err) => {
+                let payload = err
+                    .payload()
+                    .try_to_string()
+                    .unwrap_or_else(|e| e.to_string().into());
+                warn!(">> Received (ERROR: '{}')", payload);
+            }
+        }
+    }
+}
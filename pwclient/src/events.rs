@@ -0,0 +1,73 @@
+use crate::protocol;
+use common::{
+    pw, stringify_health, stringify_kernel_event, BASE_KEY_EXPR, EVENTS_KEY_EXPR, GROUP_KEY_EXPR,
+    HEALTH_KEY_EXPR,
+};
+use log::{debug, info, warn};
+
+/// Print OOM kills, segfaults and hung tasks of machine id (all machines if None) as they happen.
+pub async fn follow(session: &zenoh::Session, id: Option<u64>) {
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, EVENTS_KEY_EXPR, id
+    );
+
+    debug!("Declaring Events Subscriber on '{key_expr}'...");
+
+    let subscriber = session.declare_subscriber(&key_expr).await.unwrap();
+
+    info!("Press CTRL-C to quit...");
+    while let Ok(sample) = subscriber.recv_async().await {
+        match protocol::decode::<pw::messages::KernelEvent>(&sample) {
+            Ok(event) => {
+                let id = sample.key_expr().as_str().rsplit('/').next().unwrap_or("");
+                println!("ALERT {} {}", id, stringify_kernel_event(&event));
+            }
+            Err(err) => {
+                warn!("Could not parse message (ERROR: '{}')", err);
+            }
+        }
+    }
+}
+
+/// Print whether the required processes of machine id (all machines if None) are running, from the latest
+/// check of its watchdog.
+pub async fn health(session: &zenoh::Session, id: Option<u64>) {
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, HEALTH_KEY_EXPR, id
+    );
+
+    debug!("Querying health '{key_expr}'...");
+
+    let replies = session.get(&key_expr).await.unwrap();
+
+    let mut count = 0;
+    while let Ok(reply) = replies.recv_async().await {
+        match reply.result() {
+            Ok(sample) => match protocol::decode::<pw::messages::Health>(sample) {
+                Ok(health) => {
+                    count += 1;
+                    let id = sample.key_expr().as_str().rsplit('/').next().unwrap_or("");
+                    let mut lines = stringify_health(&health).into_iter();
+                    println!("{} {}", id, lines.next().unwrap_or_default());
+                    for line in lines {
+                        println!("  {}", line);
+                    }
+                }
+                Err(err) => warn!("Could not parse message (ERROR: '{}')", err),
+            },
+            // Machines without required processes reply with an error, only worth telling when asked for one.
+            Err(err) if id != "*" => {
+                eprintln!("{}", err.payload().try_to_string().unwrap_or_default())
+            }
+            Err(_) => {}
+        }
+    }
+
+    if count == 0 && id == "*" {
+        eprintln!("No machine has required processes");
+    }
+}
@@ -0,0 +1,606 @@
+use crate::protocol;
+use common::{pw, BASE_KEY_EXPR, LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR, METRICS_KEY_EXPR};
+use log::{debug, info, warn};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use zenoh::sample::{Sample, SampleKind};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Longest request we read, scrapers send a few hundred bytes.
+const MAX_REQUEST: usize = 8192;
+
+/// A client that sends no complete request within this is disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What we know of a machine, keyed by group and machine id.
+#[derive(Default)]
+struct Exported {
+    machine: Option<pw::messages::Machine>,
+    online: bool,
+    metrics: Option<pw::messages::Metrics>,
+}
+
+type Machines = Arc<Mutex<BTreeMap<(String, String), Exported>>>;
+
+/// Group and machine id from a key expression `pw/<group>/<kind>/<id>`.
+fn keys(sample: &Sample) -> Option<(String, String)> {
+    let mut chunks = sample.key_expr().as_str().split('/').skip(1);
+    let group = chunks.next()?.to_string();
+    let id = chunks.nth(1)?.to_string();
+
+    Some((group, id))
+}
+
+/// Keep what sample carries, a machine, liveliness or metrics.
+fn update(machines: &Machines, sample: &Sample) {
+    let Some(keys) = keys(sample) else {
+        return;
+    };
+    let Ok(mut machines) = machines.lock() else {
+        return;
+    };
+    let kind = sample
+        .key_expr()
+        .as_str()
+        .split('/')
+        .nth(2)
+        .unwrap_or_default();
+    let result = match kind {
+        MACHINE_KEY_EXPR => protocol::decode::<pw::messages::Machine>(sample)
+            .map(|machine| machines.entry(keys).or_default().machine = Some(machine)),
+        LIVELINESS_KEY_EXPR => {
+            machines.entry(keys).or_default().online = sample.kind() == SampleKind::Put;
+            Ok(())
+        }
+        METRICS_KEY_EXPR => protocol::decode::<pw::messages::Metrics>(sample)
+            .map(|metrics| machines.entry(keys).or_default().metrics = Some(metrics)),
+        _ => Ok(()),
+    };
+
+    if let Err(err) = result {
+        warn!("Could not parse message (ERROR: '{}')", err);
+    }
+}
+
+/// Samples of kind of all machines in all groups.
+fn key_expr(kind: &str) -> String {
+    format!("{}/*/{}/*", BASE_KEY_EXPR, kind)
+}
+
+/// Keep the machines up to date until the session is closed.
+async fn receive(session: zenoh::Session, machines: Machines) -> zenoh::Result<()> {
+    // Machines that started before us are only available from storage.
+    let getter = session.get(key_expr(MACHINE_KEY_EXPR)).await?;
+    while let Ok(reply) = getter.recv_async().await {
+        if let Ok(sample) = reply.result() {
+            update(&machines, sample);
+        }
+    }
+
+    let liveliness = session
+        .liveliness()
+        .declare_subscriber(key_expr(LIVELINESS_KEY_EXPR))
+        .history(true)
+        .await?;
+    let machine = session
+        .declare_subscriber(key_expr(MACHINE_KEY_EXPR))
+        .await?;
+    let metrics = session
+        .declare_subscriber(key_expr(METRICS_KEY_EXPR))
+        .await?;
+
+    loop {
+        let sample = tokio::select! {
+            sample = liveliness.recv_async() => sample,
+            sample = machine.recv_async() => sample,
+            sample = metrics.recv_async() => sample,
+        };
+
+        match sample {
+            Ok(sample) => update(&machines, &sample),
+            Err(_) => return Ok(()),
+        }
+    }
+}
+
+/// Serve the metrics of all machines on `http://<listen>/metrics` until CTRL-C.
+pub async fn serve(session: &zenoh::Session, listen: SocketAddr) {
+    let listener = match TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Could not listen on {} ({})", listen, err);
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics", listen);
+
+    let machines = Machines::default();
+    let mut receiving = tokio::spawn(receive(session.clone(), machines.clone()));
+
+    loop {
+        tokio::select! {
+            received = &mut receiving => {
+                // Without samples every scrape would see stale machines.
+                match received {
+                    Ok(Ok(())) => eprintln!("Session closed, no more metrics"),
+                    Ok(Err(err)) => eprintln!("Could not subscribe to the machines ({})", err),
+                    Err(err) => eprintln!("Could not receive metrics ({})", err),
+                }
+                break;
+            }
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    debug!("Scrape from {}", peer);
+                    tokio::spawn(respond(stream, machines.clone()));
+                }
+                Err(err) => warn!("Could not accept connection ({})", err),
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+}
+
+/// Answer one request and close the connection.
+async fn respond(mut stream: TcpStream, machines: Machines) {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Some(request)) => request,
+        Ok(None) => return,
+        Err(_) => {
+            debug!("No request within {:?}", REQUEST_TIMEOUT);
+            return;
+        }
+    };
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path.split('?').next()) {
+        ("GET", Some("/metrics")) => {
+            let body = match machines.lock() {
+                Ok(machines) => exposition(&machines),
+                Err(_) => String::new(),
+            };
+            ("200 OK", CONTENT_TYPE, body)
+        }
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()).await {
+        debug!("Could not respond ({})", err);
+    }
+}
+
+/// The request head, None if the client closed the connection before sending it.
+async fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => request.extend(&buf[..n]),
+        }
+    }
+
+    Some(request)
+}
+
+/// Samples of one metric, OpenMetrics wants them together.
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    /// Label set and value, a label set is only allowed once (e.g. tmpfs mounted twice on the same path).
+    samples: Vec<(String, f64)>,
+}
+
+/// OpenMetrics text, families in the order they were first added.
+#[derive(Default)]
+struct Exposition {
+    families: Vec<Family>,
+}
+
+impl Exposition {
+    fn add(
+        &mut self,
+        name: &'static str,
+        kind: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        let index = match self.families.iter().position(|family| family.name == name) {
+            Some(index) => index,
+            None => {
+                self.families.push(Family {
+                    name,
+                    kind,
+                    help,
+                    samples: vec![],
+                });
+                self.families.len() - 1
+            }
+        };
+
+        let labels = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let samples = &mut self.families[index].samples;
+        if !samples.iter().any(|(existing, _)| *existing == labels) {
+            samples.push((labels, value));
+        }
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, "gauge", help, labels, value);
+    }
+
+    fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, "counter", help, labels, value);
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::new();
+
+        for family in &self.families {
+            text += &format!("# TYPE {} {}\n", family.name, family.kind);
+            text += &format!("# HELP {} {}\n", family.name, family.help);
+            // Counter samples have the _total suffix, the family does not.
+            let suffix = if family.kind == "counter" {
+                "_total"
+            } else {
+                ""
+            };
+            for (labels, value) in &family.samples {
+                text += &format!("{}{}{{{}}} {}\n", family.name, suffix, labels, value);
+            }
+        }
+        text += "# EOF\n";
+
+        text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The metrics of all machines, offline machines only with pw_up 0 and their info.
+fn exposition(machines: &BTreeMap<(String, String), Exported>) -> String {
+    let mut exposition = Exposition::default();
+
+    for ((group, id), exported) in machines {
+        let hostname = exported
+            .machine
+            .as_ref()
+            .map(|machine| machine.hostname.as_str())
+            .unwrap_or_default();
+        let labels = [
+            ("machine", id.as_str()),
+            ("hostname", hostname),
+            ("group", group.as_str()),
+        ];
+
+        exposition.gauge(
+            "pw_up",
+            "Whether the machine is online (liveliness token present).",
+            &labels,
+            if exported.online { 1.0 } else { 0.0 },
+        );
+
+        if let Some(machine) = &exported.machine {
+            let os = machine
+                .os_release
+                .as_ref()
+                .map(|os| os.pretty_name.as_str())
+                .unwrap_or_default();
+            exposition.gauge(
+                "pw_machine_info",
+                "Kernel, architecture and distribution of the machine.",
+                &[
+                    &labels[..],
+                    &[
+                        ("kernel", machine.version.as_str()),
+                        ("architecture", machine.architecture.as_str()),
+                        ("os", os),
+                    ],
+                ]
+                .concat(),
+                1.0,
+            );
+            exposition.gauge(
+                "pw_memory_total_bytes",
+                "Physical memory.",
+                &labels,
+                machine.physical_mem_total_kb as f64 * 1024.0,
+            );
+        }
+
+        if let Some(metrics) = exported.metrics.as_ref().filter(|_| exported.online) {
+            add_metrics(&mut exposition, &labels, metrics);
+        }
+    }
+
+    exposition.text()
+}
+
+/// labels followed by extra.
+fn with<'a>(
+    labels: &[(&'a str, &'a str)],
+    extra: &[(&'a str, &'a str)],
+) -> Vec<(&'a str, &'a str)> {
+    labels.iter().chain(extra).copied().collect()
+}
+
+fn add_metrics(
+    exposition: &mut Exposition,
+    labels: &[(&str, &str)],
+    metrics: &pw::messages::Metrics,
+) {
+    exposition.gauge(
+        "pw_uptime_seconds",
+        "Seconds since boot.",
+        labels,
+        metrics.uptime as f64,
+    );
+
+    if let Some(load) = &metrics.load_average {
+        for (period, value) in [("1m", load.one), ("5m", load.five), ("15m", load.fifteen)] {
+            exposition.gauge(
+                "pw_load_average",
+                "Load average from /proc/loadavg.",
+                &with(labels, &[("period", period)]),
+                value,
+            );
+        }
+    }
+
+    for (resource, pressure) in [
+        ("cpu", &metrics.cpu_pressure),
+        ("memory", &metrics.memory_pressure),
+        ("io", &metrics.io_pressure),
+    ] {
+        let Some(pressure) = pressure else {
+            continue;
+        };
+        for (kind, avg10, total) in [
+            ("some", pressure.some_avg10, pressure.some_total),
+            ("full", pressure.full_avg10, pressure.full_total),
+        ] {
+            let labels = with(labels, &[("resource", resource), ("kind", kind)]);
+            exposition.gauge(
+                "pw_pressure_avg10_percent",
+                "Pressure stall average over 10 seconds.",
+                &labels,
+                avg10,
+            );
+            exposition.counter(
+                "pw_pressure_stall_seconds",
+                "Total pressure stall time.",
+                &labels,
+                total as f64 / 1_000_000.0,
+            );
+        }
+    }
+
+    for filesystem in &metrics.filesystems {
+        let labels = with(
+            labels,
+            &[
+                ("mountpoint", filesystem.mount_point.as_str()),
+                ("device", filesystem.device.as_str()),
+                ("fstype", filesystem.fs_type.as_str()),
+            ],
+        );
+        exposition.gauge(
+            "pw_filesystem_size_bytes",
+            "Size of the filesystem.",
+            &labels,
+            filesystem.total_bytes as f64,
+        );
+        exposition.gauge(
+            "pw_filesystem_free_bytes",
+            "Free bytes in the filesystem.",
+            &labels,
+            filesystem.free_bytes as f64,
+        );
+        exposition.gauge(
+            "pw_filesystem_available_bytes",
+            "Free bytes available to unprivileged users.",
+            &labels,
+            filesystem.available_bytes as f64,
+        );
+    }
+
+    for device in &metrics.block_devices {
+        let labels = with(labels, &[("device", device.name.as_str())]);
+        exposition.counter(
+            "pw_disk_read_bytes",
+            "Bytes read since boot.",
+            &labels,
+            device.read_bytes as f64,
+        );
+        exposition.counter(
+            "pw_disk_written_bytes",
+            "Bytes written since boot.",
+            &labels,
+            device.written_bytes as f64,
+        );
+        exposition.gauge(
+            "pw_disk_utilization_percent",
+            "Percentage of time the device was busy.",
+            &labels,
+            device.utilization,
+        );
+    }
+
+    for zone in &metrics.thermal_zones {
+        exposition.gauge(
+            "pw_thermal_zone_celsius",
+            "Temperature of the thermal zone.",
+            &with(labels, &[("zone", zone.name.as_str())]),
+            zone.temperature as f64 / 1000.0,
+        );
+    }
+
+    for frequency in &metrics.cpu_frequencies {
+        let cpu = frequency.cpu.to_string();
+        exposition.gauge(
+            "pw_cpu_frequency_hertz",
+            "Current frequency of the cpu.",
+            &with(labels, &[("cpu", cpu.as_str())]),
+            frequency.current_khz as f64 * 1000.0,
+        );
+    }
+
+    for sensor in &metrics.sensors {
+        exposition.gauge(
+            "pw_sensor_value",
+            "Hardware monitoring sensor in degree Celsius, volt, ampere, watt or RPM.",
+            &with(
+                labels,
+                &[
+                    ("chip", sensor.chip.as_str()),
+                    ("label", sensor.label.as_str()),
+                    ("kind", sensor.kind().as_str_name()),
+                ],
+            ),
+            sensor.value,
+        );
+    }
+
+    exposition.gauge(
+        "pw_throttled",
+        "Raspberry Pi firmware throttling flags, 0 on other machines.",
+        labels,
+        metrics.throttled as f64,
+    );
+
+    for cgroup in &metrics.cgroups {
+        let labels = with(labels, &[("cgroup", cgroup.path.as_str())]);
+        exposition.counter(
+            "pw_cgroup_cpu_seconds",
+            "Cpu time used by the cgroup.",
+            &labels,
+            cgroup.cpu_usage_usec as f64 / 1_000_000.0,
+        );
+        exposition.gauge(
+            "pw_cgroup_memory_bytes",
+            "Memory used by the cgroup.",
+            &labels,
+            cgroup.memory_current as f64,
+        );
+        exposition.counter(
+            "pw_cgroup_oom_kills",
+            "Processes killed by the OOM killer in the cgroup.",
+            &labels,
+            cgroup.oom_kill as f64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let mut exposition = Exposition::default();
+        let labels = [("machine", "42"), ("hostname", "pi \"lab\"\\1\nb")];
+
+        exposition.gauge("pw_up", "Whether the machine is online.", &labels, 1.0);
+        exposition.counter("pw_stall_seconds", "Stall time.", &labels, 2.5);
+        exposition.gauge(
+            "pw_up",
+            "Whether the machine is online.",
+            &[("machine", "7")],
+            0.0,
+        );
+        // A label set only once per family, e.g. tmpfs mounted twice on the same path.
+        exposition.gauge(
+            "pw_up",
+            "Whether the machine is online.",
+            &[("machine", "7")],
+            1.0,
+        );
+
+        assert_eq!(
+            exposition.text(),
+            "# TYPE pw_up gauge\n\
+             # HELP pw_up Whether the machine is online.\n\
+             pw_up{machine=\"42\",hostname=\"pi \\\"lab\\\"\\\\1\\nb\"} 1\n\
+             pw_up{machine=\"7\"} 0\n\
+             # TYPE pw_stall_seconds counter\n\
+             # HELP pw_stall_seconds Stall time.\n\
+             pw_stall_seconds_total{machine=\"42\",hostname=\"pi \\\"lab\\\"\\\\1\\nb\"} 2.5\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(exposition(&BTreeMap::new()), "# EOF\n");
+    }
+
+    #[test]
+    fn offline() {
+        let mut machines = BTreeMap::new();
+        machines.insert(
+            ("1".to_string(), "42".to_string()),
+            Exported {
+                machine: None,
+                online: false,
+                metrics: Some(pw::messages::Metrics {
+                    uptime: 100,
+                    ..Default::default()
+                }),
+            },
+        );
+
+        // The last metrics of an offline machine are not exported.
+        let text = exposition(&machines);
+        assert!(text.contains("pw_up{machine=\"42\",hostname=\"\",group=\"1\"} 0\n"));
+        assert!(!text.contains("pw_uptime_seconds"));
+    }
+}
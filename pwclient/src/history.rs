@@ -0,0 +1,94 @@
+use crate::processes;
+use crate::protocol;
+use clap::ValueEnum;
+use common::{
+    deserialize, pw, stringify_duration, stringify_kernel_event, stringify_metrics, BASE_KEY_EXPR,
+    EVENTS_SERIES, GROUP_KEY_EXPR, HISTORY_KEY_EXPR, METRICS_SERIES, PROCESSES_SERIES,
+};
+use log::{debug, warn};
+use zenoh::query::ConsolidationMode;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Series {
+    Metrics,
+    /// Only kept if the service is configured to.
+    Processes,
+    /// OOM kills, segfaults and hung tasks.
+    Events,
+}
+
+impl Series {
+    fn name(&self) -> &'static str {
+        match self {
+            Series::Metrics => METRICS_SERIES,
+            Series::Processes => PROCESSES_SERIES,
+            Series::Events => EVENTS_SERIES,
+        }
+    }
+
+    fn stringify(&self, payload: &[u8]) -> Result<Vec<String>, prost::DecodeError> {
+        match self {
+            Series::Metrics => deserialize::<pw::messages::Metrics>(payload)
+                .map(|metrics| stringify_metrics(&metrics)),
+            Series::Processes => deserialize::<pw::messages::Processes>(payload)
+                .map(|processes| processes::stringify(&processes)),
+            Series::Events => deserialize::<pw::messages::KernelEvent>(payload)
+                .map(|event| vec![stringify_kernel_event(&event)]),
+        }
+    }
+}
+
+/// Print the samples of series machine id kept for the last since seconds, oldest first.
+pub async fn show(session: &zenoh::Session, id: u64, series: Series, since: u64) {
+    let selector = format!(
+        "{}/{}/{}/{}/{}?from=-{}",
+        BASE_KEY_EXPR,
+        GROUP_KEY_EXPR,
+        HISTORY_KEY_EXPR,
+        id,
+        series.name(),
+        since
+    );
+
+    debug!("Querying history '{selector}'...");
+
+    // All replies have the same key expression, the default consolidation would keep only the last.
+    let replies = session
+        .get(&selector)
+        .consolidation(ConsolidationMode::None)
+        .await
+        .unwrap();
+
+    let mut count = 0;
+    while let Ok(reply) = replies.recv_async().await {
+        match reply.result() {
+            Ok(sample) => {
+                let lines =
+                    protocol::decode::<pw::messages::HistorySample>(sample).and_then(|history| {
+                        let when = stringify_duration(history.time_usec / 1_000_000);
+                        Ok((when, series.stringify(&history.payload)?))
+                    });
+
+                match lines {
+                    Ok((when, lines)) => {
+                        count += 1;
+                        println!("{}", when);
+                        for line in lines {
+                            println!("  {}", line);
+                        }
+                    }
+                    Err(err) => warn!("Could not parse message (ERROR: '{}')", err),
+                }
+            }
+            Err(err) => {
+                let payload = err
+                    .payload()
+                    .try_to_string()
+                    .unwrap_or_else(|e| e.to_string().into());
+                println!("Error: {}", payload);
+            }
+        }
+    }
+
+    println!("{} samples", count);
+}
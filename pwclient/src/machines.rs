@@ -0,0 +1,227 @@
+use crate::output::{machine_id, machine_row, Output, Writer};
+use crate::protocol;
+use clap::ValueEnum;
+use common::{
+    kernel_release, os_version, pw, stringify_message, stringify_version_info, BASE_KEY_EXPR,
+    GROUP_KEY_EXPR, LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR, VERSION_KEY_EXPR,
+};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use zenoh::sample::SampleKind;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GroupBy {
+    /// Distribution and version, e.g. "Raspbian GNU/Linux 11 (bullseye)".
+    Os,
+    /// Kernel release and architecture.
+    Kernel,
+    /// Machine hardware name, e.g. "armv7l".
+    Arch,
+}
+
+impl GroupBy {
+    fn key(&self, machine: &pw::messages::Machine) -> String {
+        let key = match self {
+            GroupBy::Os => os_version(machine).unwrap_or_default(),
+            GroupBy::Kernel => kernel_release(machine),
+            GroupBy::Arch => machine.architecture.clone(),
+        };
+
+        if key.is_empty() {
+            return "unknown".to_string();
+        }
+
+        key
+    }
+}
+
+/// All machines with their hostname, grouped by group_by, or as rows with output json or csv.
+pub async fn show(session: &zenoh::Session, group_by: GroupBy, output: Output) {
+    let machines = query(session).await;
+
+    if output != Output::Table {
+        let online = online(session).await;
+        let mut writer = Writer::new(output);
+        for (id, machine) in &machines {
+            writer.write(&machine_row(id, Some(machine), online.contains(id)));
+        }
+        return;
+    }
+
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (id, machine) in machines {
+        groups
+            .entry(group_by.key(&machine))
+            .or_default()
+            .push(format!("{} ({})", machine.hostname, id));
+    }
+
+    for (key, mut machines) in groups {
+        machines.sort();
+        println!("{} [{}]", key, machines.len());
+        for machine in machines {
+            println!("  {}", machine);
+        }
+    }
+}
+
+/// All machines and whether they are online, then (unless once) every machine that goes online or offline.
+pub async fn follow(session: &zenoh::Session, output: Output, once: bool) {
+    let machines = query(session).await;
+    let mut online = online(session).await;
+    let mut writer = Writer::new(output);
+
+    let mut print = |id: &str, online: bool| {
+        let machine = machines.get(id);
+        if output != Output::Table {
+            writer.write(&machine_row(id, machine, online));
+            return;
+        }
+
+        let state = if online { "online" } else { "offline" };
+        match machine {
+            Some(machine) => {
+                println!("{} {} {}", id, machine.hostname, state);
+                for (key, value) in stringify_message(machine) {
+                    println!("  {}: {}", key, value);
+                }
+            }
+            None => println!("{} {}", id, state),
+        }
+    };
+
+    // Online machines that did not reply to the query, e.g. without a storage in the router.
+    let ids: BTreeSet<&String> = machines.keys().chain(&online).collect();
+    for id in ids {
+        print(id, online.contains(id));
+    }
+
+    if once {
+        return;
+    }
+
+    let key_expr = format!(
+        "{}/{}/{}/*",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, LIVELINESS_KEY_EXPR
+    );
+
+    debug!("Declaring Liveliness Subscriber on '{key_expr}'...");
+
+    let subscriber = session
+        .liveliness()
+        .declare_subscriber(&key_expr)
+        .await
+        .unwrap();
+
+    info!("Press CTRL-C to quit...");
+    while let Ok(sample) = subscriber.recv_async().await {
+        let id = machine_id(&sample);
+        let put = sample.kind() == SampleKind::Put;
+        // The token may have been declared between our query and subscribing.
+        if put != online.contains(&id) {
+            if put {
+                online.insert(id.clone());
+            } else {
+                online.remove(&id);
+            }
+            print(&id, put);
+        }
+    }
+}
+
+/// Print our build, then the build of the service on machine id (all machines if None) as it replies.
+pub async fn versions(
+    session: &zenoh::Session,
+    id: Option<u64>,
+    local: &pw::messages::VersionInfo,
+) {
+    println!("local {}", stringify_version_info(local));
+
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, VERSION_KEY_EXPR, id
+    );
+
+    debug!("Querying versions '{key_expr}'...");
+
+    let mut count = 0;
+    let replies = session.get(&key_expr).await.unwrap();
+    while let Ok(reply) = replies.recv_async().await {
+        match reply.result() {
+            Ok(sample) => match protocol::decode::<pw::messages::VersionInfo>(sample) {
+                Ok(info) => {
+                    count += 1;
+                    println!("{} {}", machine_id(sample), stringify_version_info(&info));
+                }
+                Err(err) => warn!("Could not parse message (ERROR: '{}')", err),
+            },
+            Err(err) => {
+                let payload = err
+                    .payload()
+                    .try_to_string()
+                    .unwrap_or_else(|e| e.to_string().into());
+                warn!(">> Received (ERROR: '{}')", payload);
+            }
+        }
+    }
+
+    // Services from before the version was published do not reply.
+    if count == 0 {
+        eprintln!("No service replied with its version");
+    }
+}
+
+/// The machines in storage, keyed by id.
+async fn query(session: &zenoh::Session) -> BTreeMap<String, pw::messages::Machine> {
+    let key_expr = format!(
+        "{}/{}/{}/**",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, MACHINE_KEY_EXPR
+    );
+
+    debug!("Declaring Machine getter on '{key_expr}'...");
+
+    let mut machines = BTreeMap::new();
+
+    let getter = session.get(&key_expr).await.unwrap();
+    while let Ok(reply) = getter.recv_async().await {
+        match reply.result() {
+            Ok(sample) => match protocol::decode::<pw::messages::Machine>(sample) {
+                Ok(machine) => {
+                    machines.insert(machine_id(sample), machine);
+                }
+                Err(err) => {
+                    warn!("Could not parse message (ERROR: '{}')", err);
+                }
+            },
+            Err(err) => {
+                let payload = err
+                    .payload()
+                    .try_to_string()
+                    .unwrap_or_else(|e| e.to_string().into());
+                warn!(">> Received (ERROR: '{}')", payload);
+            }
+        }
+    }
+
+    machines
+}
+
+/// Ids of the machines that are online.
+pub async fn online(session: &zenoh::Session) -> HashSet<String> {
+    let key_expr = format!(
+        "{}/{}/{}/*",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, LIVELINESS_KEY_EXPR
+    );
+
+    let mut online = HashSet::new();
+
+    let getter = session.liveliness().get(&key_expr).await.unwrap();
+    while let Ok(reply) = getter.recv_async().await {
+        if let Ok(sample) = reply.result() {
+            online.insert(machine_id(sample));
+        }
+    }
+
+    online
+}
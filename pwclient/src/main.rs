@@ -1,32 +1,248 @@
-use clap::Parser;
-use common::{
-    deserialize_machine, stringify_duration, stringify_message, BASE_KEY_EXPR, GROUP_KEY_EXPR,
-    LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR,
+mod commands;
+mod connections;
+mod events;
+mod exporter;
+mod history;
+mod machines;
+mod metrics;
+mod output;
+mod processes;
+mod protocol;
+mod recording;
+mod tui;
+mod units;
+
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use common::{kmsg_level, pw::messages::signal_request::Signo};
+use log::info;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
 };
-use log::{debug, info, warn};
-use zenoh::sample::SampleKind;
 
-fn version_info() -> String {
-    let mut build_type = "release";
-    if cfg!(debug_assertions) {
-        build_type = "debug";
+/// This build, from the environment vergen and cargo set when it was built.
+fn version() -> common::pw::messages::VersionInfo {
+    let features = option_env!("VERGEN_CARGO_FEATURES").unwrap_or_default();
+
+    common::pw::messages::VersionInfo {
+        name: option_env!("CARGO_PKG_NAME")
+            .unwrap_or_default()
+            .to_string(),
+        version: option_env!("CARGO_PKG_VERSION")
+            .unwrap_or_default()
+            .to_string(),
+        git_sha: option_env!("VERGEN_GIT_SHA")
+            .unwrap_or_default()
+            .to_string(),
+        git_dirty: option_env!("VERGEN_GIT_DIRTY") == Some("true"),
+        build_timestamp: option_env!("VERGEN_BUILD_TIMESTAMP")
+            .unwrap_or_default()
+            .to_string(),
+        target_triple: option_env!("VERGEN_CARGO_TARGET_TRIPLE")
+            .unwrap_or_default()
+            .to_string(),
+        features: features
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+        debug: cfg!(debug_assertions),
+        protocol: Some(common::protocol()),
     }
+}
 
-    format!(
-        "{} v{} {} (Git SHA: {}, dirty: {}, build time: {})",
-        option_env!("CARGO_PKG_NAME").unwrap_or_default(),
-        option_env!("CARGO_PKG_VERSION").unwrap_or_default(),
-        build_type,
-        option_env!("VERGEN_GIT_SHA").unwrap_or_default(),
-        option_env!("VERGEN_GIT_DIRTY").unwrap_or_default(),
-        option_env!("VERGEN_BUILD_TIMESTAMP").unwrap_or_default()
-    )
+fn version_info() -> String {
+    common::stringify_version_info(&version())
 }
 
 #[derive(Parser, Default, Debug)]
 struct Arguments {
     #[clap(default_value = "pw_config.json")]
     config_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Output of the machines when there is no command, the machines, metrics and processes commands
+    /// have their own.
+    #[clap(long, value_enum, default_value = "table")]
+    output: output::Output,
+    /// Exit after the machines instead of following them, when there is no command.
+    #[clap(long)]
+    once: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Open file descriptors and sockets of process pid on machine id.
+    Fds { id: u64, pid: i32 },
+    /// Status, capabilities, limits and environment of process pid on machine id.
+    Detail { id: u64, pid: i32 },
+    /// Send a signal to process pid on machine id, the service must allow the signal command.
+    Signal {
+        id: u64,
+        pid: i32,
+        /// term, kill, hup, stop or cont.
+        #[clap(long, value_parser = parse_signal, default_value = "term")]
+        signal: Signo,
+    },
+    /// Change the nice value (-20..19) of process pid on machine id.
+    Renice {
+        id: u64,
+        pid: i32,
+        #[clap(allow_negative_numbers = true)]
+        nice: i32,
+    },
+    /// Restrict process pid on machine id to CPUs, e.g. "0,1".
+    Affinity {
+        id: u64,
+        pid: i32,
+        #[clap(value_delimiter = ',', required = true)]
+        cpus: Vec<u32>,
+    },
+    /// Stream the kernel log of machine id until CTRL-C.
+    Dmesg {
+        id: u64,
+        /// Only records with this level or more severe, name (err, warning, ...) or number 0-7.
+        #[clap(long, value_parser = parse_level)]
+        level: Option<u32>,
+        /// Only records matching this regular expression.
+        #[clap(long)]
+        grep: Option<String>,
+        /// Start with the records already in the kernel ring buffer.
+        #[clap(long)]
+        all: bool,
+    },
+    /// Listening sockets and established connections on machine id (all machines if omitted).
+    Connections {
+        id: Option<u64>,
+        /// Only listening sockets.
+        #[clap(long)]
+        listening: bool,
+        /// Only established connections.
+        #[clap(long)]
+        established: bool,
+        /// Only sockets with this local or remote port.
+        #[clap(long)]
+        port: Option<u16>,
+        /// Only sockets owned by processes with a name containing this.
+        #[clap(long)]
+        process: Option<String>,
+    },
+    /// Alert on OOM kills, segfaults and hung tasks on machine id (all machines if omitted).
+    Events { id: Option<u64> },
+    /// Whether the processes the service of machine id (all machines if omitted) requires are running.
+    Health { id: Option<u64> },
+    /// Build (git SHA, build time, target, features) of the service on machine id (all machines if omitted)
+    /// and of this pwclient.
+    Version { id: Option<u64> },
+    /// Samples machine id kept of the last minutes, e.g. to see what happened before an incident.
+    History {
+        id: u64,
+        #[clap(long, value_enum, default_value = "metrics")]
+        series: history::Series,
+        /// Seconds back from now.
+        #[clap(long, default_value = "600")]
+        since: u64,
+    },
+    /// All machines grouped by operating system, kernel or architecture.
+    Machines {
+        #[clap(long, value_enum, default_value = "os")]
+        group_by: machines::GroupBy,
+        /// Grouped for humans, or one row per machine.
+        #[clap(long, value_enum, default_value = "table")]
+        output: output::Output,
+    },
+    /// Follow metrics samples of machine id (all machines if omitted).
+    Metrics {
+        id: Option<u64>,
+        /// Samples for humans, or one row per sample.
+        #[clap(long, value_enum, default_value = "table")]
+        output: output::Output,
+        /// Exit after the first sample of each machine instead of following.
+        #[clap(long)]
+        once: bool,
+    },
+    /// Follow cpu, memory and I/O usage of the units and containers of machine id (all machines if omitted).
+    Cgroups {
+        id: Option<u64>,
+        /// Exit after the first sample of each machine instead of following.
+        #[clap(long)]
+        once: bool,
+    },
+    /// Follow the process lists of machine id (all machines if omitted).
+    Processes {
+        id: Option<u64>,
+        #[clap(long, value_enum, default_value = "none")]
+        group_by: processes::GroupBy,
+        /// Column to sort by, the rates are per second since the previous list.
+        #[clap(long, value_enum, default_value = "pid")]
+        sort: processes::SortBy,
+        /// Lists for humans, or one row per process.
+        #[clap(long, value_enum, default_value = "table")]
+        output: output::Output,
+        /// Exit after the first process list of each machine instead of following.
+        #[clap(long)]
+        once: bool,
+    },
+    /// Follow the systemd services of machine id (all machines if omitted), needs a service built with
+    /// the systemd feature.
+    Units {
+        id: Option<u64>,
+        /// Only failed services and services that were restarted.
+        #[clap(long)]
+        failing: bool,
+    },
+    /// Serve the metrics of all machines in all groups for Prometheus on http://<listen>/metrics.
+    Exporter {
+        #[clap(long, default_value = "127.0.0.1:9464")]
+        listen: SocketAddr,
+    },
+    /// Interactive view of all machines with their metrics.
+    Top {
+        /// Record every received sample to this file, for replay.
+        #[clap(long)]
+        record: Option<PathBuf>,
+        /// Alert rules, a TOML (.toml) or JSON file.
+        #[clap(long)]
+        alerts: Option<PathBuf>,
+    },
+    /// Interactive view of a recording made with top --record, without connecting to the network.
+    Replay {
+        file: PathBuf,
+        /// Replay this many times faster than recorded.
+        #[clap(long, default_value = "1", value_parser = parse_speed)]
+        speed: f64,
+        /// Alert rules, evaluated against the replayed samples.
+        #[clap(long)]
+        alerts: Option<PathBuf>,
+    },
+}
+
+/// The rules in path if there is one, None (after telling why) if they can not be used.
+fn load_alerts(path: Option<&Path>) -> Option<Option<tui::alerts::Alerts>> {
+    match path.map(tui::alerts::Alerts::load).transpose() {
+        Ok(alerts) => Some(alerts),
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    }
+}
+
+fn parse_speed(speed: &str) -> Result<f64, String> {
+    match speed.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("Invalid speed '{}', a number above 0", speed)),
+    }
+}
+
+fn parse_signal(signal: &str) -> Result<Signo, String> {
+    let name = signal.to_lowercase();
+    Signo::from_str_name(name.strip_prefix("sig").unwrap_or(&name))
+        .ok_or(format!("Unknown signal '{}'", signal))
+}
+
+fn parse_level(level: &str) -> Result<u32, String> {
+    kmsg_level(level).ok_or(format!("Unknown level '{}'", level))
 }
 
 #[tokio::main]
@@ -34,71 +250,112 @@ async fn main() {
     env_logger::init();
     let args = Arguments::parse();
 
+    // Before a command they would be ignored, commands that support them take them after their name.
+    if args.command.is_some() && (args.output != output::Output::Table || args.once) {
+        Arguments::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--output and --once are only allowed without a command or after machines, metrics, \
+                 cgroups (--once) or processes",
+            )
+            .exit();
+    }
+
     info!("Starting {}", version_info());
 
+    if let Some(Command::Replay {
+        file,
+        speed,
+        alerts,
+    }) = &args.command
+    {
+        let Some(alerts) = load_alerts(alerts.as_deref()) else {
+            return;
+        };
+        match recording::read(file) {
+            Ok(records) => tui::run(tui::Source::Replay(records, *speed), alerts).await,
+            Err(err) => eprintln!("Could not read {} ({})", file.display(), err),
+        }
+        return;
+    }
+
     zenoh::init_log_from_env_or("error");
     let config = zenoh::Config::from_file(args.config_file).unwrap();
     let session = zenoh::open(config).await.unwrap();
 
-    let key_expr_machine = format!(
-        "{}/{}/{}/**",
-        BASE_KEY_EXPR, GROUP_KEY_EXPR, MACHINE_KEY_EXPR,
-    );
-
-    debug!("Declaring Machine getter on '{key_expr_machine}'...");
-
-    let machine_getter = session.get(key_expr_machine).await.unwrap();
-    while let Ok(reply) = machine_getter.recv_async().await {
-        match reply.result() {
-            Ok(sample) => {
-                let payload = &*(sample.payload().to_bytes());
-                match deserialize_machine(payload) {
-                    Ok(machine) => {
-                        //debug!("{:?}", sample);
-                        info!(
-                            "Received [from {}, to {}, when {}] : '{:?}')",
-                            "<zid>",
-                            sample.key_expr().as_str(),
-                            stringify_duration(
-                                sample.timestamp().unwrap().get_time().as_secs().into()
-                            ),
-                            stringify_message(&machine)
-                        );
-                    }
-                    Err(err) => {
-                        warn!("Could not parse message (ERROR: '{}')", err);
-                    }
-                }
+    if let Some(command) = args.command {
+        match command {
+            Command::Fds { id, pid } => commands::open_files(&session, id, pid).await,
+            Command::Detail { id, pid } => commands::detail(&session, id, pid).await,
+            Command::Signal { id, pid, signal } => {
+                commands::signal(&session, id, pid, signal).await
+            }
+            Command::Renice { id, pid, nice } => commands::renice(&session, id, pid, nice).await,
+            Command::Affinity { id, pid, cpus } => {
+                commands::affinity(&session, id, pid, cpus).await
             }
-            Err(err) => {
-                let payload = err
-                    .payload()
-                    .try_to_string()
-                    .unwrap_or_else(|e| e.to_string().into());
-                warn!(">> Received (ERROR: '{}')", payload);
+            Command::Dmesg {
+                id,
+                level,
+                grep,
+                all,
+            } => commands::kmsg(&session, id, level, grep, all).await,
+            Command::Connections {
+                id,
+                listening,
+                established,
+                port,
+                process,
+            } => {
+                let filter = connections::Filter {
+                    listening,
+                    established,
+                    port,
+                    process,
+                };
+                connections::show(&session, id, &filter).await
             }
+            Command::Events { id } => events::follow(&session, id).await,
+            Command::Health { id } => events::health(&session, id).await,
+            Command::Version { id } => machines::versions(&session, id, &version()).await,
+            Command::Exporter { listen } => exporter::serve(&session, listen).await,
+            Command::History { id, series, since } => {
+                history::show(&session, id, series, since).await
+            }
+            Command::Machines { group_by, output } => {
+                machines::show(&session, group_by, output).await
+            }
+            Command::Metrics { id, output, once } => {
+                metrics::follow(&session, id, output, once).await
+            }
+            Command::Cgroups { id, once } => metrics::follow_cgroups(&session, id, once).await,
+            Command::Processes {
+                id,
+                group_by,
+                sort,
+                output,
+                once,
+            } => processes::follow(&session, id, group_by, sort, output, once).await,
+            Command::Units { id, failing } => units::follow(&session, id, failing).await,
+            Command::Top { record, alerts } => {
+                let Some(alerts) = load_alerts(alerts.as_deref()) else {
+                    return;
+                };
+                let recorder = match record.as_deref().map(recording::Recorder::create) {
+                    Some(Err(err)) => {
+                        eprintln!("Could not create recording ({})", err);
+                        return;
+                    }
+                    recorder => recorder.and_then(Result::ok),
+                };
+                tui::run(tui::Source::Live(session.clone(), recorder), alerts).await
+            }
+            // Handled before connecting.
+            Command::Replay { .. } => {}
         }
-    }
 
-    let key_expr_liveliness = format!(
-        "{}/{}/{}/**",
-        BASE_KEY_EXPR, GROUP_KEY_EXPR, LIVELINESS_KEY_EXPR
-    );
-
-    debug!("Declaring Liveliness Subscriber on '{key_expr_liveliness}'...");
-
-    let liveliness_subscriber = session
-        .liveliness()
-        .declare_subscriber(&key_expr_liveliness)
-        .history(true)
-        .await
-        .unwrap();
-
-    info!("Press CTRL-C to quit...");
-    while let Ok(sample) = liveliness_subscriber.recv_async().await {
-        match sample.kind() {
-            SampleKind::Put => info!("machine online ('{}')", sample.key_expr().as_str()),
-            SampleKind::Delete => info!("machine offline ('{}')", sample.key_expr().as_str()),
-        }
+        return;
     }
+
+    machines::follow(&session, args.output, args.once).await;
 }
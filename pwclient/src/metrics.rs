@@ -0,0 +1,82 @@
+use crate::machines;
+use crate::output::{self, machine_id, metrics_row, Output, Round, Writer};
+use crate::protocol;
+use common::{
+    pw, stringify_cgroup, stringify_duration, stringify_metrics, BASE_KEY_EXPR, GROUP_KEY_EXPR,
+    METRICS_KEY_EXPR,
+};
+use log::{debug, info, warn};
+
+/// Print metrics samples of machine id (all machines if None) as they arrive, with once only the first
+/// sample of each machine.
+pub async fn follow(session: &zenoh::Session, id: Option<u64>, output: Output, once: bool) {
+    subscribe(session, id, output, once, stringify_metrics).await
+}
+
+/// Print the usage of all units and containers of machine id (all machines if None),
+/// the largest memory users first.
+pub async fn follow_cgroups(session: &zenoh::Session, id: Option<u64>, once: bool) {
+    subscribe(session, id, Output::Table, once, |metrics| {
+        let mut cgroups: Vec<&pw::messages::CgroupUsage> = metrics.cgroups.iter().collect();
+        cgroups.sort_by_key(|cgroup| std::cmp::Reverse(cgroup.memory_current));
+        cgroups.into_iter().map(stringify_cgroup).collect()
+    })
+    .await
+}
+
+async fn subscribe<F>(
+    session: &zenoh::Session,
+    id: Option<u64>,
+    output: Output,
+    once: bool,
+    stringify: F,
+) where
+    F: Fn(&pw::messages::Metrics) -> Vec<String>,
+{
+    // The machines online now, a round ends when each of them sent a sample.
+    let mut round = if once {
+        Some(Round::new(machines::online(session).await, id))
+    } else {
+        None
+    };
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, METRICS_KEY_EXPR, id
+    );
+
+    debug!("Declaring Metrics Subscriber on '{key_expr}'...");
+
+    let subscriber = session.declare_subscriber(&key_expr).await.unwrap();
+
+    if !once {
+        info!("Press CTRL-C to quit...");
+    }
+
+    let mut writer = Writer::new(output);
+    while let Some(sample) = output::next(&subscriber, &mut round).await {
+        match protocol::decode::<pw::messages::Metrics>(&sample) {
+            Ok(metrics) if output != Output::Table => {
+                writer.write(&metrics_row(
+                    &machine_id(&sample),
+                    &output::time(&sample),
+                    &metrics,
+                ));
+            }
+            Ok(metrics) => {
+                let when = sample
+                    .timestamp()
+                    .map(|t| stringify_duration(t.get_time().as_secs().into()))
+                    .unwrap_or_default();
+
+                println!("{} {}", sample.key_expr().as_str(), when);
+                for line in stringify(&metrics) {
+                    println!("  {}", line);
+                }
+            }
+            Err(err) => {
+                warn!("Could not parse message (ERROR: '{}')", err);
+            }
+        }
+    }
+}
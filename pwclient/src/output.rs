@@ -0,0 +1,363 @@
+use clap::ValueEnum;
+use common::{max_temperature, os_version, pw, stringify_container};
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    process,
+    time::Duration,
+};
+use zenoh::{handlers::FifoChannelHandler, pubsub::Subscriber, sample::Sample};
+
+/// With --once, how long we wait for the online machines before giving up on the missing ones.
+const ONCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Output {
+    /// For humans, the columns may change between versions.
+    #[default]
+    Table,
+    /// One object per line (JSON Lines).
+    Json,
+    /// A header line with the column names, then one line per row.
+    Csv,
+}
+
+/// Column names and values, the columns of a kind of row are always the same and in the same order.
+pub type Row = Vec<(&'static str, Value)>;
+
+/// Prints rows as JSON or CSV, the CSV header only before the first row.
+pub struct Writer {
+    output: Output,
+    header: bool,
+}
+
+impl Writer {
+    pub fn new(output: Output) -> Self {
+        Self {
+            output,
+            header: false,
+        }
+    }
+
+    /// Exits when stdout is closed, e.g. piped into head.
+    pub fn write(&mut self, row: &Row) {
+        let mut stdout = io::stdout().lock();
+        for line in self.lines(row) {
+            if writeln!(stdout, "{}", line).is_err() {
+                process::exit(0);
+            }
+        }
+    }
+
+    /// The lines of row, with the CSV header before the first row.
+    fn lines(&mut self, row: &Row) -> Vec<String> {
+        let mut lines = vec![];
+
+        match self.output {
+            Output::Csv => {
+                if !self.header {
+                    let names: Vec<&str> = row.iter().map(|(name, _)| *name).collect();
+                    lines.push(names.join(","));
+                    self.header = true;
+                }
+                let values: Vec<String> = row.iter().map(|(_, value)| csv(value)).collect();
+                lines.push(values.join(","));
+            }
+            // Tables are formatted by the commands, as they were before there were other outputs.
+            Output::Json | Output::Table => {
+                // Written by hand, a serde_json map would sort the columns.
+                let fields: Vec<String> = row
+                    .iter()
+                    .map(|(name, value)| format!("{}:{}", Value::from(*name), value))
+                    .collect();
+                lines.push(format!("{{{}}}", fields.join(",")));
+            }
+        }
+
+        lines
+    }
+}
+
+/// A value as CSV field, quoted if needed, empty for null.
+fn csv(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// f32 as it is displayed, widening it to f64 would add digits that were never measured.
+fn float(value: f32) -> Value {
+    json!(value.to_string().parse::<f64>().unwrap_or_default())
+}
+
+/// Seconds since the epoch of the sample's timestamp, null if it has none.
+pub fn time(sample: &Sample) -> Value {
+    sample
+        .timestamp()
+        .map_or(Value::Null, |t| json!(t.get_time().to_duration().as_secs()))
+}
+
+/// Machine id from a key expression, the last chunk.
+pub fn machine_id(sample: &Sample) -> String {
+    let key_expr = sample.key_expr().as_str();
+    key_expr.rsplit('/').next().unwrap_or(key_expr).to_string()
+}
+
+/// A machine and whether it is online, everything but the id is null if we did not receive the machine.
+pub fn machine_row(id: &str, machine: Option<&pw::messages::Machine>, online: bool) -> Row {
+    let field = |value: fn(&pw::messages::Machine) -> Value| machine.map_or(Value::Null, value);
+
+    vec![
+        ("machine", json!(id)),
+        ("online", json!(online)),
+        ("hostname", field(|m| json!(m.hostname))),
+        ("booted", field(|m| json!(m.boottime))),
+        (
+            "kernel",
+            field(|m| json!(m.kernel.as_ref().map(|kernel| kernel.release.as_str()))),
+        ),
+        ("architecture", field(|m| json!(m.architecture))),
+        ("os", field(|m| json!(os_version(m)))),
+        ("cpu", field(|m| json!(m.cpu_model_name))),
+        ("cpu_cores", field(|m| json!(m.cpu_cores))),
+        ("cpu_threads", field(|m| json!(m.cpu_threads))),
+        ("memory_kb", field(|m| json!(m.physical_mem_total_kb))),
+        ("storage_bytes", field(|m| json!(m.storage_total_bytes))),
+        (
+            "model",
+            field(|m| {
+                let dmi = format!("{} {}", m.dmi_vendor, m.dmi_product);
+                match m.device_tree_model.as_str() {
+                    "" => json!(dmi.trim()),
+                    model => json!(model),
+                }
+            }),
+        ),
+        ("serial", field(|m| json!(m.serial))),
+        (
+            "protocol",
+            field(|m| json!(m.protocol.map_or(1, |protocol| protocol.version))),
+        ),
+        (
+            "service_version",
+            field(|m| json!(m.service.as_ref().map(|service| service.version.as_str()))),
+        ),
+        (
+            "service_git_sha",
+            field(|m| json!(m.service.as_ref().map(|service| service.git_sha.as_str()))),
+        ),
+    ]
+}
+
+/// One process of a process list, the rates are null in the first list the process is in.
+pub fn process_row(id: &str, time: &Value, process: &pw::messages::Process) -> Row {
+    let rate = |value: fn(&pw::messages::ProcessRates) -> f32| {
+        process
+            .rates
+            .as_ref()
+            .map_or(Value::Null, |rates| float(value(rates)))
+    };
+
+    vec![
+        ("machine", json!(id)),
+        ("time", time.clone()),
+        ("pid", json!(process.pid)),
+        ("ppid", json!(process.ppid)),
+        ("uid", json!(process.uid)),
+        ("name", json!(process.name)),
+        ("cpu_percent", float(process.cpu_percent)),
+        ("rss_kb", json!(process.rss_kb)),
+        ("read_bytes_per_second", rate(|r| r.read_bytes)),
+        ("write_bytes_per_second", rate(|r| r.write_bytes)),
+        (
+            "syscalls_per_second",
+            rate(|r| r.read_syscalls + r.write_syscalls),
+        ),
+        (
+            "context_switches_per_second",
+            rate(|r| r.voluntary_ctxt_switches + r.nonvoluntary_ctxt_switches),
+        ),
+        ("major_faults_per_second", rate(|r| r.major_faults)),
+        ("unit", json!(process.unit)),
+        (
+            "container",
+            json!(process.container.as_ref().map(stringify_container)),
+        ),
+        ("cmdline", json!(process.cmdline)),
+    ]
+}
+
+/// The scalar values of a metrics sample, pressure is null on kernels without PSI.
+pub fn metrics_row(id: &str, time: &Value, metrics: &pw::messages::Metrics) -> Row {
+    let load = metrics.load_average.unwrap_or_default();
+    let pressure = |pressure: &Option<pw::messages::Pressure>| {
+        pressure
+            .as_ref()
+            .map_or(Value::Null, |pressure| json!(pressure.some_avg10))
+    };
+
+    vec![
+        ("machine", json!(id)),
+        ("time", time.clone()),
+        ("uptime", json!(metrics.uptime)),
+        ("load1", json!(load.one)),
+        ("load5", json!(load.five)),
+        ("load15", json!(load.fifteen)),
+        ("cpu_pressure", pressure(&metrics.cpu_pressure)),
+        ("memory_pressure", pressure(&metrics.memory_pressure)),
+        ("io_pressure", pressure(&metrics.io_pressure)),
+        ("temperature", json!(max_temperature(metrics))),
+        ("throttled", json!(metrics.throttled)),
+    ]
+}
+
+/// With --once, the samples of one round, every machine once.
+pub struct Round {
+    /// Machines online when the round started, the round is complete when each of them sent a sample.
+    online: HashSet<String>,
+    seen: HashSet<String>,
+    deadline: tokio::time::Instant,
+}
+
+impl Round {
+    /// online are the ids of the online machines, only id of them if the command is for one machine.
+    pub fn new(online: HashSet<String>, id: Option<u64>) -> Self {
+        Self {
+            online: online
+                .into_iter()
+                .filter(|online| id.is_none_or(|id| *online == id.to_string()))
+                .collect(),
+            seen: HashSet::new(),
+            deadline: tokio::time::Instant::now() + ONCE_TIMEOUT,
+        }
+    }
+
+    fn complete(&self) -> bool {
+        self.online.is_subset(&self.seen)
+    }
+}
+
+/// The next sample of subscriber, None when it closed or, with a round, when every online machine sent a
+/// sample or ONCE_TIMEOUT expired. Further samples of a machine in the round are skipped.
+pub async fn next(
+    subscriber: &Subscriber<FifoChannelHandler<Sample>>,
+    round: &mut Option<Round>,
+) -> Option<Sample> {
+    let Some(round) = round else {
+        return subscriber.recv_async().await.ok();
+    };
+
+    while !round.complete() {
+        let Ok(sample) = tokio::time::timeout_at(round.deadline, subscriber.recv_async()).await
+        else {
+            let mut missing: Vec<&String> = round.online.difference(&round.seen).collect();
+            missing.sort();
+            for id in missing {
+                eprintln!("Machine {}: nothing received within {:?}", id, ONCE_TIMEOUT);
+            }
+            return None;
+        };
+        let sample = sample.ok()?;
+
+        if round.seen.insert(machine_id(&sample)) {
+            return Some(sample);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(row: &Row) -> Vec<&'static str> {
+        row.iter().map(|(name, _)| *name).collect()
+    }
+
+    #[test]
+    fn csv_fields() {
+        assert_eq!(csv(&Value::Null), "");
+        assert_eq!(csv(&json!(1.5)), "1.5");
+        assert_eq!(csv(&json!(true)), "true");
+        assert_eq!(csv(&json!("sshd")), "sshd");
+        assert_eq!(csv(&json!("a,b")), "\"a,b\"");
+        assert_eq!(csv(&json!("say \"hi\"")), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv(&json!("two\nlines")), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn lines() {
+        let row: Row = vec![("machine", json!("42")), ("cmdline", json!("sleep 1,2"))];
+
+        let mut writer = Writer::new(Output::Csv);
+        assert_eq!(
+            writer.lines(&row),
+            vec!["machine,cmdline", "42,\"sleep 1,2\""]
+        );
+        assert_eq!(writer.lines(&row), vec!["42,\"sleep 1,2\""]);
+
+        // In the order of the row, not sorted.
+        let mut writer = Writer::new(Output::Json);
+        assert_eq!(
+            writer.lines(&row),
+            vec![r#"{"machine":"42","cmdline":"sleep 1,2"}"#]
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let machine = pw::messages::Machine {
+            hostname: "pi".to_string(),
+            ..Default::default()
+        };
+        let unknown = machine_row("42", None, false);
+        let known = machine_row("42", Some(&machine), true);
+        assert_eq!(names(&unknown), names(&known));
+        assert_eq!(&names(&known)[..3], ["machine", "online", "hostname"]);
+        assert_eq!(unknown[2].1, Value::Null);
+
+        let first = pw::messages::Process {
+            pid: 1,
+            ..Default::default()
+        };
+        let later = pw::messages::Process {
+            rates: Some(Default::default()),
+            ..first.clone()
+        };
+        let first = process_row("42", &json!(1), &first);
+        assert_eq!(names(&first), names(&process_row("42", &json!(1), &later)));
+        assert_eq!(first[8], ("read_bytes_per_second", Value::Null));
+        assert_eq!(names(&first).last(), Some(&"cmdline"));
+
+        let metrics = metrics_row("42", &Value::Null, &Default::default());
+        assert_eq!(&names(&metrics)[..3], ["machine", "time", "uptime"]);
+        assert_eq!(metrics[6], ("cpu_pressure", Value::Null));
+    }
+
+    #[test]
+    fn round() {
+        let online: HashSet<String> = ["1", "2"].map(String::from).into();
+
+        let mut round = Round::new(online.clone(), None);
+        assert!(!round.complete());
+        round.seen.insert("1".to_string());
+        assert!(!round.complete());
+        round.seen.insert("3".to_string());
+        round.seen.insert("2".to_string());
+        assert!(round.complete());
+
+        // Only the machine asked for.
+        assert_eq!(Round::new(online.clone(), Some(2)).online.len(), 1);
+        assert!(Round::new(online, Some(3)).complete());
+    }
+}
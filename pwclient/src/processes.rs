@@ -0,0 +1,185 @@
+use crate::machines;
+use crate::output::{self, machine_id, process_row, Output, Round, Writer};
+use crate::protocol;
+use clap::ValueEnum;
+use common::{
+    pw, stringify_container, stringify_duration, stringify_overhead, stringify_process,
+    BASE_KEY_EXPR, GROUP_KEY_EXPR, PROCESSES_KEY_EXPR,
+};
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum GroupBy {
+    /// One list sorted by pid.
+    None,
+    /// Container runtime and id, "host" for processes outside containers.
+    Container,
+    /// systemd service or scope.
+    Unit,
+}
+
+impl GroupBy {
+    fn key(&self, process: &pw::messages::Process) -> String {
+        match self {
+            GroupBy::None => String::new(),
+            GroupBy::Container => process
+                .container
+                .as_ref()
+                .map_or("host".to_string(), stringify_container),
+            GroupBy::Unit if process.unit.is_empty() => "-".to_string(),
+            GroupBy::Unit => process.unit.clone(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum SortBy {
+    Pid,
+    Cpu,
+    Rss,
+    /// Bytes read from storage.
+    Read,
+    /// Bytes written to storage.
+    Write,
+    /// Read and write system calls.
+    Syscalls,
+    /// Voluntary and involuntary context switches.
+    Ctxsw,
+    /// Major faults, the ones needing I/O.
+    Faults,
+}
+
+impl SortBy {
+    /// Largest first, except for the pid.
+    fn sort(&self, processes: &mut [&pw::messages::Process]) {
+        let rates = |process: &pw::messages::Process| process.rates.unwrap_or_default();
+        let key = |process: &pw::messages::Process| -> f32 {
+            let rates = rates(process);
+            match self {
+                SortBy::Pid => -(process.pid as f32),
+                SortBy::Cpu => process.cpu_percent,
+                SortBy::Rss => process.rss_kb as f32,
+                SortBy::Read => rates.read_bytes,
+                SortBy::Write => rates.write_bytes,
+                SortBy::Syscalls => rates.read_syscalls + rates.write_syscalls,
+                SortBy::Ctxsw => rates.voluntary_ctxt_switches + rates.nonvoluntary_ctxt_switches,
+                SortBy::Faults => rates.major_faults,
+            }
+        };
+
+        processes.sort_by(|a, b| key(b).total_cmp(&key(a)));
+    }
+}
+
+/// Print the process lists of machine id (all machines if None) as they arrive, with once only the first
+/// list of each machine. Rows are not grouped, they have the unit and container.
+pub async fn follow(
+    session: &zenoh::Session,
+    id: Option<u64>,
+    group_by: GroupBy,
+    sort: SortBy,
+    output: Output,
+    once: bool,
+) {
+    // The machines online now, a round ends when each of them sent a sample.
+    let mut round = if once {
+        Some(Round::new(machines::online(session).await, id))
+    } else {
+        None
+    };
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, PROCESSES_KEY_EXPR, id
+    );
+
+    debug!("Declaring Processes Subscriber on '{key_expr}'...");
+
+    let subscriber = session.declare_subscriber(&key_expr).await.unwrap();
+
+    if !once {
+        info!("Press CTRL-C to quit...");
+    }
+
+    let mut writer = Writer::new(output);
+    while let Some(sample) = output::next(&subscriber, &mut round).await {
+        match protocol::decode::<pw::messages::Processes>(&sample) {
+            Ok(processes) if output != Output::Table => {
+                let (id, time) = (machine_id(&sample), output::time(&sample));
+                let mut processes: Vec<&pw::messages::Process> =
+                    processes.processes.iter().collect();
+                sort.sort(&mut processes);
+                for process in processes {
+                    writer.write(&process_row(&id, &time, process));
+                }
+            }
+            Ok(processes) => {
+                let when = sample
+                    .timestamp()
+                    .map(|t| stringify_duration(t.get_time().as_secs().into()))
+                    .unwrap_or_default();
+
+                println!("{} {}", sample.key_expr().as_str(), when);
+                for line in stringify_grouped(&processes, group_by, sort) {
+                    println!("  {}", line);
+                }
+            }
+            Err(err) => {
+                warn!("Could not parse message (ERROR: '{}')", err);
+            }
+        }
+    }
+}
+
+/// The overhead of the service and the processes as a table, in the order the service sent them.
+pub fn stringify(processes: &pw::messages::Processes) -> Vec<String> {
+    stringify_grouped(processes, GroupBy::None, SortBy::Pid)
+}
+
+fn stringify_grouped(
+    processes: &pw::messages::Processes,
+    group_by: GroupBy,
+    sort: SortBy,
+) -> Vec<String> {
+    let mut lines: Vec<String> = stringify_overhead(processes).into_iter().collect();
+
+    let mut groups: BTreeMap<String, Vec<&pw::messages::Process>> = BTreeMap::new();
+    for process in &processes.processes {
+        groups
+            .entry(group_by.key(process))
+            .or_default()
+            .push(process);
+    }
+
+    for (key, mut processes) in groups {
+        sort.sort(&mut processes);
+
+        let indent = if key.is_empty() {
+            ""
+        } else {
+            lines.push(format!("{} [{}]", key, processes.len()));
+            "  "
+        };
+
+        lines.push(format!(
+            "{}{:>7} {:>7} {:>5} {:>5} {:>9} {:>9} {:>9} {:>7} {:>7} {:>7} COMMAND",
+            indent,
+            "PID",
+            "PPID",
+            "UID",
+            "CPU%",
+            "RSS",
+            "READ/s",
+            "WRITE/s",
+            "SYSC/s",
+            "CTXSW/s",
+            "MAJFL/s"
+        ));
+        for process in processes {
+            lines.push(format!("{}{}", indent, stringify_process(process)));
+        }
+    }
+
+    lines
+}
@@ -0,0 +1,45 @@
+use common::{compatibility, deserialize, peer_protocol, stringify_compatibility, Compatibility};
+use prost::{DecodeError, Message};
+use std::{collections::BTreeSet, sync::Mutex};
+use zenoh::sample::Sample;
+
+/// Machines and protocol versions we already warned about, each is only told once.
+static WARNED: Mutex<BTreeSet<(String, u32)>> = Mutex::new(BTreeSet::new());
+
+/// The protocol of the service that sent sample, from its attachment.
+pub fn peer(sample: &Sample) -> common::pw::messages::Protocol {
+    let attachment = sample.attachment().map(|attachment| attachment.to_bytes());
+    peer_protocol(attachment.as_deref())
+}
+
+/// Whether sample can be read, warns once per machine if its service speaks another protocol.
+pub fn check(sample: &Sample) -> bool {
+    let peer = peer(sample);
+
+    if let Some(what) = stringify_compatibility(&peer) {
+        // pw/<group>/<kind>/<id>, commands and history have more chunks after the id.
+        let id = sample
+            .key_expr()
+            .as_str()
+            .split('/')
+            .nth(3)
+            .unwrap_or_default()
+            .to_string();
+        let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+        if warned.insert((id.clone(), peer.version)) {
+            eprintln!("Machine {}: {}", id, what);
+        }
+    }
+
+    compatibility(&peer) != Compatibility::Incompatible
+}
+
+/// The message in sample, an error without decoding it if its service speaks an incompatible protocol.
+pub fn decode<M: Message + Default>(sample: &Sample) -> Result<M, DecodeError> {
+    if !check(sample) {
+        #[allow(deprecated)]
+        return Err(DecodeError::new("incompatible protocol"));
+    }
+
+    deserialize(&sample.payload().to_bytes())
+}
@@ -0,0 +1,235 @@
+use log::warn;
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zenoh::sample::{Sample, SampleKind};
+
+/// First bytes of a recording, a file without them is not replayed.
+const MAGIC: &[u8; 4] = b"PWR2";
+
+/// Recordings from before the attachments were recorded.
+const MAGIC_V1: &[u8; 4] = b"PWR1";
+
+/// A received sample as it was recorded.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// The zenoh timestamp of the sample, the time we received it if it had none.
+    pub time_usec: u64,
+    pub key_expr: String,
+    pub kind: SampleKind,
+    pub payload: Vec<u8>,
+    /// The protocol of the service, empty if the sample had no attachment or was recorded without them.
+    pub attachment: Vec<u8>,
+}
+
+impl Record {
+    fn new(sample: &Sample) -> Self {
+        let time_usec = sample
+            .timestamp()
+            .map(|timestamp| timestamp.get_time().to_duration())
+            .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_micros() as u64)
+            .unwrap_or_default();
+
+        Self {
+            time_usec,
+            key_expr: sample.key_expr().as_str().to_string(),
+            kind: sample.kind(),
+            payload: sample.payload().to_bytes().to_vec(),
+            attachment: sample
+                .attachment()
+                .map(|attachment| attachment.to_bytes().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Writes every sample it is given to a file, one write per sample so a killed client leaves a usable file.
+/// Cloned for each subscriber, they all write to the same file.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl Recorder {
+    /// Create (or truncate) the recording at path.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(Some(file))),
+        })
+    }
+
+    /// Append sample, recording stops at the first error.
+    pub fn record(&self, sample: &Sample) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+
+        if let Some(writer) = file.as_mut() {
+            if let Err(err) = writer.write_all(&encode_record(&Record::new(sample))) {
+                warn!("Could not write recording, recording stopped ({})", err);
+                *file = None;
+            }
+        }
+    }
+}
+
+/// The records in the recording at path, in the order they were received.
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    let data = fs::read(path)?;
+
+    if let Some(records) = data.strip_prefix(MAGIC) {
+        Ok(decode_records(records, true))
+    } else if let Some(records) = data.strip_prefix(MAGIC_V1) {
+        Ok(decode_records(records, false))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a recording", path.display()),
+        ))
+    }
+}
+
+/// time_usec (u64), kind (u8, 0 put, 1 delete), key expression length (u16), key expression, payload length
+/// (u32), payload, attachment length (u16), attachment, little endian.
+fn encode_record(record: &Record) -> Vec<u8> {
+    let mut data = Vec::with_capacity(
+        17 + record.key_expr.len() + record.payload.len() + record.attachment.len(),
+    );
+
+    data.extend(record.time_usec.to_le_bytes());
+    data.push(match record.kind {
+        SampleKind::Put => 0,
+        SampleKind::Delete => 1,
+    });
+    data.extend((record.key_expr.len() as u16).to_le_bytes());
+    data.extend(record.key_expr.as_bytes());
+    data.extend((record.payload.len() as u32).to_le_bytes());
+    data.extend(&record.payload);
+    data.extend((record.attachment.len() as u16).to_le_bytes());
+    data.extend(&record.attachment);
+
+    data
+}
+
+/// Records until the end of data, a truncated record at the end (pwclient killed while writing) is dropped.
+/// Records of PWR1 recordings have no attachment.
+fn decode_records(mut data: &[u8], attachments: bool) -> Vec<Record> {
+    let mut records = vec![];
+
+    fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = data.split_at_checked(n)?;
+        *data = rest;
+        Some(taken)
+    }
+
+    while let Some(time_usec) = take(&mut data, 8) {
+        let Some(kind) = take(&mut data, 1) else {
+            break;
+        };
+        let Some(key_expr) = take(&mut data, 2)
+            .and_then(|len| take(&mut data, u16::from_le_bytes(len.try_into().ok()?) as usize))
+        else {
+            break;
+        };
+        let Some(payload) = take(&mut data, 4)
+            .and_then(|len| take(&mut data, u32::from_le_bytes(len.try_into().ok()?) as usize))
+        else {
+            break;
+        };
+        let attachment = if attachments {
+            let Some(attachment) = take(&mut data, 2)
+                .and_then(|len| take(&mut data, u16::from_le_bytes(len.try_into().ok()?) as usize))
+            else {
+                break;
+            };
+            attachment
+        } else {
+            &[]
+        };
+
+        records.push(Record {
+            time_usec: u64::from_le_bytes(time_usec.try_into().unwrap_or_default()),
+            key_expr: String::from_utf8_lossy(key_expr).into_owned(),
+            kind: if kind[0] == 1 {
+                SampleKind::Delete
+            } else {
+                SampleKind::Put
+            },
+            payload: payload.to_vec(),
+            attachment: attachment.to_vec(),
+        });
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time_usec: u64, key_expr: &str, kind: SampleKind, attachment: &[u8]) -> Record {
+        Record {
+            time_usec,
+            key_expr: key_expr.to_string(),
+            kind,
+            payload: key_expr.as_bytes().to_vec(),
+            attachment: attachment.to_vec(),
+        }
+    }
+
+    #[test]
+    fn records() {
+        let records = vec![
+            record(1, "pw/1/s/42", SampleKind::Put, &[8, 3, 16, 1]),
+            record(2, "pw/1/l/42", SampleKind::Delete, &[]),
+        ];
+        let mut data: Vec<u8> = records.iter().flat_map(encode_record).collect();
+        assert_eq!(decode_records(&data, true), records);
+
+        // pwclient killed while writing the third.
+        let third = encode_record(&record(3, "pw/1/s/7", SampleKind::Put, &[1]));
+        data.extend(&third[..third.len() - 1]);
+        assert_eq!(decode_records(&data, true), records);
+    }
+
+    #[test]
+    fn read_versions() {
+        let path = std::env::temp_dir().join(format!("pwclient-recording-{}", std::process::id()));
+
+        // PWR1 records end with the payload.
+        let mut data = MAGIC_V1.to_vec();
+        let v1 = encode_record(&record(1, "pw/1/s/42", SampleKind::Put, &[]));
+        data.extend(&v1[..v1.len() - 2]);
+        fs::write(&path, &data).unwrap();
+        assert_eq!(
+            read(&path).unwrap(),
+            vec![record(1, "pw/1/s/42", SampleKind::Put, &[])]
+        );
+
+        let mut data = MAGIC.to_vec();
+        data.extend(encode_record(&record(
+            2,
+            "pw/1/s/42",
+            SampleKind::Put,
+            &[8, 3],
+        )));
+        fs::write(&path, &data).unwrap();
+        assert_eq!(
+            read(&path).unwrap(),
+            vec![record(2, "pw/1/s/42", SampleKind::Put, &[8, 3])]
+        );
+
+        fs::write(&path, b"PWH1").unwrap();
+        assert!(read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
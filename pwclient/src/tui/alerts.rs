@@ -0,0 +1,681 @@
+use super::Update;
+use common::{max_temperature, memory_available_percent, pw};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Number of firing and resolved transitions kept for the alerts pane.
+const RECENT_LENGTH: usize = 50;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Metric {
+    /// Percent of the memory available.
+    MemoryAvailable,
+    /// Hottest thermal zone in °C.
+    Temperature,
+    /// Load average over 1 minute.
+    Load,
+    /// Pressure stall percentages ("some", 10 seconds).
+    CpuPressure,
+    MemoryPressure,
+    IoPressure,
+    /// Percent used of the fullest writable filesystem.
+    FilesystemUsed,
+}
+
+impl Metric {
+    fn value(&self, metrics: &pw::messages::Metrics) -> Option<f64> {
+        let pressure = |pressure: &Option<pw::messages::Pressure>| {
+            pressure.as_ref().map(|pressure| pressure.some_avg10)
+        };
+
+        match self {
+            Metric::MemoryAvailable => memory_available_percent(metrics),
+            Metric::Temperature => max_temperature(metrics),
+            Metric::Load => metrics.load_average.as_ref().map(|load| load.one),
+            Metric::CpuPressure => pressure(&metrics.cpu_pressure),
+            Metric::MemoryPressure => pressure(&metrics.memory_pressure),
+            Metric::IoPressure => pressure(&metrics.io_pressure),
+            Metric::FilesystemUsed => metrics
+                .filesystems
+                .iter()
+                .filter(|filesystem| !filesystem.read_only && filesystem.total_bytes > 0)
+                .map(|filesystem| {
+                    filesystem.total_bytes.saturating_sub(filesystem.free_bytes) as f64 * 100.0
+                        / filesystem.total_bytes as f64
+                })
+                .reduce(f64::max),
+        }
+    }
+
+    fn stringify(&self, value: f64) -> String {
+        match self {
+            Metric::Temperature => format!("{:.1}°C", value),
+            Metric::Load => format!("{:.2}", value),
+            _ => format!("{:.1}%", value),
+        }
+    }
+}
+
+/// A rule as it is written in the rules file, exactly one of metric (with below or above), process or offline.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    metric: Option<Metric>,
+    below: Option<f64>,
+    above: Option<f64>,
+    /// Fires if no process of the machine has this name.
+    process: Option<String>,
+    #[serde(default)]
+    offline: bool,
+    /// How long the condition must hold before the rule fires, e.g. "90s", "2m" or "1h".
+    #[serde(rename = "for")]
+    hold: Option<String>,
+    /// Run with sh -c when the rule fires or resolves.
+    command: Option<String>,
+    /// Append a line to this file when the rule fires or resolves.
+    file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug)]
+enum Condition {
+    Below(Metric, f64),
+    Above(Metric, f64),
+    NotRunning(String),
+    Offline,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    condition: Condition,
+    hold: Duration,
+    command: Option<String>,
+    file: Option<PathBuf>,
+}
+
+impl TryFrom<RuleConfig> for Rule {
+    type Error = String;
+
+    fn try_from(config: RuleConfig) -> Result<Self, Self::Error> {
+        let condition = match (&config.metric, config.process, config.offline) {
+            (Some(metric), None, false) => match (config.below, config.above) {
+                (Some(below), None) => Condition::Below(*metric, below),
+                (None, Some(above)) => Condition::Above(*metric, above),
+                _ => {
+                    return Err(format!(
+                        "rule '{}' needs either below or above",
+                        config.name
+                    ))
+                }
+            },
+            (None, Some(process), false) if !process.is_empty() => Condition::NotRunning(process),
+            (None, None, true) => Condition::Offline,
+            _ => {
+                return Err(format!(
+                    "rule '{}' needs exactly one of metric, process or offline",
+                    config.name
+                ))
+            }
+        };
+
+        if config.metric.is_none() && (config.below.is_some() || config.above.is_some()) {
+            return Err(format!(
+                "rule '{}' has below or above without a metric",
+                config.name
+            ));
+        }
+
+        let hold = match &config.hold {
+            Some(hold) => parse_duration(hold).ok_or(format!(
+                "rule '{}' has an invalid for '{}', e.g. \"90s\", \"2m\" or \"1h\"",
+                config.name, hold
+            ))?,
+            None => Duration::ZERO,
+        };
+
+        Ok(Self {
+            name: config.name,
+            condition,
+            hold,
+            command: config.command,
+            file: config.file,
+        })
+    }
+}
+
+/// "90s", "2m", "1h", "1d" or seconds without a unit.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, seconds) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 3600),
+        (i, 'd') => (&value[..i], 86400),
+        _ => (value, 1),
+    };
+
+    let seconds = number.trim().parse::<u64>().ok()?.checked_mul(seconds)?;
+
+    Some(Duration::from_secs(seconds))
+}
+
+/// A rule that holds for a machine, pending until it held for the rule's duration.
+struct Active {
+    since: SystemTime,
+    firing: bool,
+    value: String,
+}
+
+/// A rule that started firing or resolved.
+pub struct Transition {
+    pub rule: String,
+    pub machine: String,
+    pub firing: bool,
+    pub value: String,
+    pub time: SystemTime,
+}
+
+/// Evaluates the rules against what we receive and keeps which are firing. Times are those of the samples,
+/// so a replay holds and fires rules as they did when it was recorded.
+pub struct Alerts {
+    rules: Vec<Rule>,
+    /// Keyed by rule index and machine id.
+    active: BTreeMap<(usize, String), Active>,
+    hostnames: HashMap<String, String>,
+    /// Newest last.
+    pub recent: VecDeque<Transition>,
+}
+
+impl Alerts {
+    /// Read the rules from a TOML (`.toml`) or JSON file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {} ({})", path.display(), e))?;
+
+        let config: RulesConfig = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        }
+        .map_err(|e| format!("Invalid rules {}: {}", path.display(), e))?;
+
+        let rules = config
+            .rules
+            .into_iter()
+            .map(Rule::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid rules {}: {}", path.display(), e))?;
+
+        Ok(Self {
+            rules,
+            active: BTreeMap::new(),
+            hostnames: HashMap::new(),
+            recent: VecDeque::new(),
+        })
+    }
+
+    /// Whether a rule needs the process lists, the view only subscribes to them if so.
+    pub fn needs_processes(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| matches!(rule.condition, Condition::NotRunning(_)))
+    }
+
+    /// Evaluate the rules that update, received at now, tells something about.
+    pub fn update(&mut self, update: &Update, now: SystemTime) {
+        let id = match update {
+            Update::Machine(id, machine) => {
+                self.hostnames.insert(id.clone(), machine.hostname.clone());
+                return;
+            }
+            Update::Metrics(id, _) | Update::Processes(id, _) => id,
+            Update::Online(..) | Update::Event(..) | Update::Incompatible(..) => return,
+        };
+
+        for index in 0..self.rules.len() {
+            if let Some((holds, value)) = self.rules[index].condition.evaluate(update) {
+                self.observe(index, id, holds, value, now);
+            }
+        }
+    }
+
+    /// Evaluate the offline rules for machine id, called with what the view shows for every machine.
+    pub fn online(&mut self, id: &str, online: bool, now: SystemTime) {
+        for index in 0..self.rules.len() {
+            if matches!(self.rules[index].condition, Condition::Offline) {
+                let value = if online { "online" } else { "offline" };
+                self.observe(index, id, !online, value.to_string(), now);
+            }
+        }
+    }
+
+    fn observe(&mut self, index: usize, id: &str, holds: bool, value: String, now: SystemTime) {
+        let key = (index, id.to_string());
+
+        if holds {
+            self.active
+                .entry(key)
+                .and_modify(|active| active.value.clone_from(&value))
+                .or_insert(Active {
+                    since: now,
+                    firing: false,
+                    value,
+                });
+        } else if let Some(active) = self.active.remove(&key) {
+            if active.firing {
+                self.transition(index, id, false, value, now);
+            }
+        }
+    }
+
+    /// Fire the rules that held long enough at now.
+    pub fn tick(&mut self, now: SystemTime) {
+        let due: Vec<(usize, String, String)> = self
+            .active
+            .iter_mut()
+            .filter(|((index, _), active)| {
+                !active.firing
+                    && now.duration_since(active.since).unwrap_or_default()
+                        >= self.rules[*index].hold
+            })
+            .map(|((index, id), active)| {
+                active.firing = true;
+                (*index, id.clone(), active.value.clone())
+            })
+            .collect();
+
+        for (index, id, value) in due {
+            self.transition(index, &id, true, value, now);
+        }
+    }
+
+    fn transition(
+        &mut self,
+        index: usize,
+        id: &str,
+        firing: bool,
+        value: String,
+        time: SystemTime,
+    ) {
+        let rule = &self.rules[index];
+        let transition = Transition {
+            rule: rule.name.clone(),
+            machine: self.name(id),
+            firing,
+            value,
+            time,
+        };
+
+        if let Some(command) = &rule.command {
+            run_command(command, id, &transition);
+        }
+        if let Some(file) = &rule.file {
+            if let Err(err) = append(file, id, &transition) {
+                warn!("Could not write alert to {} ({})", file.display(), err);
+            }
+        }
+
+        self.recent.push_back(transition);
+        if self.recent.len() > RECENT_LENGTH {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Hostname of machine id, the id if we do not know it.
+    fn name(&self, id: &str) -> String {
+        match self.hostnames.get(id) {
+            Some(hostname) if !hostname.is_empty() => hostname.clone(),
+            _ => id.to_string(),
+        }
+    }
+
+    /// Rule names and values of the rules firing for machine id, and since when.
+    pub fn firing(&self, id: &str) -> Vec<(&str, &str, SystemTime)> {
+        self.active
+            .iter()
+            .filter(|((_, machine), active)| machine == id && active.firing)
+            .map(|((index, _), active)| {
+                (
+                    self.rules[*index].name.as_str(),
+                    active.value.as_str(),
+                    active.since,
+                )
+            })
+            .collect()
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds and the value to show, None if update says nothing about it.
+    fn evaluate(&self, update: &Update) -> Option<(bool, String)> {
+        match (self, update) {
+            (Condition::Below(metric, threshold), Update::Metrics(_, metrics)) => {
+                let value = metric.value(metrics)?;
+                Some((value < *threshold, metric.stringify(value)))
+            }
+            (Condition::Above(metric, threshold), Update::Metrics(_, metrics)) => {
+                let value = metric.value(metrics)?;
+                Some((value > *threshold, metric.stringify(value)))
+            }
+            (Condition::NotRunning(name), Update::Processes(_, processes)) => {
+                let running = processes
+                    .processes
+                    .iter()
+                    .any(|process| process.name == *name || program(&process.cmdline) == name);
+                // Cut by filters or top, a process missing from the list may still run.
+                let partial = (processes.processes.len() as u32) < processes.scanned;
+                if !running && partial {
+                    return None;
+                }
+                let value = if running { "running" } else { "not running" };
+                Some((!running, value.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// File name of the program in a command line, the process name is cut at 15 characters.
+fn program(cmdline: &str) -> &str {
+    let program = cmdline.split(' ').next().unwrap_or_default();
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+fn state(transition: &Transition) -> &'static str {
+    if transition.firing {
+        "firing"
+    } else {
+        "resolved"
+    }
+}
+
+/// Run command with the transition in the environment, without waiting for it.
+fn run_command(command: &str, id: &str, transition: &Transition) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PW_RULE", &transition.rule)
+        .env("PW_MACHINE", id)
+        .env("PW_HOSTNAME", &transition.machine)
+        .env("PW_STATE", state(transition))
+        .env("PW_VALUE", &transition.value)
+        .spawn();
+
+    match child {
+        // Reaped in the background so it does not stay a zombie.
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => warn!("Could not run '{}' ({})", command, err),
+    }
+}
+
+/// "<seconds since the epoch> <state> <rule> <machine id> <hostname> <value>", tab separated.
+fn append(path: &Path, id: &str, transition: &Transition) -> std::io::Result<()> {
+    let time = transition
+        .time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        time,
+        state(transition),
+        transition.rule,
+        id,
+        transition.machine,
+        transition.value
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str) -> Result<Vec<Rule>, String> {
+        let config: RulesConfig = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.rules.into_iter().map(Rule::try_from).collect()
+    }
+
+    fn alerts(content: &str) -> Alerts {
+        Alerts {
+            rules: rules(content).unwrap(),
+            active: BTreeMap::new(),
+            hostnames: HashMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn memory(available_kb: u64) -> Update {
+        Update::Metrics(
+            "42".to_string(),
+            Box::new(pw::messages::Metrics {
+                memory: Some(pw::messages::Memory {
+                    total_kb: 1000,
+                    available_kb,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn processes(names: &[&str], scanned: u32) -> Update {
+        Update::Processes(
+            "42".to_string(),
+            Box::new(pw::messages::Processes {
+                processes: names
+                    .iter()
+                    .map(|name| pw::messages::Process {
+                        name: name.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                scanned,
+                ..Default::default()
+            }),
+        )
+    }
+
+    /// Rule, firing and value of the transitions so far.
+    fn transitions(alerts: &Alerts) -> Vec<(&str, bool, &str)> {
+        alerts
+            .recent
+            .iter()
+            .map(|t| (t.rule.as_str(), t.firing, t.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 2m "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15)));
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-1m"), None);
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("18446744073709551615d"), None);
+    }
+
+    #[test]
+    fn validation() {
+        let parsed = rules(
+            r#"
+            [[rules]]
+            name = "low"
+            metric = "memory-available"
+            below = 10
+            for = "2m"
+
+            [[rules]]
+            name = "sshd"
+            process = "sshd"
+
+            [[rules]]
+            name = "offline"
+            offline = true
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parsed[0].condition,
+            Condition::Below(Metric::MemoryAvailable, 10.0)
+        ));
+        assert_eq!(parsed[0].hold, Duration::from_secs(120));
+        assert!(matches!(&parsed[1].condition, Condition::NotRunning(name) if name == "sshd"));
+        assert_eq!(parsed[1].hold, Duration::ZERO);
+        assert!(matches!(parsed[2].condition, Condition::Offline));
+
+        let error = |rule: &str| rules(&format!("[[rules]]\nname = \"r\"\n{}", rule)).unwrap_err();
+        assert!(error("metric = \"load\"").contains("below or above"));
+        assert!(error("metric = \"load\"\nbelow = 1\nabove = 2").contains("below or above"));
+        assert!(error("process = \"sshd\"\noffline = true").contains("exactly one"));
+        assert!(error("process = \"\"").contains("exactly one"));
+        assert!(error("").contains("exactly one"));
+        assert!(error("offline = true\nabove = 2").contains("without a metric"));
+        assert!(error("offline = true\nfor = \"soon\"").contains("invalid for 'soon'"));
+        assert!(error("metric = \"disk\"\nabove = 2").contains("unknown variant"));
+        assert!(error("offline = true\nwhen = 1").contains("unknown field"));
+    }
+
+    #[test]
+    fn pending_firing_resolved() {
+        let mut alerts = alerts(
+            r#"
+            [[rules]]
+            name = "low"
+            metric = "memory-available"
+            below = 10
+            for = "60s"
+            "#,
+        );
+
+        // Pending until it held for 60 seconds of sample time.
+        alerts.update(&memory(50), at(1000));
+        alerts.tick(at(1000));
+        assert_eq!(alerts.active.len(), 1);
+        assert!(alerts.firing("42").is_empty());
+
+        alerts.update(&memory(80), at(1030));
+        alerts.tick(at(1059));
+        assert!(alerts.firing("42").is_empty());
+
+        alerts.tick(at(1060));
+        assert_eq!(alerts.firing("42"), vec![("low", "8.0%", at(1000))]);
+        assert_eq!(transitions(&alerts), vec![("low", true, "8.0%")]);
+        assert_eq!(alerts.recent[0].time, at(1060));
+
+        // Fires once.
+        alerts.tick(at(2000));
+        assert_eq!(alerts.recent.len(), 1);
+
+        alerts.update(&memory(500), at(2010));
+        assert!(alerts.firing("42").is_empty());
+        assert_eq!(
+            transitions(&alerts),
+            vec![("low", true, "8.0%"), ("low", false, "50.0%")]
+        );
+        assert_eq!(alerts.recent[1].time, at(2010));
+
+        // Resolved before it held long enough, nothing fires.
+        alerts.update(&memory(50), at(3000));
+        alerts.update(&memory(500), at(3030));
+        alerts.tick(at(4000));
+        assert_eq!(alerts.recent.len(), 2);
+    }
+
+    #[test]
+    fn offline() {
+        let mut alerts = alerts(
+            r#"
+            [[rules]]
+            name = "offline"
+            offline = true
+            for = "5m"
+            "#,
+        );
+        alerts.update(
+            &Update::Machine(
+                "42".to_string(),
+                Box::new(pw::messages::Machine {
+                    hostname: "pi".to_string(),
+                    ..Default::default()
+                }),
+            ),
+            at(0),
+        );
+
+        alerts.online("42", false, at(100));
+        alerts.tick(at(399));
+        assert!(alerts.recent.is_empty());
+        alerts.online("42", false, at(400));
+        alerts.tick(at(400));
+        assert_eq!(transitions(&alerts), vec![("offline", true, "offline")]);
+        assert_eq!(alerts.recent[0].machine, "pi");
+
+        alerts.online("42", true, at(500));
+        assert_eq!(
+            transitions(&alerts),
+            vec![("offline", true, "offline"), ("offline", false, "online")]
+        );
+    }
+
+    #[test]
+    fn not_running() {
+        let mut alerts = alerts(
+            r#"
+            [[rules]]
+            name = "sshd"
+            process = "sshd"
+            "#,
+        );
+        assert!(alerts.needs_processes());
+
+        alerts.update(&processes(&["init", "sshd"], 2), at(0));
+        alerts.tick(at(0));
+        assert!(alerts.recent.is_empty());
+
+        // Cut by filters or top, sshd may still run.
+        alerts.update(&processes(&["init"], 200), at(1));
+        alerts.tick(at(1));
+        assert!(alerts.recent.is_empty());
+
+        alerts.update(&processes(&["init"], 1), at(2));
+        alerts.tick(at(2));
+        assert_eq!(transitions(&alerts), vec![("sshd", true, "not running")]);
+
+        // Found in a cut list, it runs.
+        alerts.update(&processes(&["sshd"], 200), at(3));
+        assert_eq!(
+            transitions(&alerts),
+            vec![("sshd", true, "not running"), ("sshd", false, "running")]
+        );
+    }
+}
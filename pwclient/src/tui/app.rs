@@ -0,0 +1,143 @@
+use super::{alerts::Alerts, Update};
+use common::pw;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::SystemTime,
+};
+
+/// Number of metrics samples kept per machine for the sparklines.
+const HISTORY_LENGTH: usize = 300;
+
+/// Number of kernel events kept per machine.
+const EVENTS_LENGTH: usize = 20;
+
+#[derive(Default)]
+pub struct MachineState {
+    pub machine: Option<pw::messages::Machine>,
+    pub online: bool,
+    pub history: VecDeque<pw::messages::Metrics>,
+    pub events: VecDeque<pw::messages::KernelEvent>,
+    /// Events received since the user last acknowledged them.
+    pub alerts: usize,
+    /// Set if its service speaks a protocol we can not read.
+    pub incompatible: Option<pw::messages::Protocol>,
+}
+
+impl MachineState {
+    pub fn latest(&self) -> Option<&pw::messages::Metrics> {
+        self.history.back()
+    }
+
+    pub fn name(&self, id: &str) -> String {
+        match &self.machine {
+            Some(machine) if !machine.hostname.is_empty() => machine.hostname.clone(),
+            _ => id.to_string(),
+        }
+    }
+}
+
+/// State of the TUI, machines are keyed by id (last chunk of their key expressions).
+#[derive(Default)]
+pub struct App {
+    pub machines: BTreeMap<String, MachineState>,
+    pub selected: usize,
+    pub quit: bool,
+    /// Shown in the machine list border, whether we record or replay.
+    pub title: Option<String>,
+    pub alerts: Option<Alerts>,
+    /// Replaying a recording, the time stands still between records and after the last.
+    pub replay: bool,
+    /// Time of the newest sample.
+    pub time: Option<SystemTime>,
+}
+
+impl App {
+    /// What happened at time, the time of the sample that carried update.
+    pub fn update(&mut self, time: SystemTime, update: Update) {
+        if let Some(alerts) = &mut self.alerts {
+            alerts.update(&update, time);
+        }
+        self.time = self.time.max(Some(time));
+
+        match update {
+            Update::Machine(id, machine) => {
+                self.machines.entry(id).or_default().machine = Some(*machine);
+            }
+            Update::Online(id, online) => {
+                self.machines.entry(id).or_default().online = online;
+            }
+            Update::Metrics(id, metrics) => {
+                let state = self.machines.entry(id).or_default();
+                // Receiving metrics means the machine is alive, even if we missed the liveliness token.
+                state.online = true;
+                state.history.push_back(*metrics);
+                if state.history.len() > HISTORY_LENGTH {
+                    state.history.pop_front();
+                }
+            }
+            Update::Event(id, event) => {
+                let state = self.machines.entry(id).or_default();
+                state.alerts += 1;
+                state.events.push_back(*event);
+                if state.events.len() > EVENTS_LENGTH {
+                    state.events.pop_front();
+                }
+            }
+            Update::Processes(..) => {}
+            Update::Incompatible(id, protocol) => {
+                self.machines.entry(id).or_default().incompatible = Some(protocol);
+            }
+        }
+    }
+
+    /// Now live, the time of the newest record in a replay, None before the first record.
+    pub fn now(&self) -> Option<SystemTime> {
+        if self.replay {
+            self.time
+        } else {
+            Some(SystemTime::now())
+        }
+    }
+
+    /// Evaluate the offline rules and fire the rules that held long enough.
+    pub fn evaluate(&mut self) {
+        let Some(now) = self.now() else {
+            return;
+        };
+
+        if let Some(alerts) = &mut self.alerts {
+            for (id, state) in &self.machines {
+                alerts.online(id, state.online, now);
+            }
+            alerts.tick(now);
+        }
+    }
+
+    /// Rules firing for machine id.
+    pub fn firing(&self, id: &str) -> usize {
+        self.alerts
+            .as_ref()
+            .map_or(0, |alerts| alerts.firing(id).len())
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.machines.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Clear the alerts of the selected machine, the events stay visible.
+    pub fn acknowledge(&mut self) {
+        if let Some(state) = self.machines.values_mut().nth(self.selected) {
+            state.alerts = 0;
+        }
+    }
+
+    pub fn selected(&self) -> Option<(&String, &MachineState)> {
+        self.machines.iter().nth(self.selected)
+    }
+}
@@ -0,0 +1,258 @@
+pub mod alerts;
+mod app;
+mod ui;
+
+use crate::recording::{Record, Recorder};
+use alerts::Alerts;
+use app::App;
+use common::{
+    compatibility, deserialize, deserialize_machine, peer_protocol, pw, Compatibility,
+    BASE_KEY_EXPR, EVENTS_KEY_EXPR, GROUP_KEY_EXPR, LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR,
+    METRICS_KEY_EXPR, PROCESSES_KEY_EXPR,
+};
+use log::warn;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use std::{
+    sync::mpsc::{channel, Sender},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use zenoh::sample::{Sample, SampleKind};
+
+/// How long we wait for key presses before redrawing.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Information received from the network, keyed by machine id.
+pub enum Update {
+    Machine(String, Box<pw::messages::Machine>),
+    Online(String, bool),
+    Metrics(String, Box<pw::messages::Metrics>),
+    Event(String, Box<pw::messages::KernelEvent>),
+    /// Only received if an alert rule needs them.
+    Processes(String, Box<pw::messages::Processes>),
+    /// The service speaks a protocol we can not read, its samples are dropped.
+    Incompatible(String, pw::messages::Protocol),
+}
+
+/// Where the samples shown come from.
+pub enum Source {
+    /// Subscribe to the group, recording every sample if there is a recorder.
+    Live(zenoh::Session, Option<Recorder>),
+    /// Samples of a recording, speed times faster than they were received.
+    Replay(Vec<Record>, f64),
+}
+
+fn key_expr(kind: &str) -> String {
+    format!("{}/{}/{}/*", BASE_KEY_EXPR, GROUP_KEY_EXPR, kind)
+}
+
+/// The update a sample on key_expr carries, None if the view does not show it. attachment is the protocol of
+/// the service, liveliness samples have none.
+fn decode(
+    key_expr: &str,
+    kind: SampleKind,
+    payload: &[u8],
+    attachment: Option<&[u8]>,
+) -> Option<Update> {
+    let mut chunks = key_expr.rsplit('/');
+    let id = chunks.next()?.to_string();
+    let chunk = chunks.next()?;
+
+    let protocol = peer_protocol(attachment);
+    if chunk != LIVELINESS_KEY_EXPR && compatibility(&protocol) == Compatibility::Incompatible {
+        return Some(Update::Incompatible(id, protocol));
+    }
+
+    let update = match chunk {
+        MACHINE_KEY_EXPR => deserialize_machine(payload).map(|m| Update::Machine(id, Box::new(m))),
+        LIVELINESS_KEY_EXPR => Ok(Update::Online(id, kind == SampleKind::Put)),
+        METRICS_KEY_EXPR => deserialize::<pw::messages::Metrics>(payload)
+            .map(|metrics| Update::Metrics(id, Box::new(metrics))),
+        EVENTS_KEY_EXPR => deserialize::<pw::messages::KernelEvent>(payload)
+            .map(|event| Update::Event(id, Box::new(event))),
+        PROCESSES_KEY_EXPR => deserialize::<pw::messages::Processes>(payload)
+            .map(|processes| Update::Processes(id, Box::new(processes))),
+        _ => return None,
+    };
+
+    update
+        .inspect_err(|err| warn!("Could not parse message (ERROR: '{}')", err))
+        .ok()
+}
+
+/// Record sample if we are recording and pass what it carries on to the view, with its timestamp (now if it
+/// has none, e.g. liveliness).
+fn forward(sample: &Sample, sender: &Sender<(SystemTime, Update)>, recorder: &Option<Recorder>) {
+    if let Some(recorder) = recorder {
+        recorder.record(sample);
+    }
+
+    let payload = sample.payload().to_bytes();
+    let attachment = sample.attachment().map(|attachment| attachment.to_bytes());
+    if let Some(update) = decode(
+        sample.key_expr().as_str(),
+        sample.kind(),
+        &payload,
+        attachment.as_deref(),
+    ) {
+        let time = sample
+            .timestamp()
+            .map_or_else(SystemTime::now, |t| t.get_time().to_system_time());
+        let _ = sender.send((time, update));
+    }
+}
+
+async fn receive_machines(
+    session: zenoh::Session,
+    sender: Sender<(SystemTime, Update)>,
+    recorder: Option<Recorder>,
+) {
+    // Machines that started before us are only available from storage.
+    let getter = session.get(key_expr(MACHINE_KEY_EXPR)).await.unwrap();
+    while let Ok(reply) = getter.recv_async().await {
+        if let Ok(sample) = reply.result() {
+            forward(sample, &sender, &recorder);
+        }
+    }
+
+    let subscriber = session
+        .declare_subscriber(key_expr(MACHINE_KEY_EXPR))
+        .await
+        .unwrap();
+    while let Ok(sample) = subscriber.recv_async().await {
+        forward(&sample, &sender, &recorder);
+    }
+}
+
+async fn receive_liveliness(
+    session: zenoh::Session,
+    sender: Sender<(SystemTime, Update)>,
+    recorder: Option<Recorder>,
+) {
+    let subscriber = session
+        .liveliness()
+        .declare_subscriber(key_expr(LIVELINESS_KEY_EXPR))
+        .history(true)
+        .await
+        .unwrap();
+
+    while let Ok(sample) = subscriber.recv_async().await {
+        forward(&sample, &sender, &recorder);
+    }
+}
+
+/// Receive the samples published on kind, metrics, events or processes.
+async fn receive(
+    session: zenoh::Session,
+    kind: &str,
+    sender: Sender<(SystemTime, Update)>,
+    recorder: Option<Recorder>,
+) {
+    let subscriber = session.declare_subscriber(key_expr(kind)).await.unwrap();
+
+    while let Ok(sample) = subscriber.recv_async().await {
+        forward(&sample, &sender, &recorder);
+    }
+}
+
+/// Pass the records on with the time between them divided by speed.
+async fn replay(records: Vec<Record>, speed: f64, sender: Sender<(SystemTime, Update)>) {
+    let Some(first) = records.first().map(|record| record.time_usec) else {
+        return;
+    };
+    let start = tokio::time::Instant::now();
+
+    for record in records {
+        let offset = record.time_usec.saturating_sub(first) as f64 / speed;
+        tokio::time::sleep_until(start + Duration::from_micros(offset as u64)).await;
+
+        let attachment = (!record.attachment.is_empty()).then_some(record.attachment.as_slice());
+        if let Some(update) = decode(&record.key_expr, record.kind, &record.payload, attachment) {
+            let time = UNIX_EPOCH + Duration::from_micros(record.time_usec);
+            let _ = sender.send((time, update));
+        }
+    }
+}
+
+/// Interactive view of all machines in the group, with the firing alerts if there are rules, runs until the
+/// user quits.
+pub async fn run(source: Source, alerts: Option<Alerts>) {
+    let processes = alerts.as_ref().is_some_and(Alerts::needs_processes);
+
+    let (sender, receiver) = channel();
+    let replaying = matches!(source, Source::Replay(..));
+
+    let title = match source {
+        Source::Live(session, recorder) => {
+            tokio::spawn(receive_machines(
+                session.clone(),
+                sender.clone(),
+                recorder.clone(),
+            ));
+            tokio::spawn(receive_liveliness(
+                session.clone(),
+                sender.clone(),
+                recorder.clone(),
+            ));
+            tokio::spawn(receive(
+                session.clone(),
+                METRICS_KEY_EXPR,
+                sender.clone(),
+                recorder.clone(),
+            ));
+            if processes {
+                tokio::spawn(receive(
+                    session.clone(),
+                    PROCESSES_KEY_EXPR,
+                    sender.clone(),
+                    recorder.clone(),
+                ));
+            }
+            tokio::spawn(receive(session, EVENTS_KEY_EXPR, sender, recorder.clone()));
+            recorder.map(|_| "recording".to_string())
+        }
+        Source::Replay(records, speed) => {
+            let title = format!("replay {}x", speed);
+            tokio::spawn(replay(records, speed, sender));
+            Some(title)
+        }
+    };
+
+    let mut terminal = ratatui::init();
+    let mut app = App {
+        title,
+        alerts,
+        replay: replaying,
+        ..Default::default()
+    };
+
+    while !app.quit {
+        while let Ok((time, update)) = receiver.try_recv() {
+            app.update(time, update);
+        }
+        app.evaluate();
+
+        if let Err(err) = terminal.draw(|frame| ui::draw(frame, &app)) {
+            warn!("Could not draw (ERROR: '{}')", err);
+            break;
+        }
+
+        let event = tokio::task::block_in_place(|| match event::poll(TICK) {
+            Ok(true) => event::read().ok(),
+            _ => None,
+        });
+
+        if let Some(Event::Key(key)) = event {
+            if key.kind == KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => app.quit = true,
+                    KeyCode::Down | KeyCode::Char('j') => app.next(),
+                    KeyCode::Up | KeyCode::Char('k') => app.previous(),
+                    KeyCode::Char('a') => app.acknowledge(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    ratatui::restore();
+}
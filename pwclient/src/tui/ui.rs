@@ -0,0 +1,268 @@
+use super::alerts::Alerts;
+use super::app::{App, MachineState};
+use common::{
+    max_temperature, pw, stringify_compatibility, stringify_duration, stringify_kernel_event,
+    stringify_message, stringify_metrics, stringify_uptime, throttling_reasons,
+};
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph, Sparkline},
+    Frame,
+};
+use std::time::UNIX_EPOCH;
+
+/// Height of the pane with the alerts that fired or resolved, if there are rules.
+const ALERTS_HEIGHT: u16 = 8;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let alerts_height = if app.alerts.is_some() {
+        ALERTS_HEIGHT
+    } else {
+        0
+    };
+    let [main, alerts, footer] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(alerts_height),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list, details] =
+        Layout::horizontal([Constraint::Length(30), Constraint::Min(0)]).areas(main);
+
+    draw_machines(frame, app, list);
+
+    match app.selected() {
+        Some((id, state)) => draw_machine(frame, app, id, state, details),
+        None => frame.render_widget(
+            Paragraph::new("Waiting for machines...").block(Block::bordered()),
+            details,
+        ),
+    }
+
+    if let Some(rules) = &app.alerts {
+        draw_alerts(frame, rules, alerts);
+    }
+
+    frame.render_widget(
+        Line::from(" q: quit, ↑/↓: select machine, a: acknowledge alerts").dark_gray(),
+        footer,
+    );
+}
+
+fn draw_machines(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .machines
+        .iter()
+        .map(|(id, state)| {
+            let throttling = state
+                .latest()
+                .is_some_and(|metrics| !throttling_reasons(metrics).is_empty());
+
+            let firing = app.firing(id);
+
+            if firing > 0 {
+                ListItem::new(format!("{} ({} firing)", state.name(id), firing))
+                    .style(Style::default().fg(Color::Red).bold())
+            } else if state.alerts > 0 {
+                ListItem::new(format!("{} ({} alerts)", state.name(id), state.alerts))
+                    .style(Style::default().fg(Color::Red).bold())
+            } else if !state.online {
+                ListItem::new(state.name(id)).style(Style::default().fg(Color::DarkGray))
+            } else if throttling {
+                ListItem::new(format!("{} (throttling)", state.name(id)))
+                    .style(Style::default().fg(Color::Red))
+            } else {
+                ListItem::new(state.name(id)).style(Style::default().fg(Color::Green))
+            }
+        })
+        .collect();
+
+    let title = match &app.title {
+        Some(title) => format!("Machines ({})", title),
+        None => "Machines".to_string(),
+    };
+
+    let list = List::new(items)
+        .block(Block::bordered().title(title))
+        .highlight_symbol("> ")
+        .highlight_style(Style::default().reversed());
+
+    let mut state = ListState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_machine(frame: &mut Frame, app: &App, id: &str, state: &MachineState, area: Rect) {
+    let mut lines: Vec<Line> = vec![Line::from(format!("id: {}", id))];
+
+    for (rule, value, since) in app.alerts.iter().flat_map(|alerts| alerts.firing(id)) {
+        lines.push(
+            Line::from(format!(
+                "{}: {}, since {}",
+                rule,
+                value,
+                stringify_uptime(
+                    app.now()
+                        .and_then(|now| now.duration_since(since).ok())
+                        .unwrap_or_default()
+                        .as_secs()
+                )
+            ))
+            .red()
+            .bold(),
+        );
+    }
+
+    if let Some(compatibility) = state
+        .incompatible
+        .as_ref()
+        .and_then(stringify_compatibility)
+    {
+        lines.push(Line::from(compatibility).red().bold());
+    }
+
+    if let Some(machine) = &state.machine {
+        for (key, value) in stringify_message(machine) {
+            lines.push(Line::from(format!("{}: {}", key, value)));
+        }
+    }
+
+    if let Some(metrics) = state.latest() {
+        lines.push(Line::from(""));
+        for line in stringify_metrics(metrics) {
+            lines.push(Line::from(line));
+        }
+    }
+
+    if !state.events.is_empty() {
+        lines.push(Line::from(""));
+        for event in state.events.iter().rev() {
+            lines.push(Line::from(stringify_kernel_event(event)).red());
+        }
+    }
+
+    let [info, sparklines] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(SPARKLINES.len() as u16 * SPARKLINE_HEIGHT),
+    ])
+    .areas(area);
+
+    let title = if state.online {
+        "Machine"
+    } else {
+        "Machine (offline)"
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        info,
+    );
+
+    let areas = Layout::vertical([Constraint::Length(SPARKLINE_HEIGHT); SPARKLINES.len()])
+        .split(sparklines);
+
+    for ((name, value, max), area) in SPARKLINES.iter().zip(areas.iter()) {
+        draw_sparkline(frame, name, state, value, *max, *area);
+    }
+}
+
+type MetricsValue = fn(&pw::messages::Metrics) -> Option<f64>;
+
+/// Sparklines shown for the selected machine, name, value and fixed maximum (if any).
+const SPARKLINES: [(&str, MetricsValue, Option<f64>); 5] = [
+    (
+        "load (1 min)",
+        |metrics| metrics.load_average.as_ref().map(|load| load.one),
+        None,
+    ),
+    (
+        "cpu pressure (some, 10 s)",
+        |metrics| metrics.cpu_pressure.as_ref().map(|p| p.some_avg10),
+        Some(100.0),
+    ),
+    (
+        "memory pressure (some, 10 s)",
+        |metrics| metrics.memory_pressure.as_ref().map(|p| p.some_avg10),
+        Some(100.0),
+    ),
+    (
+        "io pressure (some, 10 s)",
+        |metrics| metrics.io_pressure.as_ref().map(|p| p.some_avg10),
+        Some(100.0),
+    ),
+    ("temperature (°C)", max_temperature, None),
+];
+
+const SPARKLINE_HEIGHT: u16 = 4;
+
+/// Values are scaled by 100 since sparklines only take integers.
+fn draw_sparkline(
+    frame: &mut Frame,
+    name: &str,
+    state: &MachineState,
+    value: &MetricsValue,
+    max: Option<f64>,
+    area: Rect,
+) {
+    let title = match state.latest().and_then(value) {
+        Some(latest) => format!("{}: {:.2}", name, latest),
+        None => format!("{}: n/a", name),
+    };
+
+    // Only the newest samples that fit inside the borders.
+    let width = area.width.saturating_sub(2) as usize;
+    let skip = state.history.len().saturating_sub(width);
+    let data: Vec<u64> = state
+        .history
+        .iter()
+        .skip(skip)
+        .map(|metrics| (value(metrics).unwrap_or_default() * 100.0) as u64)
+        .collect();
+
+    let mut sparkline = Sparkline::default()
+        .block(Block::bordered().title(title))
+        .data(&data)
+        .style(Style::default().fg(Color::Cyan));
+
+    if let Some(max) = max {
+        sparkline = sparkline.max((max * 100.0) as u64);
+    }
+
+    frame.render_widget(sparkline, area);
+}
+
+/// Rules that fired or resolved, newest first.
+fn draw_alerts(frame: &mut Frame, alerts: &Alerts, area: Rect) {
+    let lines: Vec<Line> = alerts
+        .recent
+        .iter()
+        .rev()
+        .map(|transition| {
+            let time = transition
+                .time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let (state, color) = if transition.firing {
+                ("FIRING", Color::Red)
+            } else {
+                ("resolved", Color::Green)
+            };
+
+            Line::from(format!(
+                "{} {} {} on {}: {}",
+                stringify_duration(time),
+                state,
+                transition.rule,
+                transition.machine,
+                transition.value
+            ))
+            .style(Style::default().fg(color))
+        })
+        .collect();
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Alerts")),
+        area,
+    );
+}
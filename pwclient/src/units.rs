@@ -0,0 +1,41 @@
+use crate::protocol;
+use common::{
+    pw, stringify_duration, stringify_unit, unit_failing, BASE_KEY_EXPR, GROUP_KEY_EXPR,
+    UNITS_KEY_EXPR,
+};
+use log::{debug, info, warn};
+
+/// Print the systemd services of machine id (all machines if None) as they arrive.
+pub async fn follow(session: &zenoh::Session, id: Option<u64>, failing: bool) {
+    let id = id.map_or("*".to_string(), |id| id.to_string());
+    let key_expr = format!(
+        "{}/{}/{}/{}",
+        BASE_KEY_EXPR, GROUP_KEY_EXPR, UNITS_KEY_EXPR, id
+    );
+
+    debug!("Declaring Units Subscriber on '{key_expr}'...");
+
+    let subscriber = session.declare_subscriber(&key_expr).await.unwrap();
+
+    info!("Press CTRL-C to quit...");
+    while let Ok(sample) = subscriber.recv_async().await {
+        match protocol::decode::<pw::messages::Units>(&sample) {
+            Ok(units) => {
+                let when = sample
+                    .timestamp()
+                    .map(|t| stringify_duration(t.get_time().as_secs().into()))
+                    .unwrap_or_default();
+
+                println!("{} {}", sample.key_expr().as_str(), when);
+                for unit in &units.units {
+                    if !failing || unit_failing(unit) {
+                        println!("  {}", stringify_unit(unit));
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("Could not parse message (ERROR: '{}')", err);
+            }
+        }
+    }
+}
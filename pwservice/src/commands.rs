@@ -0,0 +1,88 @@
+use crate::config::CommandsConfig;
+use crate::platform::control::{process_control, renice, send_signal, set_affinity};
+use crate::platform::error::{machine_error, MachineError};
+use crate::platform::process::{open_files, process_detail, process_name};
+use common::{
+    deserialize, pw, pw::messages::signal_request::Signo, serialize, AFFINITY_COMMAND,
+    DETAIL_COMMAND, OPEN_FILES_COMMAND, RENICE_COMMAND, SIGNAL_COMMAND,
+};
+use log::{info, warn};
+
+/// Log target of everything clients change on the machine, e.g. RUST_LOG=audit=info.
+pub const AUDIT: &str = "audit";
+
+/// Execute command (last chunk of the key expression) with the serialized request in payload.
+/// Returns the serialized reply. The caller checks the command is allowed.
+/// boottime (seconds since the epoch) converts times since boot to wall clock.
+pub fn execute(
+    command: &str,
+    payload: &[u8],
+    config: &CommandsConfig,
+    boottime: u64,
+) -> Result<Vec<u8>, MachineError> {
+    match command {
+        OPEN_FILES_COMMAND => {
+            let request: pw::messages::ProcessRequest = deserialize(payload)
+                .map_err(|e| machine_error!("Could not parse request ({})", e))?;
+
+            Ok(serialize(&open_files(request.pid)?))
+        }
+        DETAIL_COMMAND => {
+            let request: pw::messages::ProcessRequest = deserialize(payload)
+                .map_err(|e| machine_error!("Could not parse request ({})", e))?;
+
+            let detail = process_detail(request.pid, &config.redaction(), boottime)?;
+            Ok(serialize(&detail))
+        }
+        SIGNAL_COMMAND => {
+            let request: pw::messages::SignalRequest = deserialize(payload)
+                .map_err(|e| machine_error!("Could not parse request ({})", e))?;
+            let signal = Signo::try_from(request.signal)
+                .map_err(|_| machine_error!("Unknown signal {}", request.signal))?;
+            let action = format!("SIG{}", signal.as_str_name().to_uppercase());
+
+            if !config.allows_signal(signal) {
+                warn!(target: AUDIT, "Denied {} to pid {}, signal not allowed", action, request.pid);
+                return Err(machine_error!("{} not allowed", action));
+            }
+
+            audited(&action, request.pid, |pid| send_signal(pid, signal))
+        }
+        RENICE_COMMAND => {
+            let request: pw::messages::ReniceRequest = deserialize(payload)
+                .map_err(|e| machine_error!("Could not parse request ({})", e))?;
+            let action = format!("renice {}", request.nice);
+
+            audited(&action, request.pid, |pid| renice(pid, request.nice))
+        }
+        AFFINITY_COMMAND => {
+            let request: pw::messages::AffinityRequest = deserialize(payload)
+                .map_err(|e| machine_error!("Could not parse request ({})", e))?;
+            let action = format!("affinity {:?}", request.cpus);
+
+            audited(&action, request.pid, |pid| set_affinity(pid, &request.cpus))
+        }
+        _ => Err(machine_error!("Unknown command '{}'", command)),
+    }
+}
+
+/// Change process pid, log the outcome and reply with the process after the change.
+fn audited(
+    action: &str,
+    pid: i32,
+    change: impl FnOnce(i32) -> Result<(), MachineError>,
+) -> Result<Vec<u8>, MachineError> {
+    // Resolved before, a killed process has no name anymore.
+    let name = process_name(pid).unwrap_or_default();
+
+    match change(pid) {
+        Ok(()) => {
+            info!(target: AUDIT, "{} pid {} ({})", action, pid, name);
+            Ok(serialize(&process_control(pid)))
+        }
+        Err(err) => {
+            warn!(target: AUDIT, "Failed {} pid {} ({}): {}", action, pid, name, err);
+            Err(err)
+        }
+    }
+}
@@ -1,82 +1,663 @@
+use crate::commands::{self, AUDIT};
+use crate::config::Config;
+use crate::history::{now_usec, parse_time, History};
+use crate::platform::error::{machine_error, MachineError};
+use crate::platform::events::EventCollector;
 use crate::platform::machine::Machine;
+use crate::platform::metrics::MetricsCollector;
+use crate::platform::net::load_connections;
+use crate::platform::overhead::ScanBudget;
+use crate::platform::process::{running, ProcessCollector, Running};
+use crate::streams::Streams;
+#[cfg(feature = "systemd")]
+use common::UNITS_KEY_EXPR;
 use common::{
-    BASE_KEY_EXPR, BOOTID_KEY_EXPR, COMMAND_KEY_EXPR, LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR,
+    protocol,
+    pw::messages::{Health, Metrics, Processes},
+    serialize, BASE_KEY_EXPR, COMMAND_KEY_EXPR, CONNECTIONS_KEY_EXPR, EVENTS_KEY_EXPR,
+    EVENTS_SERIES, HEALTH_KEY_EXPR, HISTORY_KEY_EXPR, KMSG_COMMAND, KMSG_KEY_EXPR,
+    LIVELINESS_KEY_EXPR, MACHINE_KEY_EXPR, METRICS_KEY_EXPR, METRICS_SERIES, PROCESSES_KEY_EXPR,
+    PROCESSES_SERIES, STOP_STREAM_COMMAND, VERSION_KEY_EXPR,
 };
 use log::{info, warn};
-use zenoh::bytes::ZBytes;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use zenoh::{bytes::ZBytes, liveliness::LivelinessToken, query::Query};
+
+/// Group of the machine if neither the argument nor the configuration sets one.
+const DEFAULT_GROUP: &str = "1";
+
+/// How often the connection table is published.
+const CONNECTIONS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a metrics sample is published.
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the process list is published.
+const PROCESSES_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the kernel log is checked for events.
+const EVENTS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the watchdog checks the required processes.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the status of the systemd services is published.
+#[cfg(feature = "systemd")]
+const UNITS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Key expressions of one machine, they change when a reload changes the group or identity.
+#[derive(PartialEq)]
+struct KeyExprs {
+    machine: String,
+    liveliness: String,
+    command: String,
+    history: String,
+    connections: String,
+    metrics: String,
+    processes: String,
+    kmsg: String,
+    events: String,
+    health: String,
+    version: String,
+    #[cfg(feature = "systemd")]
+    units: String,
+}
+
+impl KeyExprs {
+    fn new(grp: &str, id: u64) -> Self {
+        let key_expr = |kind: &str| format!("{}/{}/{}/{}", BASE_KEY_EXPR, grp, kind, id);
+
+        Self {
+            machine: key_expr(MACHINE_KEY_EXPR),
+            liveliness: key_expr(LIVELINESS_KEY_EXPR),
+            command: format!("{}/*", key_expr(COMMAND_KEY_EXPR)),
+            history: format!("{}/*", key_expr(HISTORY_KEY_EXPR)),
+            connections: key_expr(CONNECTIONS_KEY_EXPR),
+            metrics: key_expr(METRICS_KEY_EXPR),
+            processes: key_expr(PROCESSES_KEY_EXPR),
+            kmsg: key_expr(KMSG_KEY_EXPR),
+            events: key_expr(EVENTS_KEY_EXPR),
+            health: key_expr(HEALTH_KEY_EXPR),
+            version: key_expr(VERSION_KEY_EXPR),
+            #[cfg(feature = "systemd")]
+            units: key_expr(UNITS_KEY_EXPR),
+        }
+    }
+}
 
 pub struct ZenohCommunicator {
     session: zenoh::Session,
-    key_expr_machine: String,
-    key_expr_liveliness: String,
-    key_expr_command: String,
-    key_expr_metrics: String,
+    config: Config,
+    /// Read again on SIGHUP.
+    config_path: Option<String>,
+    /// The group argument, takes precedence over the group in the configuration.
+    group: Option<String>,
 }
 
 impl ZenohCommunicator {
-    pub async fn new(config_file: &str, grp: &str, id: u64) -> Self {
+    pub async fn new(
+        config_file: &str,
+        config: Config,
+        config_path: Option<String>,
+        group: Option<String>,
+    ) -> Self {
         zenoh::init_log_from_env_or("error");
-        let config = zenoh::Config::from_file(config_file).unwrap();
+        let zenoh_config = zenoh::Config::from_file(config_file).unwrap();
 
         Self {
-            session: zenoh::open(config).await.unwrap(),
-            key_expr_machine: format!("{}/{}/{}/{}", BASE_KEY_EXPR, grp, MACHINE_KEY_EXPR, id),
-            key_expr_liveliness: format!(
-                "{}/{}/{}/{}",
-                BASE_KEY_EXPR, grp, LIVELINESS_KEY_EXPR, id
-            ),
-            key_expr_command: format!("{}/{}/{}/{}/*", BASE_KEY_EXPR, grp, COMMAND_KEY_EXPR, id),
-            key_expr_metrics: format!("{}/{}/{}/{}", BASE_KEY_EXPR, grp, BOOTID_KEY_EXPR, id),
+            session: zenoh::open(zenoh_config).await.unwrap(),
+            config,
+            config_path,
+            group,
         }
     }
 
-    pub async fn run<M: Machine>(&mut self, machine: &M) {
-        let payload = ZBytes::from(machine.serialize());
-        self.session
-            .put(&self.key_expr_machine, payload)
-            .await
-            .unwrap();
-
-        let liveliness = self
-            .session
-            .liveliness()
-            .declare_token(&self.key_expr_liveliness)
-            .await
-            .unwrap();
-
-        let subscriber = self
-            .session
-            .declare_subscriber(&self.key_expr_command)
-            .await
-            .unwrap();
-        let publisher = self
-            .session
-            .declare_publisher(&self.key_expr_metrics)
-            .await
-            .unwrap();
-
-        while let Ok(sample) = subscriber.recv_async().await {
-            // Refer to z_bytes.rs to see how to deserialize different types of message
-            let payload = sample
-                .payload()
-                .try_to_string()
-                .unwrap_or_else(|e| e.to_string().into());
+    fn key_exprs<M: Machine>(
+        &self,
+        config: &Config,
+        machine: &M,
+    ) -> Result<KeyExprs, MachineError> {
+        let grp = self
+            .group
+            .as_deref()
+            .or(config.group.as_deref())
+            .unwrap_or(DEFAULT_GROUP);
+
+        Ok(KeyExprs::new(grp, config.identity.resolve(machine)?))
+    }
+
+    /// Publish until the session is closed. SIGHUP reloads the configuration, the session is kept.
+    pub async fn run<M: Machine>(&mut self, machine: &M) -> Result<(), MachineError> {
+        let mut keys = self.key_exprs(&self.config, machine)?;
+
+        let mut hangup = signal(SignalKind::hangup())
+            .map_err(|e| machine_error!("Could not handle SIGHUP ({})", e))?;
+
+        // Kept over reloads, metrics are rates since the previous sample. Shared with the sample running on the
+        // blocking thread pool.
+        let metrics = Arc::new(Mutex::new(MetricsCollector::default()));
+        let mut events: Option<EventCollector> = None;
+        let mut liveliness: Option<LivelinessToken> = None;
+        let mut history = History::default();
+        // Kept over reloads that do not change it, the restarts are counted since pwservice started.
+        let mut watchdog_config = self.config.watchdog.clone();
+        let mut watchdog = watchdog_config.watchdog();
+        let mut health: Option<Health> = None;
+        // Kept over reloads, running kmsg streams continue and process rates are since the previous scan.
+        let mut streams = Streams::new(self.session.clone(), keys.kmsg.clone(), machine.boottime());
+        // Shared with the scan running on the blocking thread pool.
+        let processes = Arc::new(Mutex::new(ProcessCollector::new(
+            self.config.processes.filter(),
+            self.config.processes.top(),
+        )));
+
+        loop {
+            let payload = ZBytes::from(machine.serialize());
+            self.session
+                .put(&keys.machine, payload)
+                .attachment(serialize(&protocol()))
+                .await
+                .unwrap();
+
+            if liveliness.is_none() {
+                liveliness = Some(
+                    self.session
+                        .liveliness()
+                        .declare_token(&keys.liveliness)
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            // Commands are queries, the last chunk of the key expression is the command.
+            let queryable = self
+                .session
+                .declare_queryable(keys.command.clone())
+                .await
+                .unwrap();
+
+            // The last chunk is the series, the time range is in the parameters.
+            let history_queryable = self
+                .session
+                .declare_queryable(keys.history.clone())
+                .await
+                .unwrap();
+            history.apply(&self.config.history);
+
+            if self.config.watchdog != watchdog_config {
+                watchdog_config = self.config.watchdog.clone();
+                watchdog = watchdog_config.watchdog();
+                health = None;
+            }
+
+            // The latest health, for clients that connect later when there is no storage.
+            let health_queryable = self
+                .session
+                .declare_queryable(keys.health.clone())
+                .await
+                .unwrap();
+
+            // Which build runs here, the machine record has it too but needs a storage.
+            let version_queryable = self
+                .session
+                .declare_queryable(keys.version.clone())
+                .await
+                .unwrap();
+
+            let health_publisher = self
+                .session
+                .declare_publisher(keys.health.clone())
+                .await
+                .unwrap();
+
+            let metrics_publisher = self
+                .session
+                .declare_publisher(keys.metrics.clone())
+                .await
+                .unwrap();
+
+            let processes_publisher = self
+                .session
+                .declare_publisher(keys.processes.clone())
+                .await
+                .unwrap();
+
+            let events_publisher = self
+                .session
+                .declare_publisher(keys.events.clone())
+                .await
+                .unwrap();
+
+            let collectors = self.config.collectors.clone();
+
+            // Needs read access to /dev/kmsg, the rest works without it.
+            if !collectors.events.enabled {
+                events = None;
+            } else if events.is_none() {
+                events = EventCollector::open(machine.boottime())
+                    .inspect_err(|err| warn!("No kernel events ({})", err))
+                    .ok();
+            }
+
+            // Reading the socket tables of all processes takes a while on busy machines.
+            let connections = collectors.connections.enabled.then(|| {
+                tokio::spawn(publish_connections(
+                    self.session.clone(),
+                    keys.connections.clone(),
+                    collectors.connections.interval_or(CONNECTIONS_INTERVAL),
+                ))
+            });
+
+            // D-Bus calls are slow and may block for a while, keep them out of the main loop.
+            #[cfg(feature = "systemd")]
+            let units = collectors.units.enabled.then(|| {
+                tokio::spawn(publish_units(
+                    self.session.clone(),
+                    keys.units.clone(),
+                    collectors.units.interval_or(UNITS_INTERVAL),
+                ))
+            });
+
+            processes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .configure(self.config.processes.filter(), self.config.processes.top());
+            // statvfs, the cgroup tree and the sensors are read off the loop, a slow filesystem does not stall it.
+            let mut sample: Option<JoinHandle<Metrics>> = None;
+            // Reading /proc of every process takes a while, the loop keeps answering queries meanwhile.
+            let mut scan: Option<JoinHandle<(Processes, Duration)>> = None;
+            // Same for the process list of the watchdog.
+            let mut listing: Option<JoinHandle<Vec<Running>>> = None;
+            let mut budget = ScanBudget::new(
+                collectors.processes.interval_or(PROCESSES_INTERVAL),
+                self.config.processes.budget,
+            );
+            let mut metrics_interval =
+                tokio::time::interval(collectors.metrics.interval_or(METRICS_INTERVAL));
+            let mut processes_interval = tokio::time::interval(budget.interval());
+            let mut events_interval =
+                tokio::time::interval(collectors.events.interval_or(EVENTS_INTERVAL));
+            let mut watchdog_interval =
+                tokio::time::interval(watchdog_config.interval_or(WATCHDOG_INTERVAL));
+
+            loop {
+                tokio::select! {
+                    query = queryable.recv_async() => {
+                        match query {
+                            Ok(query) => self.handle_command(query, &mut streams, machine.boottime()).await,
+                            Err(_) => {
+                                if let Some(liveliness) = liveliness {
+                                    liveliness.undeclare().await.unwrap();
+                                }
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Ok(query) = history_queryable.recv_async() => handle_history(query, &history).await,
+                    Ok(query) = version_queryable.recv_async() => handle_version(query, &keys.version).await,
+                    Ok(query) = health_queryable.recv_async() => handle_health(query, &keys.health, health.as_ref()).await,
+                    _ = hangup.recv() => break,
+                    _ = metrics_interval.tick(), if collectors.metrics.enabled && sample.is_none() => {
+                        let metrics = metrics.clone();
+                        sample = Some(tokio::task::spawn_blocking(move || {
+                            metrics.lock().unwrap_or_else(|e| e.into_inner()).sample()
+                        }));
+                    }
+                    Some(sampled) = async { Some(sample.as_mut()?.await) }, if sample.is_some() => {
+                        sample = None;
+                        let sampled = match sampled {
+                            Ok(sampled) => sampled,
+                            Err(err) => {
+                                warn!("Could not sample metrics ({})", err);
+                                continue;
+                            }
+                        };
+
+                        let payload = serialize(&sampled);
+                        history.record(METRICS_SERIES, now_usec(), &payload);
+                        if let Err(err) = metrics_publisher.put(payload).attachment(serialize(&protocol())).await {
+                            warn!("Could not publish metrics ({})", err);
+                        }
+                    }
+                    _ = processes_interval.tick(), if collectors.processes.enabled && scan.is_none() => {
+                        let processes = processes.clone();
+                        scan = Some(tokio::task::spawn_blocking(move || {
+                            let started = Instant::now();
+                            let list = processes.lock().unwrap_or_else(|e| e.into_inner()).scan();
+                            (list, started.elapsed())
+                        }));
+                    }
+                    Some(scanned) = async { Some(scan.as_mut()?.await) }, if scan.is_some() => {
+                        scan = None;
+                        let (mut list, took) = match scanned {
+                            Ok(scanned) => scanned,
+                            Err(err) => {
+                                warn!("Could not scan processes ({})", err);
+                                continue;
+                            }
+                        };
+                        list.overhead = Some(budget.update(took));
+
+                        let payload = serialize(&list);
+                        history.record(PROCESSES_SERIES, now_usec(), &payload);
+                        if let Err(err) = processes_publisher.put(payload).attachment(serialize(&protocol())).await {
+                            warn!("Could not publish processes ({})", err);
+                        }
+
+                        if budget.interval() != processes_interval.period() {
+                            info!("Scanning processes every {:?}", budget.interval());
+                            processes_interval = tokio::time::interval_at(
+                                tokio::time::Instant::now() + budget.interval(),
+                                budget.interval(),
+                            );
+                        }
+                    }
+                    _ = events_interval.tick(), if events.is_some() => {
+                        for event in events.as_mut().map(EventCollector::poll).unwrap_or_default() {
+                            info!("Kernel event '{}'", event.message);
+                            let payload = serialize(&event);
+                            history.record(EVENTS_SERIES, event.time_usec, &payload);
+                            if let Err(err) = events_publisher.put(payload).attachment(serialize(&protocol())).await {
+                                warn!("Could not publish kernel event ({})", err);
+                            }
+                        }
+                    }
+                    _ = watchdog_interval.tick(), if !watchdog.is_empty() && listing.is_none() => {
+                        listing = Some(tokio::task::spawn_blocking(running));
+                    }
+                    Some(listed) = async { Some(listing.as_mut()?.await) }, if listing.is_some() => {
+                        listing = None;
+                        let listed = match listed {
+                            Ok(listed) => listed,
+                            Err(err) => {
+                                warn!("Could not list processes ({})", err);
+                                continue;
+                            }
+                        };
+
+                        let (checked, found) = watchdog.update(&listed, now_usec());
+                        for event in found {
+                            warn!("{}", event.message);
+                            let payload = serialize(&event);
+                            history.record(EVENTS_SERIES, event.time_usec, &payload);
+                            if let Err(err) = events_publisher.put(payload).attachment(serialize(&protocol())).await {
+                                warn!("Could not publish watchdog event ({})", err);
+                            }
+                        }
+
+                        if let Err(err) = health_publisher.put(serialize(&checked)).attachment(serialize(&protocol())).await {
+                            warn!("Could not publish health ({})", err);
+                        }
+                        health = Some(checked);
+                    }
+                }
+            }
+
+            if let Some(connections) = connections {
+                connections.abort();
+            }
+            // Finish the scan, the collector is configured again after the reload.
+            if let Some(scan) = scan {
+                let _ = scan.await;
+            }
+
+            #[cfg(feature = "systemd")]
+            if let Some(units) = units {
+                units.abort();
+            }
+
+            // The queryable and publishers are undeclared when dropped at the end of the loop.
+            let Some(new_keys) = self.reload(machine) else {
+                continue;
+            };
 
+            if new_keys.liveliness != keys.liveliness {
+                if let Some(liveliness) = liveliness.take() {
+                    liveliness.undeclare().await.unwrap();
+                }
+            }
+            // Clients subscribe to the old key expression, their streams are stopped.
+            if new_keys.kmsg != keys.kmsg {
+                streams = Streams::new(
+                    self.session.clone(),
+                    new_keys.kmsg.clone(),
+                    machine.boottime(),
+                );
+            }
+            keys = new_keys;
+        }
+    }
+
+    /// Read the configuration file again, keeps the current configuration if the new one is invalid.
+    /// The key expressions of the new configuration if it was applied.
+    fn reload<M: Machine>(&mut self, machine: &M) -> Option<KeyExprs> {
+        let Some(path) = &self.config_path else {
+            info!("SIGHUP without a service configuration, nothing to reload");
+            return None;
+        };
+
+        let config = match Config::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Keeping the current configuration, {}", err);
+                return None;
+            }
+        };
+
+        match self.key_exprs(&config, machine) {
+            Ok(keys) => {
+                info!("Reloaded configuration {}", path);
+                self.config = config;
+                Some(keys)
+            }
+            Err(err) => {
+                warn!("Keeping the current configuration, {}", err);
+                None
+            }
+        }
+    }
+
+    async fn handle_command(&self, query: Query, streams: &mut Streams, boottime: u64) {
+        let command = query
+            .key_expr()
+            .as_str()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let payload = query
+            .payload()
+            .map(|payload| payload.to_bytes().to_vec())
+            .unwrap_or_default();
+
+        info!(
+            "[Queryable] Received command from client: '{}'",
+            query.key_expr().as_str()
+        );
+
+        // Anyone on the network can send commands, only run what the configuration allows.
+        if !self.config.commands.allows(command) {
+            warn!(target: AUDIT, "Denied command '{}', not allowed", query.key_expr());
+            let reply = format!("Command '{}' not allowed", command);
+            if let Err(err) = query.reply_err(reply).await {
+                warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+            }
+            return;
+        }
+
+        // Streams need the session and outlive the query, everything else is a plain request/reply.
+        let result = match command {
+            KMSG_COMMAND => streams.start_kmsg(&payload),
+            STOP_STREAM_COMMAND => streams.stop(&payload),
+            _ => commands::execute(command, &payload, &self.config.commands, boottime),
+        };
+
+        match result {
+            Ok(reply) => {
+                if let Err(err) = query
+                    .reply(query.key_expr().clone(), reply)
+                    .attachment(serialize(&protocol()))
+                    .await
+                {
+                    warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+                }
+            }
+            Err(err) => {
+                warn!("Command '{}' failed ({})", command, err);
+                if let Err(err) = query.reply_err(err.to_string()).await {
+                    warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+                }
+            }
+        }
+    }
+}
+
+/// Reply with each sample of the series in the time range of the query, "from" and "to" parameters in
+/// seconds since the epoch or negative seconds before now, e.g. "pw/1/h/<id>/metrics?from=-600".
+async fn handle_history(query: Query, history: &History) {
+    let series = query
+        .key_expr()
+        .as_str()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let parameters = query.parameters();
+    let now = now_usec();
+
+    let samples = parse_time(parameters.get("from"), now, 0).and_then(|from| {
+        let to = parse_time(parameters.get("to"), now, u64::MAX)?;
+        history.query(series, from, to)
+    });
+
+    match samples {
+        Ok(samples) => {
             info!(
-                "[Subscriber] Received command from client: {} ('{}': '{}')",
-                sample.kind(),
-                sample.key_expr().as_str(),
-                payload
+                "[Queryable] {} history samples for '{}'",
+                samples.len(),
+                query.selector()
             );
+            for sample in samples {
+                if let Err(err) = query
+                    .reply(query.key_expr().clone(), serialize(sample))
+                    .attachment(serialize(&protocol()))
+                    .await
+                {
+                    warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+                    return;
+                }
+            }
+        }
+        Err(err) => {
+            if let Err(err) = query.reply_err(err.to_string()).await {
+                warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+            }
+        }
+    }
+}
+
+/// Reply with the build of pwservice, on our version key expression as the query may have wildcards.
+async fn handle_version(query: Query, key_expr: &str) {
+    if let Err(err) = query
+        .reply(key_expr, serialize(&crate::version()))
+        .attachment(serialize(&protocol()))
+        .await
+    {
+        warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+    }
+}
 
-            publisher.put("TODO test...").await.unwrap();
+/// Reply with the result of the latest watchdog check, an error if there are no required processes.
+/// The query may have wildcards, the reply is on our health key expression.
+async fn handle_health(query: Query, key_expr: &str, health: Option<&Health>) {
+    let result = match health {
+        Some(health) => {
+            query
+                .reply(key_expr, serialize(health))
+                .attachment(serialize(&protocol()))
+                .await
+        }
+        None => query.reply_err("No required processes configured").await,
+    };
 
-            if let Some(att) = sample.attachment() {
-                let att = att.try_to_string().unwrap_or_else(|e| e.to_string().into());
-                warn!("{}", att);
+    if let Err(err) = result {
+        warn!("Could not reply to '{}' ({})", query.key_expr(), err);
+    }
+}
+
+/// Publish the sockets periodically, reading them blocks so it runs on the blocking thread pool.
+async fn publish_connections(session: zenoh::Session, key_expr: String, period: Duration) {
+    let publisher = match session.declare_publisher(&key_expr).await {
+        Ok(publisher) => publisher,
+        Err(err) => {
+            warn!("Could not declare publisher on '{}' ({})", key_expr, err);
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        match tokio::task::spawn_blocking(load_connections).await {
+            Ok(connections) => {
+                if let Err(err) = publisher
+                    .put(serialize(&connections))
+                    .attachment(serialize(&protocol()))
+                    .await
+                {
+                    warn!("Could not publish connections ({})", err);
+                }
             }
+            Err(err) => warn!("Could not load connections ({})", err),
         }
+    }
+}
 
-        liveliness.undeclare().await.unwrap();
+/// Publish the systemd services periodically, gives up if there is no system bus (no systemd).
+#[cfg(feature = "systemd")]
+async fn publish_units(session: zenoh::Session, key_expr: String, period: Duration) {
+    use crate::platform::systemd::units;
+
+    let connection = match zbus::Connection::system().await {
+        Ok(connection) => connection,
+        Err(err) => {
+            warn!(
+                "Could not connect to the system bus, no unit status ({})",
+                err
+            );
+            return;
+        }
+    };
+
+    let publisher = match session.declare_publisher(&key_expr).await {
+        Ok(publisher) => publisher,
+        Err(err) => {
+            warn!("Could not declare publisher on '{}' ({})", key_expr, err);
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        match units(&connection).await {
+            Ok(units) => {
+                if let Err(err) = publisher
+                    .put(serialize(&units))
+                    .attachment(serialize(&protocol()))
+                    .await
+                {
+                    warn!("Could not publish units ({})", err);
+                }
+            }
+            Err(err) => warn!("Could not list systemd units ({})", err),
+        }
     }
 }
@@ -0,0 +1,681 @@
+use crate::platform::error::{machine_error, MachineError};
+use crate::platform::machine::{machine_id, Machine};
+use crate::platform::process::{ProcessFilter, ProcessRules, TopBy};
+use crate::platform::watchdog::{Requirement, Watchdog};
+use common::{
+    pw::messages::signal_request::Signo, AFFINITY_COMMAND, DETAIL_COMMAND, EVENTS_SERIES,
+    KMSG_COMMAND, METRICS_SERIES, OPEN_FILES_COMMAND, PROCESSES_SERIES, RENICE_COMMAND,
+    SIGNAL_COMMAND, STOP_STREAM_COMMAND,
+};
+use regex::RegexSet;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Commands pwservice knows, anything else is rejected when the configuration is loaded.
+const COMMANDS: [&str; 7] = [
+    OPEN_FILES_COMMAND,
+    DETAIL_COMMAND,
+    KMSG_COMMAND,
+    STOP_STREAM_COMMAND,
+    SIGNAL_COMMAND,
+    RENICE_COMMAND,
+    AFFINITY_COMMAND,
+];
+
+/// Series the history can keep.
+const SERIES: [&str; 3] = [METRICS_SERIES, PROCESSES_SERIES, EVENTS_SERIES];
+
+/// Bytes a sample of a series typically takes in the history file, processes for a few hundred processes.
+fn typical_sample_size(series: &str) -> u64 {
+    match series {
+        METRICS_SERIES => 2 * 1024,
+        PROCESSES_SERIES => 128 * 1024,
+        _ => 256,
+    }
+}
+
+/// Settings of pwservice itself, zenoh has its own configuration file.
+/// Everything is optional, an empty file gives the same service as no file.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Group of the machine, the group argument takes precedence.
+    pub group: Option<String>,
+    pub identity: Identity,
+    pub collectors: CollectorsConfig,
+    pub processes: ProcessesConfig,
+    pub commands: CommandsConfig,
+    pub history: HistoryConfig,
+    pub watchdog: WatchdogConfig,
+}
+
+/// What the id of the machine (last chunk of its key expressions) is derived from.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Identity {
+    /// MAC address of the interface with the default route.
+    #[default]
+    Mac,
+    /// /etc/machine-id, survives replacing the network hardware.
+    MachineId,
+    /// Serial number from /proc/cpuinfo, e.g. on Raspberry Pi.
+    Serial,
+    /// Hash of the hostname, for machines with a meaningful unique hostname.
+    Hostname,
+    /// Set by hand, e.g. { "fixed": 42 }.
+    Fixed(u64),
+}
+
+impl Identity {
+    pub fn resolve(&self, machine: &impl Machine) -> Result<u64, MachineError> {
+        match self {
+            Identity::Mac => Ok(machine.mac()),
+            Identity::MachineId => {
+                machine_id("/").ok_or_else(|| machine_error!("Identity machine-id, no machine-id"))
+            }
+            Identity::Serial => u64::from_str_radix(machine.serial(), 16)
+                .ok()
+                .filter(|serial| *serial != 0)
+                .ok_or_else(|| machine_error!("Identity serial, no serial number in cpuinfo")),
+            Identity::Hostname if machine.hostname().is_empty() => {
+                Err(machine_error!("Identity hostname, no hostname"))
+            }
+            Identity::Hostname => Ok(fnv1a(machine.hostname())),
+            Identity::Fixed(id) => Ok(*id),
+        }
+    }
+}
+
+/// 64 bit FNV-1a, unlike the std hashers stable between Rust versions.
+fn fnv1a(input: &str) -> u64 {
+    input.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A periodic publisher.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorConfig {
+    pub enabled: bool,
+    /// Seconds between samples, the built in interval of the collector if not set.
+    pub interval: Option<u64>,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: None,
+        }
+    }
+}
+
+impl CollectorConfig {
+    pub fn interval_or(&self, default: Duration) -> Duration {
+        self.interval.map_or(default, Duration::from_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorsConfig {
+    pub connections: CollectorConfig,
+    pub metrics: CollectorConfig,
+    pub processes: CollectorConfig,
+    /// OOM kills, segfaults and hung tasks from the kernel log.
+    pub events: CollectorConfig,
+    /// systemd services, only with the systemd feature.
+    pub units: CollectorConfig,
+}
+
+impl CollectorsConfig {
+    fn all(&self) -> [(&str, &CollectorConfig); 5] {
+        [
+            ("connections", &self.connections),
+            ("metrics", &self.metrics),
+            ("processes", &self.processes),
+            ("events", &self.events),
+            ("units", &self.units),
+        ]
+    }
+}
+
+/// A process matches if it matches any of the rules.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessRulesConfig {
+    /// Regular expressions matching the process name.
+    pub names: Vec<String>,
+    pub uids: Vec<i32>,
+    /// Regular expressions matching the cgroup path, e.g. "^/system.slice/docker-".
+    pub cgroups: Vec<String>,
+    pub pids: Vec<i32>,
+}
+
+impl ProcessRulesConfig {
+    fn rules(&self) -> Result<ProcessRules, MachineError> {
+        ProcessRules::new(&self.names, &self.uids, &self.cgroups, &self.pids)
+    }
+}
+
+/// Which processes are published and how often they are scanned.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProcessesConfig {
+    /// All processes if empty.
+    pub include: ProcessRulesConfig,
+    pub exclude: ProcessRulesConfig,
+    /// Only the processes using the most cpu or memory.
+    pub top: Option<usize>,
+    pub top_by: TopBy,
+    /// Percent of one CPU pwservice may use, the scan interval backs off while it uses more.
+    pub budget: Option<f32>,
+}
+
+impl ProcessesConfig {
+    /// The patterns were checked when the configuration was loaded.
+    pub fn filter(&self) -> ProcessFilter {
+        ProcessFilter::new(
+            self.include.rules().unwrap_or_default(),
+            self.exclude.rules().unwrap_or_default(),
+        )
+    }
+
+    pub fn top(&self) -> Option<(usize, TopBy)> {
+        self.top.map(|count| (count, self.top_by))
+    }
+}
+
+/// Recent samples kept for clients that connect later.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// "metrics", "processes" or "events".
+    pub series: Vec<String>,
+    /// Seconds samples are kept.
+    pub retention: u64,
+    /// Samples kept per series at most.
+    pub samples: usize,
+    /// Samples are also appended to this file and read back at startup.
+    pub file: Option<PathBuf>,
+    /// Bytes, the file is rewritten with only the kept samples when it grows beyond this.
+    pub file_size: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            series: [METRICS_SERIES, EVENTS_SERIES].map(String::from).to_vec(),
+            retention: 3600,
+            samples: 1000,
+            file: None,
+            file_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Processes that must be running, checked by pwservice whether or not a client is connected.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Seconds between checks, 5 if not set.
+    pub interval: Option<u64>,
+    pub processes: Vec<RequiredProcessConfig>,
+}
+
+impl WatchdogConfig {
+    pub fn interval_or(&self, default: Duration) -> Duration {
+        self.interval.map_or(default, Duration::from_secs)
+    }
+
+    fn requirements(&self) -> Result<Vec<Requirement>, MachineError> {
+        self.processes
+            .iter()
+            .map(|process| {
+                Requirement::new(
+                    process.name.as_deref(),
+                    process.cmdline.as_deref(),
+                    process.min,
+                )
+            })
+            .collect()
+    }
+
+    /// The patterns were checked when the configuration was loaded.
+    pub fn watchdog(&self) -> Watchdog {
+        Watchdog::new(self.requirements().unwrap_or_default())
+    }
+}
+
+/// A process matches if it matches everything that is set.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequiredProcessConfig {
+    /// Exact process name, as in /proc/<pid>/comm.
+    pub name: Option<String>,
+    /// Regular expression matching the command line, e.g. "python3 .*app\.py".
+    pub cmdline: Option<String>,
+    /// Matching processes that must be running.
+    pub min: u32,
+}
+
+impl Default for RequiredProcessConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            cmdline: None,
+            min: 1,
+        }
+    }
+}
+
+/// Which commands clients may send, only the read only ones unless configured otherwise.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandsConfig {
+    pub allow: Vec<String>,
+    /// Signals the signal command may send, "TERM", "KILL", "HUP", "STOP" or "CONT".
+    pub signals: Vec<String>,
+    /// Regular expressions, environment variables with a matching name are redacted by the detail command.
+    pub redact: Vec<String>,
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        Self {
+            allow: [
+                OPEN_FILES_COMMAND,
+                DETAIL_COMMAND,
+                KMSG_COMMAND,
+                STOP_STREAM_COMMAND,
+            ]
+            .map(String::from)
+            .to_vec(),
+            signals: ["TERM", "HUP"].map(String::from).to_vec(),
+            redact: [
+                "(?i)pass",
+                "(?i)secret",
+                "(?i)token",
+                "(?i)credential",
+                "(?i)auth",
+                "(?i)private",
+                "(?i)(^|_)(api_?)?key($|_)",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl CommandsConfig {
+    pub fn allows(&self, command: &str) -> bool {
+        self.allow.iter().any(|allowed| allowed == command)
+    }
+
+    pub fn allows_signal(&self, signal: Signo) -> bool {
+        self.signals
+            .iter()
+            .any(|allowed| signal_by_name(allowed) == Some(signal))
+    }
+
+    /// The patterns were checked when the configuration was loaded.
+    pub fn redaction(&self) -> RegexSet {
+        RegexSet::new(&self.redact).unwrap_or_else(|_| RegexSet::empty())
+    }
+}
+
+/// "TERM", "SIGTERM" or "term".
+fn signal_by_name(name: &str) -> Option<Signo> {
+    let name = name.to_lowercase();
+    Signo::from_str_name(name.strip_prefix("sig").unwrap_or(&name))
+}
+
+impl Config {
+    /// Read and validate a TOML (.toml) or JSON configuration file.
+    pub fn load(path: &str) -> Result<Self, MachineError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| machine_error!("Could not read {} ({})", path, e))?;
+
+        let toml = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "toml");
+
+        Self::parse(&content, toml)
+            .map_err(|e| machine_error!("Invalid configuration {}: {}", path, e))
+    }
+
+    fn parse(content: &str, toml: bool) -> Result<Self, String> {
+        let config: Config = if toml {
+            toml::from_str(content).map_err(|e| e.to_string())?
+        } else {
+            serde_json::from_str(content).map_err(|e| e.to_string())?
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(group) = self.group.as_ref().filter(|group| !valid_chunk(group)) {
+            return Err(format!(
+                "group '{}' must be a non empty key expression chunk without '/', '*', '$', '?' or '#'",
+                group
+            ));
+        }
+
+        for (name, collector) in self.collectors.all() {
+            if collector.interval == Some(0) {
+                return Err(format!(
+                    "collectors.{}.interval must be at least 1 second",
+                    name
+                ));
+            }
+        }
+
+        self.processes
+            .include
+            .rules()
+            .map_err(|e| format!("processes.include: {}", e.message))?;
+        self.processes
+            .exclude
+            .rules()
+            .map_err(|e| format!("processes.exclude: {}", e.message))?;
+
+        if self.processes.top == Some(0) {
+            return Err("processes.top must be at least 1".to_string());
+        }
+
+        if self
+            .processes
+            .budget
+            .is_some_and(|budget| !(budget > 0.0 && budget <= 100.0))
+        {
+            return Err(
+                "processes.budget must be a percentage of one CPU, above 0 up to 100".to_string(),
+            );
+        }
+
+        if let Some(command) = self
+            .commands
+            .allow
+            .iter()
+            .find(|command| !COMMANDS.contains(&command.as_str()))
+        {
+            return Err(format!(
+                "commands.allow: unknown command '{}', known are {}",
+                command,
+                COMMANDS.join(", ")
+            ));
+        }
+
+        if let Some(signal) = self
+            .commands
+            .signals
+            .iter()
+            .find(|signal| signal_by_name(signal).is_none())
+        {
+            return Err(format!(
+                "commands.signals: unknown signal '{}', known are TERM, KILL, HUP, STOP and CONT",
+                signal
+            ));
+        }
+
+        RegexSet::new(&self.commands.redact).map_err(|e| format!("commands.redact: {}", e))?;
+
+        if let Some(series) = self
+            .history
+            .series
+            .iter()
+            .find(|series| !SERIES.contains(&series.as_str()))
+        {
+            return Err(format!(
+                "history.series: unknown series '{}', known are {}",
+                series,
+                SERIES.join(", ")
+            ));
+        }
+
+        if self.history.retention == 0 || self.history.samples == 0 {
+            return Err("history.retention and history.samples must be at least 1".to_string());
+        }
+
+        if self.watchdog.interval == Some(0) {
+            return Err("watchdog.interval must be at least 1 second".to_string());
+        }
+
+        for (index, process) in self.watchdog.processes.iter().enumerate() {
+            if process.min == 0 {
+                return Err(format!(
+                    "watchdog.processes[{}].min must be at least 1",
+                    index
+                ));
+            }
+            Requirement::new(process.name.as_deref(), process.cmdline.as_deref(), 1)
+                .map_err(|e| format!("watchdog.processes[{}]: {}", index, e.message))?;
+        }
+
+        // A rewrite must at least fit a few samples.
+        if self.history.file_size < 64 * 1024 {
+            return Err("history.file-size must be at least 65536 bytes".to_string());
+        }
+
+        // A rewrite keeps the newest samples that fit in half the file, the rest would only be in memory.
+        let typical: u64 = self
+            .history
+            .series
+            .iter()
+            .map(|series| self.history.samples as u64 * typical_sample_size(series))
+            .sum();
+        if self.history.file.is_some() && typical > self.history.file_size / 2 {
+            return Err(format!(
+                "history.file-size: {} samples of {} take about {} bytes, more than half the file, \
+                 lower history.samples or raise history.file-size",
+                self.history.samples,
+                self.history.series.join(", "),
+                typical
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Used as a chunk of the key expressions, must not contain wildcards or separators.
+pub fn valid_chunk(chunk: &str) -> bool {
+    !chunk.is_empty() && !chunk.contains(['/', '*', '$', '?', '#'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TestMachine;
+
+    impl Machine for TestMachine {
+        fn mac(&self) -> u64 {
+            0x0012_3456_789a
+        }
+        fn boottime(&self) -> u64 {
+            0
+        }
+        fn hostname(&self) -> &str {
+            "raspberrypi"
+        }
+        fn serial(&self) -> &str {
+            "00000000abcdef12"
+        }
+        fn serialize(&self) -> Vec<u8> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::parse("{}", false).unwrap();
+
+        assert_eq!(config, Config::default());
+        assert_eq!(Config::parse("", true).unwrap(), Config::default());
+        assert!(config.commands.allows(OPEN_FILES_COMMAND));
+        assert!(!config.commands.allows(SIGNAL_COMMAND));
+        assert!(config.collectors.metrics.enabled);
+        assert_eq!(
+            config
+                .collectors
+                .metrics
+                .interval_or(Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+
+        let redaction = config.commands.redaction();
+        for name in [
+            "DB_PASSWORD",
+            "GITHUB_TOKEN",
+            "AWS_SECRET_ACCESS_KEY",
+            "API_KEY",
+            "key",
+        ] {
+            assert!(redaction.is_match(name), "{} not redacted", name);
+        }
+        for name in ["PATH", "KEYBOARD", "MONKEY_BUSINESS", "LANG"] {
+            assert!(!redaction.is_match(name), "{} redacted", name);
+        }
+    }
+
+    #[test]
+    fn load_json() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/pwservice.json");
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+
+        assert!(config.commands.allows(SIGNAL_COMMAND));
+        assert!(config.commands.allows(RENICE_COMMAND));
+        assert!(!config.commands.allows(AFFINITY_COMMAND));
+        assert!(config.commands.allows_signal(Signo::Term));
+        assert!(config.commands.allows_signal(Signo::Stop));
+        assert!(!config.commands.allows_signal(Signo::Kill));
+    }
+
+    #[test]
+    fn load_toml() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/pwservice.toml");
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(config.group.as_deref(), Some("lab"));
+        assert_eq!(config.identity, Identity::MachineId);
+        assert!(!config.collectors.connections.enabled);
+        assert_eq!(
+            config
+                .collectors
+                .metrics
+                .interval_or(Duration::from_secs(5)),
+            Duration::from_secs(2)
+        );
+        assert!(config.collectors.processes.enabled);
+        assert_eq!(
+            config.processes.exclude.names,
+            vec!["^kworker/".to_string()]
+        );
+        assert_eq!(config.processes.exclude.uids, vec![33]);
+        assert_eq!(config.processes.top(), Some((20, TopBy::Memory)));
+        assert_eq!(config.processes.budget, Some(2.5));
+        assert!(config.commands.allows(DETAIL_COMMAND));
+        assert!(!config.commands.allows(KMSG_COMMAND));
+        assert_eq!(config.commands.redact, vec!["(?i)pass".to_string()]);
+        assert_eq!(config.history.series, vec![METRICS_SERIES.to_string()]);
+        assert_eq!(config.history.retention, 600);
+        assert_eq!(
+            config.history.file.as_deref(),
+            Some(Path::new("/var/lib/pwservice/history"))
+        );
+        assert_eq!(config.watchdog.interval, Some(10));
+        assert_eq!(
+            config.watchdog.processes,
+            vec![
+                RequiredProcessConfig {
+                    name: Some("nginx".to_string()),
+                    cmdline: None,
+                    min: 2,
+                },
+                RequiredProcessConfig {
+                    name: None,
+                    cmdline: Some("python3 .*app\\.py".to_string()),
+                    min: 1,
+                },
+            ]
+        );
+        assert!(!config.watchdog.watchdog().is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |content: &str| Config::parse(content, false).unwrap_err();
+
+        assert!(error(r#"{"commands": {"allow": ["reboot"]}}"#).contains("'reboot'"));
+        assert!(error(r#"{"commands": {"signals": ["USR1"]}}"#).contains("'USR1'"));
+        assert!(error(r#"{"command": {}}"#).contains("unknown field `command`"));
+        assert!(error(r#"{"commands": {"redact": ["("]}}"#).starts_with("commands.redact"));
+        assert!(error(r#"{"group": "a/b"}"#).starts_with("group"));
+        assert!(error(r#"{"identity": "uuid"}"#).contains("unknown variant `uuid`"));
+        assert!(error(r#"{"processes": {"include": {"names": ["["]}}}"#)
+            .starts_with("processes.include"));
+        assert!(error(r#"{"processes": {"exclude": {"cgroups": ["("]}}}"#)
+            .starts_with("processes.exclude"));
+        assert!(error(r#"{"processes": {"top": 0}}"#).starts_with("processes.top"));
+        assert!(error(r#"{"processes": {"budget": -1}}"#).starts_with("processes.budget"));
+        assert!(error(r#"{"processes": {"top-by": "io"}}"#).contains("unknown variant `io`"));
+        assert!(error(r#"{"history": {"series": ["units"]}}"#).contains("'units'"));
+        assert!(error(r#"{"history": {"samples": 0}}"#).starts_with("history"));
+        assert!(error(r#"{"history": {"file-size": 1024}}"#).starts_with("history.file-size"));
+        assert!(error(
+            r#"{"history": {"series": ["processes"], "file": "/tmp/history", "file-size": 1048576}}"#
+        )
+        .contains("1000 samples of processes take about 131072000 bytes"));
+        assert!(error(r#"{"watchdog": {"processes": [{"min": 1}]}}"#)
+            .starts_with("watchdog.processes[0]"));
+        assert!(error(r#"{"watchdog": {"processes": [{"cmdline": "("}]}}"#)
+            .starts_with("watchdog.processes[0]"));
+        assert!(
+            error(r#"{"watchdog": {"processes": [{"name": "a", "min": 0}]}}"#)
+                .starts_with("watchdog.processes[0].min")
+        );
+        assert!(error(r#"{"watchdog": {"interval": 0}}"#).starts_with("watchdog.interval"));
+        assert_eq!(
+            error(r#"{"collectors": {"metrics": {"interval": 0}}}"#),
+            "collectors.metrics.interval must be at least 1 second"
+        );
+
+        // TOML errors point at the line.
+        let error = Config::parse("[collectors.metrics]\ninterval = \"5s\"\n", true).unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+    }
+
+    #[test]
+    fn identity() {
+        let machine = TestMachine;
+
+        assert_eq!(Identity::Mac.resolve(&machine).unwrap(), 0x0012_3456_789a);
+        assert_eq!(Identity::Serial.resolve(&machine).unwrap(), 0xabcd_ef12);
+        assert_eq!(Identity::Fixed(42).resolve(&machine).unwrap(), 42);
+        assert_eq!(
+            Identity::Hostname.resolve(&machine).unwrap(),
+            fnv1a("raspberrypi")
+        );
+        // Reference value of the FNV-1a specification.
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+
+        let config = Config::parse(r#"{"identity": {"fixed": 7}}"#, false).unwrap();
+        assert_eq!(config.identity, Identity::Fixed(7));
+    }
+}
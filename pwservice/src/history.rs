@@ -0,0 +1,452 @@
+use crate::config::HistoryConfig;
+use crate::platform::error::{machine_error, MachineError};
+use common::pw::messages::HistorySample;
+use log::{info, warn};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// First bytes of a history file, a file without them is not read.
+const MAGIC: &[u8; 4] = b"PWH1";
+
+/// Recent samples of the published series, for clients that connect after an incident.
+#[derive(Default)]
+pub struct History {
+    config: HistoryConfig,
+    series: HashMap<String, VecDeque<HistorySample>>,
+    file: Option<HistoryFile>,
+}
+
+impl History {
+    /// Apply a (re)loaded configuration, samples of series that are still kept stay.
+    pub fn apply(&mut self, config: &HistoryConfig) {
+        if !config.enabled {
+            self.series.clear();
+            self.file = None;
+        } else if config.file != self.config.file || self.file.is_none() {
+            self.file = config
+                .file
+                .as_ref()
+                .and_then(|path| self.open(path, config));
+        }
+
+        self.series
+            .retain(|name, _| config.series.iter().any(|series| series == name));
+        self.config = config.clone();
+
+        let now = now_usec();
+        for samples in self.series.values_mut() {
+            prune(samples, &self.config, now);
+        }
+    }
+
+    /// Open the file and add what it holds, None if it can not be used.
+    fn open(&mut self, path: &Path, config: &HistoryConfig) -> Option<HistoryFile> {
+        let (file, records) = HistoryFile::open(path, config.file_size)
+            .inspect_err(|err| warn!("No history file ({})", err))
+            .ok()?;
+
+        info!(
+            "Read {} history samples from {}",
+            records.len(),
+            path.display()
+        );
+        for (series, sample) in records {
+            let samples = self.series.entry(series).or_default();
+            if samples
+                .back()
+                .is_none_or(|last| last.time_usec < sample.time_usec)
+            {
+                samples.push_back(sample);
+            }
+        }
+
+        Some(file)
+    }
+
+    /// Keep payload, a serialized message published at time_usec, if series is configured.
+    pub fn record(&mut self, series: &str, time_usec: u64, payload: &[u8]) {
+        if !self.config.enabled || !self.config.series.iter().any(|name| name == series) {
+            return;
+        }
+
+        let sample = HistorySample {
+            time_usec,
+            payload: payload.to_vec(),
+        };
+
+        if let Some(file) = &mut self.file {
+            if let Err(err) = file.append(series, &sample) {
+                warn!(
+                    "Could not write history file, history only in memory ({})",
+                    err
+                );
+                self.file = None;
+            }
+        }
+
+        let samples = self.series.entry(series.to_string()).or_default();
+        samples.push_back(sample);
+        prune(samples, &self.config, time_usec);
+
+        if let Some(file) = self.file.as_mut().filter(|file| file.full()) {
+            let kept = self.series.iter().flat_map(|(series, samples)| {
+                samples.iter().map(move |sample| (series.as_str(), sample))
+            });
+            if let Err(err) = file.rewrite(kept) {
+                warn!(
+                    "Could not rewrite history file, history only in memory ({})",
+                    err
+                );
+                self.file = None;
+            }
+        }
+    }
+
+    /// Samples of series from from_usec up to to_usec, oldest first.
+    pub fn query(
+        &self,
+        series: &str,
+        from_usec: u64,
+        to_usec: u64,
+    ) -> Result<Vec<&HistorySample>, MachineError> {
+        if !self.config.enabled || !self.config.series.iter().any(|name| name == series) {
+            return Err(machine_error!("No history of '{}'", series));
+        }
+
+        Ok(self
+            .series
+            .get(series)
+            .into_iter()
+            .flatten()
+            .filter(|sample| (from_usec..=to_usec).contains(&sample.time_usec))
+            .collect())
+    }
+}
+
+/// Drop samples older than the retention and the oldest beyond the number of samples.
+fn prune(samples: &mut VecDeque<HistorySample>, config: &HistoryConfig, now_usec: u64) {
+    let oldest = now_usec.saturating_sub(config.retention * 1_000_000);
+
+    while samples
+        .front()
+        .is_some_and(|sample| sample.time_usec < oldest)
+        || samples.len() > config.samples
+    {
+        samples.pop_front();
+    }
+}
+
+pub fn now_usec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Start or end of a time range in a query, seconds since the epoch or negative seconds before now.
+pub fn parse_time(value: Option<&str>, now_usec: u64, default: u64) -> Result<u64, MachineError> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+
+    match value.parse::<i64>() {
+        Ok(seconds) if seconds < 0 => {
+            Ok(now_usec.saturating_sub(seconds.unsigned_abs() * 1_000_000))
+        }
+        Ok(seconds) => Ok(seconds as u64 * 1_000_000),
+        Err(_) => Err(machine_error!(
+            "Invalid time '{}', seconds since the epoch or negative seconds before now",
+            value
+        )),
+    }
+}
+
+/// Samples appended as they are recorded, rewritten with the newest kept samples that fit in half of size
+/// when it grows beyond size, so the file stays bounded like a ring. Not synced, a crash loses what the kernel did not write yet.
+struct HistoryFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    size: u64,
+}
+
+impl HistoryFile {
+    /// The file and the samples in it, a new file if it does not exist.
+    fn open(path: &Path, size: u64) -> Result<(Self, Vec<(String, HistorySample)>), MachineError> {
+        let records = match fs::read(path) {
+            Ok(data) if data.starts_with(MAGIC) => decode_records(&data[MAGIC.len()..]),
+            Ok(data) if data.is_empty() => vec![],
+            Ok(_) => return Err(machine_error!("{} is not a history file", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(machine_error!("Could not read {} ({})", path.display(), e)),
+        };
+
+        // Also drops a truncated last record.
+        let (file, len) = write(
+            path,
+            records
+                .iter()
+                .map(|(series, sample)| (series.as_str(), sample)),
+        )
+        .map_err(|e| machine_error!("Could not write {} ({})", path.display(), e))?;
+
+        let file = Self {
+            path: path.to_path_buf(),
+            file,
+            len,
+            size,
+        };
+
+        Ok((file, records))
+    }
+
+    fn full(&self) -> bool {
+        self.len > self.size
+    }
+
+    fn append(&mut self, series: &str, sample: &HistorySample) -> io::Result<()> {
+        let record = encode_record(series, sample);
+        self.file.write_all(&record)?;
+        self.len += record.len() as u64;
+
+        Ok(())
+    }
+
+    /// The samples beyond half of size are dropped oldest first, else every append after a rewrite of more
+    /// than size would rewrite again.
+    fn rewrite<'a>(
+        &mut self,
+        samples: impl Iterator<Item = (&'a str, &'a HistorySample)>,
+    ) -> io::Result<()> {
+        let mut samples: Vec<_> = samples.collect();
+        samples.sort_by_key(|(_, sample)| sample.time_usec);
+
+        let mut len = MAGIC.len() as u64;
+        let fitting = samples
+            .iter()
+            .rev()
+            .take_while(|(series, sample)| {
+                len += record_len(series, sample);
+                len <= self.size / 2
+            })
+            .count();
+        samples.drain(..samples.len() - fitting);
+
+        (self.file, self.len) = write(&self.path, samples.into_iter())?;
+
+        Ok(())
+    }
+}
+
+/// Replace the file at path by one with only samples, written next to it and renamed so a crash leaves
+/// either file. Returns it opened for appending and its length.
+fn write<'a>(
+    path: &Path,
+    samples: impl Iterator<Item = (&'a str, &'a HistorySample)>,
+) -> io::Result<(File, u64)> {
+    let temporary = path.with_extension("tmp");
+
+    let mut data = MAGIC.to_vec();
+    for (series, sample) in samples {
+        data.extend(encode_record(series, sample));
+    }
+    fs::write(&temporary, &data)?;
+    fs::rename(&temporary, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok((file, data.len() as u64))
+}
+
+/// Bytes encode_record gives.
+fn record_len(series: &str, sample: &HistorySample) -> u64 {
+    (13 + series.len() + sample.payload.len()) as u64
+}
+
+/// time_usec (u64), series length (u8), series, payload length (u32), payload, little endian.
+fn encode_record(series: &str, sample: &HistorySample) -> Vec<u8> {
+    let mut record = Vec::with_capacity(record_len(series, sample) as usize);
+
+    record.extend(sample.time_usec.to_le_bytes());
+    record.push(series.len() as u8);
+    record.extend(series.as_bytes());
+    record.extend((sample.payload.len() as u32).to_le_bytes());
+    record.extend(&sample.payload);
+
+    record
+}
+
+/// Records until the end of data, a truncated record at the end (pwservice killed while writing) is dropped.
+fn decode_records(mut data: &[u8]) -> Vec<(String, HistorySample)> {
+    let mut records = vec![];
+
+    fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = data.split_at_checked(n)?;
+        *data = rest;
+        Some(taken)
+    }
+
+    while let Some(time_usec) = take(&mut data, 8) {
+        let Some(series) = take(&mut data, 1).and_then(|len| take(&mut data, len[0] as usize))
+        else {
+            break;
+        };
+        let Some(payload) = take(&mut data, 4)
+            .and_then(|len| take(&mut data, u32::from_le_bytes(len.try_into().ok()?) as usize))
+        else {
+            break;
+        };
+
+        records.push((
+            String::from_utf8_lossy(series).into_owned(),
+            HistorySample {
+                time_usec: u64::from_le_bytes(time_usec.try_into().unwrap_or_default()),
+                payload: payload.to_vec(),
+            },
+        ));
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> HistoryConfig {
+        HistoryConfig {
+            series: vec!["metrics".to_string()],
+            retention: 60,
+            samples: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn record_query() {
+        let mut history = History::default();
+        history.apply(&config());
+
+        let start = now_usec();
+        for i in 0..5 {
+            history.record("metrics", start + i * 1_000_000, &[i as u8]);
+        }
+        history.record("processes", start, &[42]);
+
+        // Only the last 3 are kept.
+        let samples = history.query("metrics", 0, u64::MAX).unwrap();
+        assert_eq!(
+            samples.iter().map(|s| s.payload[0]).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+
+        let samples = history
+            .query("metrics", start + 3_000_000, start + 3_000_000)
+            .unwrap();
+        assert_eq!(samples.len(), 1);
+        assert!(history.query("processes", 0, u64::MAX).is_err());
+
+        // Older than the retention.
+        history.record("metrics", start + 120_000_000, &[9]);
+        assert_eq!(history.query("metrics", 0, u64::MAX).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn time() {
+        let now = 1_731_155_405_000_000;
+
+        assert_eq!(parse_time(None, now, 7).unwrap(), 7);
+        assert_eq!(parse_time(Some("-600"), now, 0).unwrap(), now - 600_000_000);
+        assert_eq!(
+            parse_time(Some("1731155000"), now, 0).unwrap(),
+            1_731_155_000_000_000
+        );
+        assert!(parse_time(Some("10m"), now, 0).is_err());
+    }
+
+    #[test]
+    fn records() {
+        let sample = |time_usec: u64, payload: &[u8]| HistorySample {
+            time_usec,
+            payload: payload.to_vec(),
+        };
+
+        let mut data = encode_record("metrics", &sample(1, b"abc"));
+        data.extend(encode_record("events", &sample(2, b"")));
+
+        let expected = vec![
+            ("metrics".to_string(), sample(1, b"abc")),
+            ("events".to_string(), sample(2, b"")),
+        ];
+        assert_eq!(decode_records(&data), expected);
+
+        data.extend(&encode_record("metrics", &sample(3, b"xyz"))[..12]);
+        assert_eq!(decode_records(&data), expected);
+    }
+
+    #[test]
+    fn file() {
+        let path = std::env::temp_dir().join(format!("pwservice-history-{}", std::process::id()));
+        let config = HistoryConfig {
+            file: Some(path.clone()),
+            file_size: 256,
+            ..config()
+        };
+
+        let start = now_usec();
+        let mut history = History::default();
+        history.apply(&config);
+        for i in 0..10 {
+            history.record("metrics", start + i, &[i as u8; 16]);
+        }
+
+        // Rewritten at the 8th sample of 36 bytes with the 3 kept samples, 2 appended since.
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4 + 5 * 36);
+
+        let mut history = History::default();
+        history.apply(&config);
+        let samples = history.query("metrics", 0, u64::MAX).unwrap();
+        assert_eq!(samples.last().unwrap().payload, vec![9; 16]);
+
+        fs::write(&path, b"not a history file").unwrap();
+        let mut history = History::default();
+        history.apply(&config);
+        assert!(history.file.is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_smaller_than_kept() {
+        let path =
+            std::env::temp_dir().join(format!("pwservice-history-small-{}", std::process::id()));
+        let config = HistoryConfig {
+            file: Some(path.clone()),
+            file_size: 256,
+            samples: 100,
+            ..config()
+        };
+
+        let start = now_usec();
+        let mut history = History::default();
+        history.apply(&config);
+        for i in 0..40 {
+            history.record("metrics", start + i, &[i as u8; 16]);
+            assert!(fs::metadata(&path).unwrap().len() <= 256);
+        }
+        assert_eq!(history.query("metrics", 0, u64::MAX).unwrap().len(), 40);
+
+        // Only the newest that fit in half the file survive a restart, 3 from the last rewrite and 2 appended.
+        let mut history = History::default();
+        history.apply(&config);
+        let samples = history.query("metrics", 0, u64::MAX).unwrap();
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples.last().unwrap().payload, vec![39; 16]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,33 +1,59 @@
+mod commands;
 mod communicator;
+mod config;
+mod history;
 mod platform;
+mod streams;
 use clap::Parser;
 use communicator::ZenohCommunicator;
-use log::info;
-use platform::machine::{LinuxMachine, Machine};
+use config::{valid_chunk, Config};
+use log::{error, info};
+use platform::machine::LinuxMachine;
 
-fn version_info() -> String {
-    let mut build_type = "release";
-    if cfg!(debug_assertions) {
-        build_type = "debug";
+/// This build, from the environment vergen and cargo set when it was built.
+fn version() -> common::pw::messages::VersionInfo {
+    let features = option_env!("VERGEN_CARGO_FEATURES").unwrap_or_default();
+
+    common::pw::messages::VersionInfo {
+        name: option_env!("CARGO_PKG_NAME")
+            .unwrap_or_default()
+            .to_string(),
+        version: option_env!("CARGO_PKG_VERSION")
+            .unwrap_or_default()
+            .to_string(),
+        git_sha: option_env!("VERGEN_GIT_SHA")
+            .unwrap_or_default()
+            .to_string(),
+        git_dirty: option_env!("VERGEN_GIT_DIRTY") == Some("true"),
+        build_timestamp: option_env!("VERGEN_BUILD_TIMESTAMP")
+            .unwrap_or_default()
+            .to_string(),
+        target_triple: option_env!("VERGEN_CARGO_TARGET_TRIPLE")
+            .unwrap_or_default()
+            .to_string(),
+        features: features
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+        debug: cfg!(debug_assertions),
+        protocol: Some(common::protocol()),
     }
+}
 
-    format!(
-        "{} v{} {} (Git SHA: {}, dirty: {}, build time: {})",
-        option_env!("CARGO_PKG_NAME").unwrap_or_default(),
-        option_env!("CARGO_PKG_VERSION").unwrap_or_default(),
-        build_type,
-        option_env!("VERGEN_GIT_SHA").unwrap_or_default(),
-        option_env!("VERGEN_GIT_DIRTY").unwrap_or_default(),
-        option_env!("VERGEN_BUILD_TIMESTAMP").unwrap_or_default()
-    )
+fn version_info() -> String {
+    common::stringify_version_info(&version())
 }
 
 #[derive(Parser, Default, Debug)]
 struct Arguments {
     #[clap(default_value = "pw_config.json")]
     config_file: String,
-    #[clap(default_value = "1")]
-    group: String,
+    /// Group of the machine, overrides the group in the service configuration. Default 1.
+    group: Option<String>,
+    /// pwservice settings (TOML or JSON), reloaded on SIGHUP.
+    #[clap(long)]
+    service_config: Option<String>,
 }
 
 #[tokio::main]
@@ -37,10 +63,29 @@ async fn main() {
 
     info!("Starting {}", version_info());
 
+    if let Some(group) = args.group.as_ref().filter(|group| !valid_chunk(group)) {
+        error!(
+            "Invalid group '{}', it is a chunk of the key expressions",
+            group
+        );
+        std::process::exit(1);
+    }
+
+    let config = match &args.service_config {
+        Some(path) => Config::load(path).unwrap_or_else(|err| {
+            error!("{}", err);
+            std::process::exit(1);
+        }),
+        None => Config::default(),
+    };
+
     let machine = LinuxMachine::new().expect("Failed to load system information");
 
     let mut communicator =
-        ZenohCommunicator::new(&args.config_file, &args.group, machine.mac()).await;
+        ZenohCommunicator::new(&args.config_file, config, args.service_config, args.group).await;
 
-    communicator.run(&machine).await;
+    if let Err(err) = communicator.run(&machine).await {
+        error!("{}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,540 @@
+use super::utils::read_lines;
+use common::pw::messages::{Cgroup, CgroupUsage, Container, Namespaces};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Mount point of cgroup2 relative to sysfs, "unified" on hybrid (v1 and v2) machines.
+const CGROUP2_MOUNTS: [&str; 2] = ["fs/cgroup", "fs/cgroup/unified"];
+
+/// Unit and container cgroups are not nested deeper than this in practice.
+const MAX_CGROUP_DEPTH: usize = 8;
+
+/// systemd scope name prefixes of container runtimes, e.g. "docker-<id>.scope".
+const CONTAINER_SCOPES: [(&str, &str); 4] = [
+    ("docker-", "docker"),
+    ("cri-containerd-", "containerd"),
+    ("crio-", "cri-o"),
+    ("libpod-", "podman"),
+];
+
+/// Control groups of the process with /proc/<pid> at proc_pid.
+pub fn cgroups(proc_pid: impl AsRef<Path>) -> Vec<Cgroup> {
+    let Ok(lines) = read_lines(proc_pid.as_ref().join("cgroup")) else {
+        return vec![];
+    };
+
+    parse_cgroup(lines)
+}
+
+/// Parse /proc/<pid>/cgroup, "hierarchy-ID:controller-list:cgroup-path".
+fn parse_cgroup(lines: Vec<String>) -> Vec<Cgroup> {
+    let mut cgroups = vec![];
+
+    for line in lines {
+        let mut fields = line.splitn(3, ':');
+        let (Some(hierarchy), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        let Ok(hierarchy) = hierarchy.parse() else {
+            continue;
+        };
+
+        cgroups.push(Cgroup {
+            hierarchy,
+            controllers: controllers.to_string(),
+            path: path.to_string(),
+        });
+    }
+
+    cgroups
+}
+
+/// The cgroup v2 path, the systemd hierarchy on v1 only machines.
+pub fn primary_cgroup(cgroups: &[Cgroup]) -> String {
+    let unified = cgroups
+        .iter()
+        .find(|cgroup| cgroup.hierarchy == 0 && cgroup.controllers.is_empty());
+    let systemd = cgroups
+        .iter()
+        .find(|cgroup| cgroup.controllers == "name=systemd");
+
+    unified
+        .or(systemd)
+        .or(cgroups.first())
+        .map(|cgroup| cgroup.path.clone())
+        .unwrap_or_default()
+}
+
+/// Container of a process from its cgroups, the innermost match of any hierarchy wins.
+pub fn container(cgroups: &[Cgroup]) -> Option<Container> {
+    cgroups
+        .iter()
+        .find_map(|cgroup| container_of_path(&cgroup.path))
+}
+
+/// Recognise the naming of the common runtimes, with the systemd and the cgroupfs cgroup drivers:
+/// "/system.slice/docker-<id>.scope", "/docker/<id>", "/kubepods/burstable/pod<uid>/<id>",
+/// ".../libpod-<id>.scope/container" and "/lxc.payload.<name>".
+fn container_of_path(path: &str) -> Option<Container> {
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+
+    for (i, component) in components.iter().enumerate().rev() {
+        let name = component.strip_suffix(".scope").unwrap_or(component);
+
+        for (prefix, runtime) in CONTAINER_SCOPES {
+            if let Some(id) = name.strip_prefix(prefix) {
+                if is_container_id(id) {
+                    return Some(new_container(runtime, id));
+                }
+            }
+        }
+
+        if is_container_id(name) {
+            let parents = &components[..i];
+            if parents.last() == Some(&"docker") {
+                return Some(new_container("docker", name));
+            }
+            if parents.iter().any(|parent| parent.starts_with("kubepods")) {
+                return Some(new_container("kubernetes", name));
+            }
+        }
+
+        if let Some(id) = name.strip_prefix("lxc.payload.") {
+            return Some(new_container("lxc", id));
+        }
+        if i == 1 && components[0] == "lxc" {
+            return Some(new_container("lxc", name));
+        }
+    }
+
+    None
+}
+
+fn new_container(runtime: &str, id: &str) -> Container {
+    Container {
+        runtime: runtime.to_string(),
+        id: id.to_string(),
+    }
+}
+
+/// Full container ids are 64 hex digits.
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The systemd unit (service or scope) of a cgroup path, e.g. "ssh.service".
+/// For user services this is the innermost unit, not "user@1000.service".
+pub fn unit(path: &str) -> String {
+    path.rsplit('/')
+        .find(|component| component.ends_with(".service") || component.ends_with(".scope"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Namespace ids of the process with /proc/<pid> at proc_pid, zero for namespaces we may not read.
+pub fn namespaces(proc_pid: impl AsRef<Path>) -> Namespaces {
+    let ns = proc_pid.as_ref().join("ns");
+    let id = |name: &str| {
+        fs::read_link(ns.join(name))
+            .ok()
+            .and_then(|target| namespace_id(&target.to_string_lossy()))
+            .unwrap_or_default()
+    };
+
+    Namespaces {
+        cgroup: id("cgroup"),
+        ipc: id("ipc"),
+        mnt: id("mnt"),
+        net: id("net"),
+        pid: id("pid"),
+        user: id("user"),
+        uts: id("uts"),
+    }
+}
+
+/// Inode of a "net:[4026531840]" link target.
+fn namespace_id(target: &str) -> Option<u64> {
+    let (_, id) = target.split_once(":[")?;
+    id.strip_suffix(']')?.parse().ok()
+}
+
+/// Cumulative counters of a cgroup, needed to calculate rates.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct CgroupCounters {
+    cpu_usage_usec: u64,
+    io_read_bytes: u64,
+    io_written_bytes: u64,
+}
+
+/// Keeps the previous sample of each cgroup to calculate cpu and I/O rates.
+#[derive(Default)]
+pub struct CgroupCollector {
+    previous: HashMap<String, CgroupCounters>,
+    previous_time: Option<Instant>,
+}
+
+impl CgroupCollector {
+    /// Usage of all unit and container cgroups in the cgroup2 hierarchy of <sys>.
+    pub fn usage(&mut self, sys: impl AsRef<Path>) -> Vec<CgroupUsage> {
+        let Some(root) = cgroup2_root(sys) else {
+            return vec![];
+        };
+
+        let now = Instant::now();
+        let elapsed = self
+            .previous_time
+            .map(|previous| now.duration_since(previous).as_secs_f64());
+
+        let mut previous = HashMap::new();
+        let mut cgroups = vec![];
+
+        for path in discover(&root) {
+            let mut usage = cgroup_usage(&root, &path);
+            let counters = CgroupCounters {
+                cpu_usage_usec: usage.cpu_usage_usec,
+                io_read_bytes: usage.io_read_bytes,
+                io_written_bytes: usage.io_written_bytes,
+            };
+
+            if let (Some(last), Some(elapsed)) = (self.previous.get(&path), elapsed) {
+                set_rates(&mut usage, last, elapsed);
+            }
+
+            previous.insert(path, counters);
+            cgroups.push(usage);
+        }
+
+        self.previous = previous;
+        self.previous_time = Some(now);
+
+        cgroups
+    }
+}
+
+fn set_rates(usage: &mut CgroupUsage, previous: &CgroupCounters, elapsed: f64) {
+    if elapsed <= 0.0 {
+        return;
+    }
+
+    let rate = |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+
+    usage.cpu_percent = rate(usage.cpu_usage_usec, previous.cpu_usage_usec) / 10_000.0;
+    usage.io_read_bytes_per_second = rate(usage.io_read_bytes, previous.io_read_bytes) as u64;
+    usage.io_written_bytes_per_second =
+        rate(usage.io_written_bytes, previous.io_written_bytes) as u64;
+}
+
+/// The cgroup2 mount, None on machines with cgroup v1 only.
+fn cgroup2_root(sys: impl AsRef<Path>) -> Option<PathBuf> {
+    CGROUP2_MOUNTS
+        .iter()
+        .map(|mount| sys.as_ref().join(mount))
+        .find(|root| root.join("cgroup.controllers").exists())
+}
+
+/// Paths (relative to root, starting with "/") of all cgroups that are a systemd unit or a container.
+fn discover(root: &Path) -> Vec<String> {
+    let mut found = vec![];
+    let mut pending = vec![(String::new(), 0)];
+
+    while let Some((path, depth)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(root.join(path.trim_start_matches('/'))) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                continue;
+            }
+
+            let child = format!("{}/{}", path, entry.file_name().to_string_lossy());
+            // Children of a unit or container (e.g. "<container>/init") are counted in it already.
+            if !unit(&child).is_empty() || container_of_path(&child).is_some() {
+                found.push(child);
+            } else if depth < MAX_CGROUP_DEPTH {
+                pending.push((child, depth + 1));
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+/// Read the interface files of cgroup path, files of disabled controllers are left at zero.
+fn cgroup_usage(root: &Path, path: &str) -> CgroupUsage {
+    let directory = root.join(path.trim_start_matches('/'));
+    let read = |name: &str| read_lines(directory.join(name)).unwrap_or_default();
+
+    let cpu = parse_flat_keyed(read("cpu.stat"));
+    let events = parse_flat_keyed(read("memory.events"));
+    let (io_read_bytes, io_written_bytes) = parse_io_stat(read("io.stat"));
+
+    CgroupUsage {
+        path: path.to_string(),
+        container: container_of_path(path),
+        unit: unit(path),
+        cpu_usage_usec: cpu.get("usage_usec").copied().unwrap_or_default(),
+        cpu_nr_throttled: cpu.get("nr_throttled").copied().unwrap_or_default(),
+        cpu_throttled_usec: cpu.get("throttled_usec").copied().unwrap_or_default(),
+        memory_current: parse_limit(read("memory.current")).unwrap_or_default(),
+        memory_max: parse_limit(read("memory.max")).unwrap_or(u64::MAX),
+        memory_max_events: events.get("max").copied().unwrap_or_default(),
+        oom: events.get("oom").copied().unwrap_or_default(),
+        oom_kill: events.get("oom_kill").copied().unwrap_or_default(),
+        io_read_bytes,
+        io_written_bytes,
+        ..Default::default()
+    }
+}
+
+/// Parse "key value" lines, e.g. cpu.stat and memory.events.
+fn parse_flat_keyed(lines: Vec<String>) -> HashMap<String, u64> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// A single number, or "max" for no limit.
+fn parse_limit(lines: Vec<String>) -> Option<u64> {
+    let line = lines.first()?.trim();
+    if line == "max" {
+        return Some(u64::MAX);
+    }
+
+    line.parse().ok()
+}
+
+/// Read and written bytes of all devices in io.stat, "8:0 rbytes=1048576 wbytes=0 rios=10 ...".
+fn parse_io_stat(lines: Vec<String>) -> (u64, u64) {
+    let mut read = 0;
+    let mut written = 0;
+
+    for line in &lines {
+        for word in line.split_whitespace().skip(1) {
+            match word.split_once('=') {
+                Some(("rbytes", value)) => read += value.parse::<u64>().unwrap_or_default(),
+                Some(("wbytes", value)) => written += value.parse::<u64>().unwrap_or_default(),
+                _ => {}
+            }
+        }
+    }
+
+    (read, written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const DOCKER_ID: &str = "3f2a4b1c9d8e7f60a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2";
+
+    fn proc() -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc");
+        path
+    }
+
+    #[test]
+    fn cgroup_v1() {
+        let cgroups = cgroups(proc().join("822"));
+
+        assert_eq!(cgroups.len(), 6);
+        assert_eq!(cgroups[3].hierarchy, 4);
+        assert_eq!(cgroups[3].controllers, "cpu,cpuacct");
+        assert_eq!(cgroups[3].path, format!("/docker/{}", DOCKER_ID));
+
+        // Hybrid hierarchy, the unified path is the primary one.
+        assert_eq!(primary_cgroup(&cgroups), "/system.slice/containerd.service");
+        assert_eq!(
+            container(&cgroups),
+            Some(new_container("docker", DOCKER_ID))
+        );
+    }
+
+    #[test]
+    fn cgroup_v2() {
+        let cgroups = cgroups(proc().join("1043"));
+        let path = primary_cgroup(&cgroups);
+
+        assert_eq!(cgroups.len(), 1);
+        assert_eq!(
+            container(&cgroups),
+            Some(new_container(
+                "podman",
+                "9b1e5c3a7d2f4e6b8a0c1d3e5f7a9b2c4d6e8f0a1b3c5d7e9f2a4b6c8d0e1f3a"
+            ))
+        );
+        assert_eq!(
+            unit(&path),
+            "libpod-9b1e5c3a7d2f4e6b8a0c1d3e5f7a9b2c4d6e8f0a1b3c5d7e9f2a4b6c8d0e1f3a.scope"
+        );
+    }
+
+    #[test]
+    fn container_paths() {
+        let id = DOCKER_ID;
+        let runtime = |path: &str| container_of_path(path).map(|container| container.runtime);
+
+        assert_eq!(
+            runtime(&format!("/system.slice/docker-{}.scope", id)).as_deref(),
+            Some("docker")
+        );
+        assert_eq!(
+            runtime(&format!(
+                "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1.slice/cri-containerd-{}.scope",
+                id
+            ))
+            .as_deref(),
+            Some("containerd")
+        );
+        assert_eq!(
+            runtime(&format!("/machine.slice/crio-{}.scope", id)).as_deref(),
+            Some("cri-o")
+        );
+        assert_eq!(
+            runtime(&format!("/kubepods/besteffort/pod1234/{}", id)).as_deref(),
+            Some("kubernetes")
+        );
+        assert_eq!(
+            container_of_path("/lxc.payload.web/system.slice/nginx.service"),
+            Some(new_container("lxc", "web"))
+        );
+        assert_eq!(
+            container_of_path("/lxc/db"),
+            Some(new_container("lxc", "db"))
+        );
+        assert_eq!(runtime("/system.slice/docker.service"), None);
+        assert_eq!(runtime(&format!("/{}", id)), None);
+        assert_eq!(runtime("/"), None);
+    }
+
+    #[test]
+    fn unit_ok() {
+        assert_eq!(unit("/system.slice/ssh.service"), "ssh.service");
+        assert_eq!(
+            unit("/user.slice/user-1000.slice/user@1000.service/app.slice/pipewire.service"),
+            "pipewire.service"
+        );
+        assert_eq!(
+            unit("/user.slice/user-1000.slice/session-2.scope"),
+            "session-2.scope"
+        );
+        assert_eq!(unit("/init.scope"), "init.scope");
+        assert_eq!(unit("/"), "");
+    }
+
+    fn sys() -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/sys");
+        path
+    }
+
+    #[test]
+    fn discover_units() {
+        let root = cgroup2_root(sys()).expect("No cgroup2 root");
+
+        let expected = vec![
+            "/init.scope".to_string(),
+            format!("/system.slice/docker-{}.scope", DOCKER_ID),
+            "/system.slice/ssh.service".to_string(),
+            "/user.slice/user-1000.slice/session-2.scope".to_string(),
+        ];
+
+        // Not the "init" cgroup inside the container.
+        assert_eq!(discover(&root), expected);
+        assert_eq!(cgroup2_root("/tmp/dontexists"), None);
+    }
+
+    #[test]
+    fn usage_container() {
+        let root = cgroup2_root(sys()).expect("No cgroup2 root");
+        let path = format!("/system.slice/docker-{}.scope", DOCKER_ID);
+
+        let expected = CgroupUsage {
+            path: path.clone(),
+            container: Some(new_container("docker", DOCKER_ID)),
+            unit: format!("docker-{}.scope", DOCKER_ID),
+            cpu_usage_usec: 98342110,
+            cpu_nr_throttled: 812,
+            cpu_throttled_usec: 40211000,
+            memory_current: 534773760,
+            memory_max: 536870912,
+            memory_max_events: 1432,
+            oom: 3,
+            oom_kill: 2,
+            io_read_bytes: 10485760 + 1048576,
+            io_written_bytes: 4096 + 2097152,
+            ..Default::default()
+        };
+
+        assert_eq!(cgroup_usage(&root, &path), expected);
+    }
+
+    #[test]
+    fn usage_unlimited() {
+        let root = cgroup2_root(sys()).expect("No cgroup2 root");
+
+        let ssh = cgroup_usage(&root, "/system.slice/ssh.service");
+        assert_eq!(ssh.memory_max, u64::MAX);
+        assert_eq!(ssh.container, None);
+
+        // No memory controller, no io.stat.
+        let init = cgroup_usage(&root, "/init.scope");
+        assert_eq!(init.cpu_usage_usec, 9000000);
+        assert_eq!(init.memory_current, 0);
+        assert_eq!(init.memory_max, u64::MAX);
+        assert_eq!(init.io_read_bytes, 0);
+    }
+
+    #[test]
+    fn usage_rates() {
+        let previous = CgroupCounters {
+            cpu_usage_usec: 1_000_000,
+            io_read_bytes: 4096,
+            io_written_bytes: 0,
+        };
+        let mut usage = CgroupUsage {
+            cpu_usage_usec: 4_000_000,
+            io_read_bytes: 8192,
+            ..Default::default()
+        };
+
+        set_rates(&mut usage, &previous, 2.0);
+
+        assert_eq!(usage.cpu_percent, 150.0);
+        assert_eq!(usage.io_read_bytes_per_second, 2048);
+        assert_eq!(usage.io_written_bytes_per_second, 0);
+
+        let mut collector = CgroupCollector::default();
+        assert_eq!(collector.usage(sys()).len(), 4);
+        assert!(collector
+            .usage(sys())
+            .iter()
+            .all(|usage| usage.cpu_percent == 0.0));
+    }
+
+    #[test]
+    fn namespaces_self() {
+        let namespaces = namespaces("/proc/self");
+
+        assert!(namespaces.net > 0);
+        assert!(namespaces.mnt > 0);
+        assert_eq!(namespace_id("net:[4026531840]"), Some(4026531840));
+        assert_eq!(namespace_id("socket:[27410]"), Some(27410));
+        assert_eq!(namespace_id("/dev/null"), None);
+    }
+}
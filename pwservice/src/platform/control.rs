@@ -0,0 +1,282 @@
+use super::error::{machine_error, MachineError};
+use super::utils::{parse_lines, read_lines};
+use common::pw::messages::{signal_request::Signo, ProcessControl};
+use std::{fs, io};
+
+/// Processes a client must never be able to touch, whatever the configuration allows.
+fn check_target(pid: i32) -> Result<(), MachineError> {
+    // 0 and negative pids address process groups, 1 is init.
+    if pid <= 1 {
+        return Err(machine_error!("Refusing to change pid {}", pid));
+    }
+    // Threads have a /proc/<tid> too, and kill(tid) signals the whole thread group.
+    let Some(tgid) = read_lines(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(parse_tgid)
+    else {
+        return Err(machine_error!("No process {}", pid));
+    };
+    if tgid == std::process::id() as i32 {
+        return Err(machine_error!(
+            "Refusing to change pid {}, it is pwservice",
+            pid
+        ));
+    }
+
+    Ok(())
+}
+
+/// Thread group id (the pid of the process) from /proc/<pid>/status.
+fn parse_tgid(lines: Vec<String>) -> Option<i32> {
+    lines
+        .iter()
+        .find_map(|line| line.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse().ok())
+}
+
+pub fn signal_number(signal: Signo) -> libc::c_int {
+    match signal {
+        Signo::Term => libc::SIGTERM,
+        Signo::Kill => libc::SIGKILL,
+        Signo::Hup => libc::SIGHUP,
+        Signo::Stop => libc::SIGSTOP,
+        Signo::Cont => libc::SIGCONT,
+    }
+}
+
+pub fn send_signal(pid: i32, signal: Signo) -> Result<(), MachineError> {
+    check_target(pid)?;
+
+    // Safe, kill takes no pointers.
+    if unsafe { libc::kill(pid, signal_number(signal)) } != 0 {
+        return Err(machine_error!(
+            "Could not send SIG{} to {} ({})",
+            signal.as_str_name().to_uppercase(),
+            pid,
+            io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// setpriority only changes one thread on Linux, renice all of them like renice(1) does for a pid.
+pub fn renice(pid: i32, nice: i32) -> Result<(), MachineError> {
+    if !(-20..=19).contains(&nice) {
+        return Err(machine_error!("Nice value {} not in -20..19", nice));
+    }
+    check_target(pid)?;
+
+    for tid in threads(pid)? {
+        // Safe, setpriority takes no pointers.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) } != 0 {
+            return Err(machine_error!(
+                "Could not renice thread {} of {} ({})",
+                tid,
+                pid,
+                io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// sched_setaffinity only changes one thread, set all of them like taskset -a does.
+pub fn set_affinity(pid: i32, cpus: &[u32]) -> Result<(), MachineError> {
+    if cpus.is_empty() {
+        return Err(machine_error!("No CPUs given"));
+    }
+    check_target(pid)?;
+
+    // Safe, an all zero cpu_set_t is an empty set.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        if *cpu as usize >= libc::CPU_SETSIZE as usize {
+            return Err(machine_error!("CPU {} out of range", cpu));
+        }
+        // Safe, the cpu is inside the set.
+        unsafe { libc::CPU_SET(*cpu as usize, &mut set) };
+    }
+
+    for tid in threads(pid)? {
+        // Safe, set is a valid cpu_set_t of the size we pass.
+        let result =
+            unsafe { libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+        if result != 0 {
+            return Err(machine_error!(
+                "Could not set affinity of thread {} of {} ({})",
+                tid,
+                pid,
+                io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// State, nice value and allowed CPUs of a process, only the pid if it is gone.
+pub fn process_control(pid: i32) -> ProcessControl {
+    let mut control = ProcessControl {
+        pid,
+        ..Default::default()
+    };
+
+    let proc_pid = format!("/proc/{}", pid);
+
+    if let Some((state, nice)) = read_lines(format!("{}/stat", proc_pid))
+        .ok()
+        .and_then(|lines| parse_state_nice(lines.first()?))
+    {
+        control.state = state;
+        control.nice = nice;
+    }
+
+    if let Ok(lines) = read_lines(format!("{}/status", proc_pid)) {
+        control.cpus = parse_cpus_allowed(lines);
+    }
+
+    control
+}
+
+fn threads(pid: i32) -> Result<Vec<i32>, MachineError> {
+    let entries = fs::read_dir(format!("/proc/{}/task", pid))
+        .map_err(|e| machine_error!("Could not read threads of {} ({})", pid, e))?;
+
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_string_lossy().parse().ok())
+        .collect())
+}
+
+/// State and nice value from "822 (my app) S 1 822 ...", the fields after the name are counted.
+fn parse_state_nice(line: &str) -> Option<(String, i32)> {
+    let end = line.rfind(')')?;
+    let fields: Vec<&str> = line[end + 1..].split_whitespace().collect();
+
+    // state is field 3 and nice field 19 of stat(5), the first two are before the fields we split.
+    Some((fields.first()?.to_string(), fields.get(16)?.parse().ok()?))
+}
+
+/// "Cpus_allowed_list: 0-3,6" from /proc/<pid>/status.
+fn parse_cpus_allowed(lines: Vec<String>) -> Vec<u32> {
+    let elements = vec![("Cpus_allowed_list:", false)];
+    let lines = parse_lines(lines, elements, true);
+
+    lines
+        .first()
+        .map(|list| parse_cpu_list(list))
+        .unwrap_or_default()
+}
+
+pub fn parse_cpu_list(list: &str) -> Vec<u32> {
+    let mut cpus = vec![];
+
+    for range in list
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+    {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+                    cpus.extend(first..=last);
+                }
+            }
+            None => cpus.extend(range.parse::<u32>()),
+        }
+    }
+
+    cpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn state_nice() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/stat");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_state_nice(&lines[0]), Some(("S".to_string(), 0)));
+        assert_eq!(
+            parse_state_nice("7 (a) b) R 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 -5 1"),
+            Some(("R".to_string(), -5))
+        );
+    }
+
+    #[test]
+    fn cpus_allowed() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/status");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_cpus_allowed(lines), vec![0]);
+        assert_eq!(parse_cpu_list("0-3,6"), vec![0, 1, 2, 3, 6]);
+        assert_eq!(parse_cpu_list(""), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn refuse_targets() {
+        assert!(send_signal(0, Signo::Term).is_err());
+        assert!(send_signal(-1, Signo::Kill).is_err());
+        assert!(send_signal(1, Signo::Term).is_err());
+        assert!(renice(std::process::id() as i32, 5).is_err());
+        assert!(renice(2, 20).is_err());
+        assert!(set_affinity(2, &[]).is_err());
+
+        // One of our own threads, signalling it would signal pwservice.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            // Safe, gettid takes no arguments.
+            sender.send(unsafe { libc::gettid() }).unwrap();
+            std::thread::park();
+        });
+        let tid = receiver.recv().unwrap();
+        assert_ne!(tid, std::process::id() as i32);
+        let err = check_target(tid).unwrap_err();
+        assert!(err.message.contains("it is pwservice"), "{}", err.message);
+        thread.thread().unpark();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn tgid() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/status");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_tgid(lines), Some(822));
+        assert_eq!(parse_tgid(vec![]), None);
+    }
+
+    #[test]
+    fn control_child() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .expect("Could not start sleep");
+        let pid = child.id() as i32;
+
+        renice(pid, 10).expect("Could not renice");
+        set_affinity(pid, &[0]).expect("Could not set affinity");
+
+        let control = process_control(pid);
+        assert_eq!(control.nice, 10);
+        assert_eq!(control.cpus, vec![0]);
+
+        send_signal(pid, Signo::Stop).expect("Could not stop");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(process_control(pid).state, "T");
+
+        send_signal(pid, Signo::Kill).expect("Could not kill");
+        child.wait().expect("Could not wait");
+    }
+}
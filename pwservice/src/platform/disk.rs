@@ -0,0 +1,410 @@
+use super::utils::read_lines;
+use common::pw::messages::{BlockDevice, Filesystem};
+use std::{collections::HashMap, ffi::CString, fs, path::Path, time::Instant};
+
+/// Sectors in /proc/diskstats are always 512 bytes, regardless of the device.
+const SECTOR_SIZE: u64 = 512;
+
+/// Devices in /proc/diskstats that are not interesting for storage health.
+const IGNORED_DEVICES: [&str; 3] = ["loop", "ram", "zram"];
+
+/// Block devices layered on top of other devices (or memory), not counted as storage.
+const VIRTUAL_BLOCK_DEVICES: [&str; 5] = ["loop", "ram", "zram", "dm-", "md"];
+
+/// Filesystems served over the network, statvfs on them blocks as long as the server does not answer.
+const NETWORK_FS_TYPES: [&str; 12] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "afs",
+    "lustre",
+    "davfs",
+    "ncpfs",
+];
+
+/// FUSE daemons backed by a remote server, mounted as "fuse.<name>". Local ones like ntfs-3g ("fuseblk") or
+/// the Android /sdcard emulation ("fuse") answer as long as the machine does.
+const NETWORK_FUSE_TYPES: [&str; 10] = [
+    "sshfs",
+    "s3fs",
+    "rclone",
+    "gcsfuse",
+    "goofys",
+    "curlftpfs",
+    "glusterfs",
+    "ceph-fuse",
+    "juicefs",
+    "blobfuse2",
+];
+
+/// Cumulative counters of a block device from /proc/diskstats.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct DiskCounters {
+    reads: u64,
+    read_sectors: u64,
+    writes: u64,
+    written_sectors: u64,
+    io_ms: u64,
+}
+
+/// Keeps the previous /proc/diskstats sample to calculate I/O rates.
+#[derive(Default)]
+pub struct DiskCollector {
+    previous: HashMap<String, DiskCounters>,
+    previous_time: Option<Instant>,
+}
+
+impl DiskCollector {
+    pub fn block_devices(&mut self) -> Vec<BlockDevice> {
+        let Ok(lines) = read_lines("/proc/diskstats") else {
+            return vec![];
+        };
+
+        let now = Instant::now();
+        let elapsed = self
+            .previous_time
+            .map(|previous| now.duration_since(previous).as_secs_f64());
+
+        let counters = parse_diskstats(lines);
+        let devices = counters
+            .iter()
+            .map(|(name, current)| {
+                let previous = self.previous.get(name);
+                block_device(name, current, previous.zip(elapsed))
+            })
+            .collect();
+
+        self.previous = counters.into_iter().collect();
+        self.previous_time = Some(now);
+
+        devices
+    }
+}
+
+fn block_device(
+    name: &str,
+    current: &DiskCounters,
+    previous: Option<(&DiskCounters, f64)>,
+) -> BlockDevice {
+    let mut device = BlockDevice {
+        name: name.to_string(),
+        read_bytes: current.read_sectors * SECTOR_SIZE,
+        written_bytes: current.written_sectors * SECTOR_SIZE,
+        ..Default::default()
+    };
+
+    if let Some((previous, elapsed)) = previous {
+        if elapsed > 0.0 {
+            let rate =
+                |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+
+            device.read_bytes_per_second =
+                (rate(current.read_sectors, previous.read_sectors) * SECTOR_SIZE as f64) as u64;
+            device.written_bytes_per_second =
+                (rate(current.written_sectors, previous.written_sectors) * SECTOR_SIZE as f64)
+                    as u64;
+            device.reads_per_second = rate(current.reads, previous.reads);
+            device.writes_per_second = rate(current.writes, previous.writes);
+            // io_ms is milliseconds spent doing I/O, as percentage of elapsed time.
+            device.utilization = (rate(current.io_ms, previous.io_ms) / 10.0).min(100.0);
+        }
+    }
+
+    device
+}
+
+/// Parse /proc/diskstats, unused and virtual devices are skipped.
+fn parse_diskstats(lines: Vec<String>) -> Vec<(String, DiskCounters)> {
+    let mut devices = vec![];
+
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 14 {
+            continue;
+        }
+
+        let name = words[2];
+        if IGNORED_DEVICES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            continue;
+        }
+
+        let number = |i: usize| words[i].parse::<u64>().unwrap_or_default();
+        let counters = DiskCounters {
+            reads: number(3),
+            read_sectors: number(5),
+            writes: number(7),
+            written_sectors: number(9),
+            io_ms: number(12),
+        };
+
+        if counters.reads == 0 && counters.writes == 0 {
+            continue;
+        }
+
+        devices.push((name.to_string(), counters));
+    }
+
+    devices
+}
+
+/// Total size of all physical block devices in <sys>/block.
+pub fn storage_total_bytes(sys: impl AsRef<Path>) -> u64 {
+    let Ok(entries) = fs::read_dir(sys.as_ref().join("block")) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            !VIRTUAL_BLOCK_DEVICES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        })
+        .filter_map(|entry| {
+            let lines = read_lines(entry.path().join("size")).ok()?;
+            lines.first()?.trim().parse::<u64>().ok()
+        })
+        .map(|sectors| sectors * SECTOR_SIZE)
+        .sum()
+}
+
+/// Mounted filesystems with usage, pseudo filesystems without blocks (proc, sysfs, ...) are skipped.
+/// Network filesystems are skipped too, including FUSE ones, a hung server would block the sample forever.
+pub fn filesystems() -> Vec<Filesystem> {
+    let Ok(lines) = read_lines("/proc/self/mountinfo") else {
+        return vec![];
+    };
+
+    parse_mountinfo(lines)
+        .into_iter()
+        .filter(|filesystem| !may_hang(&filesystem.fs_type))
+        .filter_map(|mut filesystem| {
+            let stat = statvfs(&filesystem.mount_point)?;
+            if stat.blocks == 0 {
+                return None;
+            }
+
+            filesystem.total_bytes = stat.blocks * stat.fragment_size;
+            filesystem.free_bytes = stat.free_blocks * stat.fragment_size;
+            filesystem.available_bytes = stat.available_blocks * stat.fragment_size;
+            filesystem.total_inodes = stat.files;
+            filesystem.free_inodes = stat.free_files;
+
+            Some(filesystem)
+        })
+        .collect()
+}
+
+/// Whether statvfs may wait on a remote server, e.g. "nfs4" or "fuse.sshfs".
+fn may_hang(fs_type: &str) -> bool {
+    NETWORK_FS_TYPES.contains(&fs_type)
+        || fs_type
+            .strip_prefix("fuse.")
+            .is_some_and(|subtype| NETWORK_FUSE_TYPES.contains(&subtype))
+}
+
+/// Parse /proc/self/mountinfo, e.g.
+/// "15 1 179:2 / / rw,noatime shared:1 - ext4 /dev/root rw"
+fn parse_mountinfo(lines: Vec<String>) -> Vec<Filesystem> {
+    let mut filesystems = vec![];
+
+    for line in lines {
+        // Optional fields end with a single "-".
+        let Some((mount, source)) = line.split_once(" - ") else {
+            continue;
+        };
+
+        let mount: Vec<&str> = mount.split_whitespace().collect();
+        let source: Vec<&str> = source.split_whitespace().collect();
+        if mount.len() < 6 || source.len() < 2 {
+            continue;
+        }
+
+        filesystems.push(Filesystem {
+            mount_point: unescape(mount[4]),
+            device: unescape(source[1]),
+            fs_type: source[0].to_string(),
+            read_only: mount[5].split(',').any(|option| option == "ro"),
+            ..Default::default()
+        });
+    }
+
+    filesystems
+}
+
+/// The kernel escapes space, tab, newline and backslash as octal, e.g. "\040".
+fn unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(i) = rest.find('\\') {
+        output.push_str(&rest[..i]);
+
+        let escaped = rest.get(i + 1..i + 4);
+        match escaped.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(c) => {
+                output.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                output.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// The fields we need from libc::statvfs, widened to u64 on 32 bit targets.
+struct FilesystemStat {
+    fragment_size: u64,
+    blocks: u64,
+    free_blocks: u64,
+    available_blocks: u64,
+    files: u64,
+    free_files: u64,
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn statvfs(path: &str) -> Option<FilesystemStat> {
+    let path = CString::new(path).ok()?;
+
+    // Safe, path is a valid C string and stat is only read if statvfs succeeds.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    Some(FilesystemStat {
+        fragment_size: stat.f_frsize as u64,
+        blocks: stat.f_blocks as u64,
+        free_blocks: stat.f_bfree as u64,
+        available_blocks: stat.f_bavail as u64,
+        files: stat.f_files as u64,
+        free_files: stat.f_ffree as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn mountinfo() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/self/mountinfo");
+
+        let lines = read_lines(path).expect("Could not read");
+        let filesystems = parse_mountinfo(lines);
+
+        assert_eq!(filesystems.len(), 7);
+        assert_eq!(filesystems[0].mount_point, "/");
+        assert_eq!(filesystems[0].device, "/dev/root");
+        assert_eq!(filesystems[0].fs_type, "ext4");
+        assert!(!filesystems[0].read_only);
+        assert_eq!(filesystems[5].mount_point, "/boot/firmware");
+        assert_eq!(filesystems[6].mount_point, "/media/usb stick");
+        assert!(filesystems[6].read_only);
+    }
+
+    #[test]
+    fn unescape_ok() {
+        assert_eq!(unescape("/media/usb\\040stick"), "/media/usb stick");
+        assert_eq!(unescape("/a\\134b"), "/a\\b");
+        assert_eq!(unescape("/trailing\\"), "/trailing\\");
+        assert_eq!(unescape("/plain"), "/plain");
+    }
+
+    #[test]
+    fn diskstats() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/diskstats");
+
+        let lines = read_lines(path).expect("Could not read");
+        let devices = parse_diskstats(lines);
+
+        let names: Vec<&str> = devices.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["mmcblk0", "mmcblk0p1", "mmcblk0p2"]);
+
+        let expected = DiskCounters {
+            reads: 9817,
+            read_sectors: 800742,
+            writes: 38164,
+            written_sectors: 1653416,
+            io_ms: 201960,
+        };
+        assert_eq!(devices[0].1, expected);
+    }
+
+    #[test]
+    fn block_device_rates() {
+        let previous = DiskCounters {
+            reads: 100,
+            read_sectors: 1000,
+            writes: 50,
+            written_sectors: 2000,
+            io_ms: 1000,
+        };
+        let current = DiskCounters {
+            reads: 120,
+            read_sectors: 3000,
+            writes: 60,
+            written_sectors: 2000,
+            io_ms: 2000,
+        };
+
+        let first = block_device("mmcblk0", &current, None);
+        assert_eq!(first.read_bytes, 3000 * 512);
+        assert_eq!(first.read_bytes_per_second, 0);
+
+        let device = block_device("mmcblk0", &current, Some((&previous, 2.0)));
+        assert_eq!(device.read_bytes_per_second, 512000);
+        assert_eq!(device.written_bytes_per_second, 0);
+        assert_eq!(device.reads_per_second, 10.0);
+        assert_eq!(device.writes_per_second, 5.0);
+        assert_eq!(device.utilization, 50.0);
+    }
+
+    #[test]
+    fn storage_total() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/sys");
+
+        assert_eq!(storage_total_bytes(path), (62333952 + 61440000) * 512);
+    }
+
+    #[test]
+    fn hanging_filesystems() {
+        assert!(may_hang("nfs4"));
+        assert!(may_hang("cifs"));
+        assert!(may_hang("fuse.sshfs"));
+        assert!(may_hang("fuse.rclone"));
+        assert!(!may_hang("fuse"));
+        assert!(!may_hang("fuseblk"));
+        assert!(!may_hang("fuse.mergerfs"));
+        assert!(!may_hang("ext4"));
+        assert!(!may_hang("tmpfs"));
+    }
+
+    #[test]
+    fn filesystems_root() {
+        let filesystems = filesystems();
+        let root = filesystems.iter().find(|fs| fs.mount_point == "/");
+
+        assert!(root.is_some_and(|root| root.total_bytes > 0));
+        assert!(!filesystems.iter().any(|fs| fs.fs_type == "proc"));
+    }
+}
@@ -17,3 +17,16 @@ impl fmt::Display for MachineError {
 
 /// Must implement Error, default implementations are fine.
 impl std::error::Error for MachineError {}
+
+/// Create a MachineError with a formatted message and the location it was created at.
+macro_rules! machine_error {
+    ($($arg:tt)*) => {
+        $crate::platform::error::MachineError {
+            message: format!($($arg)*),
+            line: line!(),
+            column: column!(),
+        }
+    };
+}
+
+pub(crate) use machine_error;
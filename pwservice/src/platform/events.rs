@@ -0,0 +1,281 @@
+use super::error::MachineError;
+use super::kmsg::{parse_record, KmsgReader};
+use common::pw::messages::{
+    kernel_event::Event, HungTask, KernelEvent, KmsgRecord, OomKill, Segfault,
+};
+
+/// Follows the kernel log from now on and recognises records worth an alert.
+pub struct EventCollector {
+    reader: KmsgReader,
+    boottime: u64,
+    detector: EventDetector,
+}
+
+impl EventCollector {
+    /// boottime (seconds since the epoch) converts the kernel timestamps to wall clock.
+    pub fn open(boottime: u64) -> Result<Self, MachineError> {
+        Ok(Self {
+            reader: KmsgReader::open(false)?,
+            boottime,
+            detector: EventDetector::default(),
+        })
+    }
+
+    /// Events in the records logged since the last poll.
+    pub fn poll(&mut self) -> Vec<KernelEvent> {
+        let mut events = vec![];
+
+        while let Some(data) = self.reader.next_record() {
+            if let Some(event) =
+                parse_record(&data, self.boottime).and_then(|record| self.detector.detect(&record))
+            {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+}
+
+/// An OOM kill is logged as several records, remembers what we saw before "Killed process".
+#[derive(Debug, Default)]
+struct EventDetector {
+    trigger: Option<String>,
+    cgroup: Option<String>,
+    memcg: Option<String>,
+}
+
+impl EventDetector {
+    fn detect(&mut self, record: &KmsgRecord) -> Option<KernelEvent> {
+        let message = record.message.as_str();
+
+        let event = if let Some((trigger, _)) = message.split_once(" invoked oom-killer: ") {
+            self.trigger = Some(trigger.to_string());
+            None
+        } else if let Some(summary) = message.strip_prefix("oom-kill:") {
+            for (key, value) in summary.split(',').filter_map(|kv| kv.split_once('=')) {
+                match key {
+                    "task_memcg" => self.cgroup = Some(value.to_string()),
+                    "oom_memcg" => self.memcg = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            None
+        } else if message.contains("Killed process ") {
+            parse_oom_kill(message).map(|mut oom_kill| {
+                oom_kill.trigger = self.trigger.take().unwrap_or_default();
+                oom_kill.cgroup = self.cgroup.take().unwrap_or_default();
+                oom_kill.memcg = self.memcg.take().unwrap_or_default();
+                Event::OomKill(oom_kill)
+            })
+        } else if message.starts_with("INFO: task ") {
+            parse_hung_task(message).map(Event::HungTask)
+        } else {
+            parse_segfault(message).map(Event::Segfault)
+        };
+
+        event.map(|event| KernelEvent {
+            time_usec: record.time_usec,
+            message: record.message.clone(),
+            event: Some(event),
+        })
+    }
+}
+
+/// "Out of memory: Killed process 822 (my app) total-vm:1048576kB, anon-rss:524288kB, file-rss:1024kB,
+/// shmem-rss:0kB, UID:1000 pgtables:1100kB oom_score_adj:0", the name may contain spaces and parentheses.
+fn parse_oom_kill(message: &str) -> Option<OomKill> {
+    let (_, victim) = message.split_once("Killed process ")?;
+    let (pid, rest) = victim.split_once(" (")?;
+    let end = rest.find(") total-vm:").or_else(|| rest.rfind(')'))?;
+
+    let mut oom_kill = OomKill {
+        pid: pid.parse().ok()?,
+        name: rest[..end].to_string(),
+        ..Default::default()
+    };
+
+    for token in rest[end + 1..].split_whitespace() {
+        let Some((key, value)) = token.trim_end_matches(',').split_once(':') else {
+            continue;
+        };
+        let kb = || value.trim_end_matches("kB").parse().unwrap_or_default();
+
+        match key {
+            "total-vm" => oom_kill.total_vm_kb = kb(),
+            "anon-rss" => oom_kill.anon_rss_kb = kb(),
+            "file-rss" => oom_kill.file_rss_kb = kb(),
+            "shmem-rss" => oom_kill.shmem_rss_kb = kb(),
+            "UID" => oom_kill.uid = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+
+    Some(oom_kill)
+}
+
+/// "my-app[901]: segfault at 10 ip 000055d0c1a2b3c4 sp 00007ffd1e2f3a40 error 4 in my-app[55d0c1a2a000+2000]"
+/// on x86, "crash[902]: unhandled level 2 translation fault (11) at 0x00000008, esr 0x92000006, in crash[400000+1000]"
+/// on arm64 (signal 11 is SIGSEGV).
+fn parse_segfault(message: &str) -> Option<Segfault> {
+    let (task, fault) = message.split_once(": ")?;
+    let (_, rest) = fault
+        .split_once("segfault at ")
+        .or_else(|| fault.split_once("fault (11) at "))?;
+
+    let (name, pid) = task.rsplit_once('[')?;
+    let mut segfault = Segfault {
+        pid: pid.strip_suffix(']')?.parse().ok()?,
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    let mut tokens = rest
+        .split_whitespace()
+        .map(|token| token.trim_end_matches(','));
+    segfault.address = hex(tokens.next()?)?;
+
+    while let Some(key) = tokens.next() {
+        let Some(value) = tokens.next() else {
+            break;
+        };
+
+        match key {
+            "ip" => segfault.ip = hex(value).unwrap_or_default(),
+            "sp" => segfault.sp = hex(value).unwrap_or_default(),
+            "error" | "esr" => segfault.error = hex(value).unwrap_or_default(),
+            "in" => segfault.object = value.split('[').next().unwrap_or_default().to_string(),
+            _ => {}
+        }
+    }
+
+    Some(segfault)
+}
+
+/// "INFO: task kworker/0:1:42 blocked for more than 120 seconds.", the name may contain colons.
+fn parse_hung_task(message: &str) -> Option<HungTask> {
+    let rest = message.strip_prefix("INFO: task ")?;
+    let (task, rest) = rest.split_once(" blocked for more than ")?;
+    let (name, pid) = task.rsplit_once(':')?;
+
+    Some(HungTask {
+        pid: pid.parse().ok()?,
+        name: name.to_string(),
+        blocked_seconds: rest.split_whitespace().next()?.parse().ok()?,
+    })
+}
+
+fn hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn oom_kill() {
+        let message = "Out of memory: Killed process 822 (my (app)) total-vm:1048576kB, anon-rss:524288kB, file-rss:1024kB, shmem-rss:0kB, UID:1000 pgtables:1100kB oom_score_adj:0";
+
+        let expected = OomKill {
+            pid: 822,
+            name: "my (app)".to_string(),
+            uid: 1000,
+            total_vm_kb: 1_048_576,
+            anon_rss_kb: 524_288,
+            file_rss_kb: 1024,
+            ..Default::default()
+        };
+
+        assert_eq!(parse_oom_kill(message), Some(expected));
+    }
+
+    #[test]
+    fn segfault() {
+        let expected = Segfault {
+            pid: 901,
+            name: "my-app".to_string(),
+            address: 0x10,
+            ip: 0x55d0_c1a2_b3c4,
+            sp: 0x7ffd_1e2f_3a40,
+            error: 4,
+            object: "libc.so.6".to_string(),
+        };
+
+        assert_eq!(
+            parse_segfault("my-app[901]: segfault at 10 ip 000055d0c1a2b3c4 sp 00007ffd1e2f3a40 error 4 in libc.so.6[7f3c1a000000+195000] likely on CPU 1 (core 1, socket 0)"),
+            Some(expected)
+        );
+
+        let expected = Segfault {
+            pid: 902,
+            name: "crash".to_string(),
+            address: 8,
+            error: 0x9200_0006,
+            object: "crash".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_segfault("crash[902]: unhandled level 2 translation fault (11) at 0x00000008, esr 0x92000006, in crash[400000+1000]"),
+            Some(expected)
+        );
+
+        // Other signals from the same arm64 handler are not segfaults.
+        assert_eq!(
+            parse_segfault(
+                "crash[903]: unhandled alignment fault (7) at 0x00000001, esr 0x92000061"
+            ),
+            None
+        );
+        assert_eq!(parse_segfault("usb 1-1.3: new high-speed USB device"), None);
+    }
+
+    #[test]
+    fn hung_task() {
+        let expected = HungTask {
+            pid: 42,
+            name: "kworker/0:1".to_string(),
+            blocked_seconds: 120,
+        };
+
+        assert_eq!(
+            parse_hung_task("INFO: task kworker/0:1:42 blocked for more than 120 seconds."),
+            Some(expected)
+        );
+        assert_eq!(parse_hung_task("INFO: task blocked"), None);
+    }
+
+    #[test]
+    fn detect() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/dev/kmsg");
+
+        let mut detector = EventDetector::default();
+        let events: Vec<KernelEvent> = std::fs::read_to_string(path)
+            .expect("Could not read")
+            .lines()
+            .filter_map(|line| parse_record(line, 1_731_155_405))
+            .filter_map(|record| detector.detect(&record))
+            .collect();
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].time_usec, 1_731_155_405_000_000 + 86_400_124_100);
+
+        let Some(Event::OomKill(oom_kill)) = &events[0].event else {
+            panic!("Not an OOM kill: {:?}", events[0]);
+        };
+        assert_eq!(oom_kill.pid, 822);
+        assert_eq!(oom_kill.trigger, "my app");
+        assert_eq!(oom_kill.cgroup, "/system.slice/docker-3f2a4b1c9d8e.scope");
+        assert_eq!(oom_kill.memcg, "/system.slice/docker-3f2a4b1c9d8e.scope");
+
+        assert!(matches!(events[1].event, Some(Event::Segfault(_))));
+        assert!(matches!(events[2].event, Some(Event::Segfault(_))));
+        assert!(matches!(events[3].event, Some(Event::HungTask(_))));
+
+        // The summary was consumed by the kill.
+        assert!(detector.trigger.is_none() && detector.cgroup.is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use super::error::{machine_error, MachineError};
+use common::pw::messages::KmsgRecord;
+use regex::Regex;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::OpenOptionsExt,
+};
+
+const KMSG: &str = "/dev/kmsg";
+
+/// Records are truncated to this size by the kernel, a smaller read buffer fails with EINVAL.
+const RECORD_SIZE: usize = 8192;
+
+/// Non blocking reader of /dev/kmsg, one record per read.
+pub struct KmsgReader {
+    file: File,
+}
+
+impl KmsgReader {
+    /// Start at the oldest record in the ring buffer if from_start, otherwise only new records are read.
+    pub fn open(from_start: bool) -> Result<Self, MachineError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(KMSG)
+            .map_err(|e| machine_error!("Could not open {} ({})", KMSG, e))?;
+
+        if !from_start {
+            file.seek(SeekFrom::End(0))
+                .map_err(|e| machine_error!("Could not seek {} ({})", KMSG, e))?;
+        }
+
+        Ok(Self { file })
+    }
+
+    /// The next record, None if there are no new records.
+    pub fn next_record(&mut self) -> Option<String> {
+        let mut buffer = vec![0; RECORD_SIZE];
+
+        loop {
+            match self.file.read(&mut buffer) {
+                Ok(0) => return None,
+                Ok(n) => return Some(String::from_utf8_lossy(&buffer[..n]).into_owned()),
+                // Records were overwritten before we read them, continue with the oldest left.
+                Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// Which records to stream.
+#[derive(Debug, Default)]
+pub struct KmsgFilter {
+    level: Option<u32>,
+    pattern: Option<Regex>,
+}
+
+impl KmsgFilter {
+    pub fn new(level: Option<u32>, pattern: &str) -> Result<Self, MachineError> {
+        let pattern = if pattern.is_empty() {
+            None
+        } else {
+            Some(
+                Regex::new(pattern)
+                    .map_err(|e| machine_error!("Invalid pattern '{}' ({})", pattern, e))?,
+            )
+        };
+
+        Ok(Self { level, pattern })
+    }
+
+    pub fn matches(&self, record: &KmsgRecord) -> bool {
+        if self.level.is_some_and(|level| record.priority > level) {
+            return false;
+        }
+
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(&record.message))
+    }
+}
+
+/// Parse a /dev/kmsg record, "6,339,5140900,-;usb 1-1: new high-speed USB device\n SUBSYSTEM=usb\n".
+/// boottime (seconds since the epoch) converts the monotonic timestamp to wall clock.
+pub fn parse_record(data: &str, boottime: u64) -> Option<KmsgRecord> {
+    let mut lines = data.lines();
+    let (header, message) = lines.next()?.split_once(';')?;
+
+    let mut fields = header.split(',');
+    let prefix: u32 = fields.next()?.parse().ok()?;
+    let sequence = fields.next()?.parse().ok()?;
+    let monotonic_usec: u64 = fields.next()?.parse().ok()?;
+
+    Some(KmsgRecord {
+        sequence,
+        priority: prefix & 7,
+        facility: prefix >> 3,
+        monotonic_usec,
+        time_usec: boottime * 1_000_000 + monotonic_usec,
+        message: unescape(message),
+        properties: lines
+            .filter_map(|line| line.strip_prefix(' '))
+            .map(unescape)
+            .collect(),
+    })
+}
+
+/// The kernel escapes non printable characters as "\x1b".
+fn unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(i) = rest.find("\\x") {
+        output.push_str(&rest[..i]);
+
+        let escaped = rest.get(i + 2..i + 4);
+        match escaped.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(c) => {
+                output.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                output.push_str("\\x");
+                rest = &rest[i + 2..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let data = "3,1042,7215094112,-;usb 1-1.3: device descriptor read/64, error -71\n SUBSYSTEM=usb\n DEVICE=c189:3\n";
+
+        let expected = KmsgRecord {
+            sequence: 1042,
+            priority: 3,
+            facility: 0,
+            monotonic_usec: 7215094112,
+            time_usec: 1_731_155_405_000_000 + 7215094112,
+            message: "usb 1-1.3: device descriptor read/64, error -71".to_string(),
+            properties: vec!["SUBSYSTEM=usb".to_string(), "DEVICE=c189:3".to_string()],
+        };
+
+        assert_eq!(parse_record(data, 1731155405), Some(expected));
+    }
+
+    #[test]
+    fn record_facility() {
+        // systemd logs to kmsg with facility daemon (3), priority info (6).
+        let record = parse_record("30,5,1000,-;systemd[1]: Started ssh.service.", 0).unwrap();
+
+        assert_eq!(record.priority, 6);
+        assert_eq!(record.facility, 3);
+        assert!(record.properties.is_empty());
+    }
+
+    #[test]
+    fn record_invalid() {
+        assert_eq!(parse_record("no header", 0), None);
+        assert_eq!(parse_record("x,1,2,-;message", 0), None);
+    }
+
+    #[test]
+    fn unescape_ok() {
+        assert_eq!(unescape("tab\\x09here"), "tab\there");
+        assert_eq!(unescape("broken\\xZZ"), "broken\\xZZ");
+        assert_eq!(unescape("end\\x"), "end\\x");
+    }
+
+    #[test]
+    fn filter() {
+        let record = parse_record("3,1,1,-;Out of memory: Killed process 822 (my app)", 0).unwrap();
+
+        assert!(KmsgFilter::default().matches(&record));
+        assert!(KmsgFilter::new(Some(3), "").unwrap().matches(&record));
+        assert!(!KmsgFilter::new(Some(2), "").unwrap().matches(&record));
+        assert!(KmsgFilter::new(None, "Killed process \\d+")
+            .unwrap()
+            .matches(&record));
+        assert!(!KmsgFilter::new(None, "^usb").unwrap().matches(&record));
+        assert!(KmsgFilter::new(None, "(").is_err());
+    }
+
+    #[test]
+    fn read_self() {
+        // Not readable for unprivileged users on kernels with dmesg_restrict.
+        let Ok(mut reader) = KmsgReader::open(true) else {
+            return;
+        };
+
+        let first = reader.next_record().expect("Empty kernel log");
+        assert!(parse_record(&first, 0).is_some());
+    }
+}
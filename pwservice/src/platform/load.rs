@@ -0,0 +1,216 @@
+use super::utils::{parse_lines, read_lines};
+use common::pw::messages::{LoadAverage, Memory, Pressure};
+
+/// Seconds since boot from /proc/uptime.
+pub fn uptime() -> Option<u64> {
+    let lines = read_lines("/proc/uptime").ok()?;
+    parse_uptime(lines)
+}
+
+pub fn load_average() -> Option<LoadAverage> {
+    let lines = read_lines("/proc/loadavg").ok()?;
+    parse_loadavg(lines)
+}
+
+pub fn memory() -> Option<Memory> {
+    let lines = read_lines("/proc/meminfo").ok()?;
+    parse_meminfo(lines)
+}
+
+/// Pressure stall information of resource (cpu, memory or io), None if the kernel has no PSI support.
+pub fn pressure(resource: &str) -> Option<Pressure> {
+    let lines = read_lines(format!("/proc/pressure/{}", resource)).ok()?;
+    parse_pressure(lines)
+}
+
+/// "350735.47 1234388.90", uptime and idle time in seconds.
+fn parse_uptime(lines: Vec<String>) -> Option<u64> {
+    let line = lines.first()?;
+    let uptime = line.split_whitespace().next()?.parse::<f64>().ok()?;
+
+    Some(uptime as u64)
+}
+
+/// "0.52 0.58 0.59 2/437 12453", load averages, running/total tasks and last pid.
+fn parse_loadavg(lines: Vec<String>) -> Option<LoadAverage> {
+    let words: Vec<&str> = lines.first()?.split_whitespace().collect();
+    if words.len() < 4 {
+        return None;
+    }
+
+    let (running, total) = words[3].split_once('/')?;
+
+    Some(LoadAverage {
+        one: words[0].parse().ok()?,
+        five: words[1].parse().ok()?,
+        fifteen: words[2].parse().ok()?,
+        running: running.parse().ok()?,
+        total: total.parse().ok()?,
+    })
+}
+
+/// "MemAvailable:     940044 kB", MemAvailable is missing before Linux 3.14.
+fn parse_meminfo(lines: Vec<String>) -> Option<Memory> {
+    let mut memory = Memory::default();
+
+    for line in &lines {
+        let mut words = line.split_whitespace();
+        let (Some(key), Some(value)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Ok(value) = value.parse() else {
+            continue;
+        };
+
+        match key {
+            "MemTotal:" => memory.total_kb = value,
+            "MemAvailable:" => memory.available_kb = value,
+            "SwapTotal:" => memory.swap_total_kb = value,
+            "SwapFree:" => memory.swap_free_kb = value,
+            _ => {}
+        }
+    }
+
+    (memory.total_kb > 0).then_some(memory)
+}
+
+/// "some avg10=12.50 avg60=8.31 avg300=2.04 total=48862361", followed by a "full" line.
+/// Older kernels have no "full" line for cpu, those values are left at zero.
+fn parse_pressure(lines: Vec<String>) -> Option<Pressure> {
+    let elements = vec![("some", false), ("full", false)];
+    let lines = parse_lines(lines, elements, false);
+
+    let mut pressure = Pressure::default();
+    let mut found = false;
+
+    for line in &lines {
+        let mut words = line.split_whitespace();
+        let kind = words.next()?;
+
+        let mut values = [0.0; 3];
+        let mut total = 0;
+        for word in words {
+            match word.split_once('=') {
+                Some(("avg10", value)) => values[0] = value.parse().ok()?,
+                Some(("avg60", value)) => values[1] = value.parse().ok()?,
+                Some(("avg300", value)) => values[2] = value.parse().ok()?,
+                Some(("total", value)) => total = value.parse().ok()?,
+                _ => {}
+            }
+        }
+
+        if kind == "some" {
+            pressure.some_avg10 = values[0];
+            pressure.some_avg60 = values[1];
+            pressure.some_avg300 = values[2];
+            pressure.some_total = total;
+            found = true;
+        } else {
+            pressure.full_avg10 = values[0];
+            pressure.full_avg60 = values[1];
+            pressure.full_avg300 = values[2];
+            pressure.full_total = total;
+        }
+    }
+
+    if !found {
+        return None;
+    }
+
+    Some(pressure)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn uptime_ok() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/uptime");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_uptime(lines), Some(350735));
+    }
+
+    #[test]
+    fn meminfo() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/meminfo");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = Memory {
+            total_kb: 990180,
+            available_kb: 940044,
+            swap_total_kb: 0,
+            swap_free_kb: 0,
+        };
+
+        assert_eq!(parse_meminfo(lines), Some(expected));
+        assert_eq!(parse_meminfo(vec![]), None);
+    }
+
+    #[test]
+    fn loadavg() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/loadavg");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = LoadAverage {
+            one: 0.52,
+            five: 0.58,
+            fifteen: 0.59,
+            running: 2,
+            total: 437,
+        };
+
+        assert_eq!(parse_loadavg(lines), Some(expected));
+    }
+
+    #[test]
+    fn pressure_cpu() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/pressure/cpu");
+
+        let lines = read_lines(path).expect("Could not read");
+        let pressure = parse_pressure(lines).expect("Could not parse");
+
+        assert_eq!(pressure.some_avg10, 12.5);
+        assert_eq!(pressure.some_avg60, 8.31);
+        assert_eq!(pressure.some_avg300, 2.04);
+        assert_eq!(pressure.some_total, 48862361);
+        assert_eq!(pressure.full_total, 0);
+    }
+
+    #[test]
+    fn pressure_io() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/pressure/io");
+
+        let lines = read_lines(path).expect("Could not read");
+        let pressure = parse_pressure(lines).expect("Could not parse");
+
+        assert_eq!(pressure.some_avg10, 3.2);
+        assert_eq!(pressure.full_avg10, 1.6);
+        assert_eq!(pressure.full_total, 5012231);
+    }
+
+    #[test]
+    fn pressure_without_full() {
+        let lines = vec!["some avg10=0.10 avg60=0.20 avg300=0.30 total=42".to_string()];
+        let pressure = parse_pressure(lines).expect("Could not parse");
+
+        assert_eq!(pressure.some_total, 42);
+        assert_eq!(pressure.full_avg10, 0.0);
+    }
+
+    #[test]
+    fn pressure_missing() {
+        assert_eq!(parse_pressure(vec![]), None);
+    }
+}
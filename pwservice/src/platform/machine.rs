@@ -1,14 +1,51 @@
+use super::disk::storage_total_bytes;
 use super::error::MachineError;
+use super::os::{kernel, os_release};
 use super::utils::{
     get_default_route_info, get_ip_address_info, ip_from_string, mac_from_string, parse_lines,
     parse_lines_no_separator, parse_number, parse_number_no_separator, read_lines,
 };
 use common::pw;
+use std::{collections::HashSet, ffi::CStr, fs, path::Path};
+
+/// ARM CPU part numbers (implementer 0x41) for kernels without "model name" in /proc/cpuinfo.
+const ARM_CPU_PARTS: [(&str, &str); 13] = [
+    ("0xc07", "Cortex-A7"),
+    ("0xc08", "Cortex-A8"),
+    ("0xc09", "Cortex-A9"),
+    ("0xc0f", "Cortex-A15"),
+    ("0xd03", "Cortex-A53"),
+    ("0xd04", "Cortex-A35"),
+    ("0xd05", "Cortex-A55"),
+    ("0xd07", "Cortex-A57"),
+    ("0xd08", "Cortex-A72"),
+    ("0xd09", "Cortex-A73"),
+    ("0xd0a", "Cortex-A75"),
+    ("0xd0b", "Cortex-A76"),
+    ("0xd0c", "Neoverse-N1"),
+];
+
+/// Information from /proc/cpuinfo.
+#[derive(Debug, Default, PartialEq)]
+struct CpuInfo {
+    model_name: String,
+    cores: u32,
+    threads: u32,
+    features: String,
+    hardware: String,
+    revision: String,
+    serial: String,
+}
 
 /// Trait to access machine information.
 pub trait Machine {
     //fn bootid(&self) -> &str;
     fn mac(&self) -> u64;
+    /// Seconds since the epoch.
+    fn boottime(&self) -> u64;
+    fn hostname(&self) -> &str;
+    /// Serial number from /proc/cpuinfo, empty on most x86 machines.
+    fn serial(&self) -> &str;
     fn serialize(&self) -> Vec<u8>;
 }
 
@@ -47,6 +84,18 @@ impl Machine for LinuxMachine {
         self.machine_info.network_interface.as_ref().unwrap().mac
     }
 
+    fn boottime(&self) -> u64 {
+        self.machine_info.boottime
+    }
+
+    fn hostname(&self) -> &str {
+        &self.machine_info.hostname
+    }
+
+    fn serial(&self) -> &str {
+        &self.machine_info.serial
+    }
+
     fn serialize(&self) -> Vec<u8> {
         common::serialize_machine(&self.machine_info)
     }
@@ -71,10 +120,48 @@ fn load() -> common::pw::messages::Machine {
         machine.version = value;
     }
 
+    machine.kernel = kernel();
+    machine.os_release = os_release();
+    machine.protocol = Some(common::protocol());
+    machine.service = Some(crate::version());
+
     if let Some(value) = parse_cpuinfo() {
-        machine.cpu_model_name = value;
+        machine.cpu_model_name = value.model_name;
+        machine.cpu_cores = value.cores;
+        machine.cpu_threads = value.threads;
+        machine.cpu_features = value.features;
+        machine.hardware = value.hardware;
+        machine.revision = value.revision;
+        machine.serial = value.serial;
+    }
+
+    if let Some(value) = parse_architecture() {
+        machine.architecture = value;
+    }
+
+    // Distributions without the architecture in the kernel release, e.g. "6.1.21-v7+" on Raspberry Pi OS.
+    if let Some(kernel) = machine
+        .kernel
+        .as_mut()
+        .filter(|kernel| kernel.arch.is_empty())
+    {
+        kernel.arch = machine.architecture.clone();
+    }
+
+    if let Some(value) = parse_device_tree_model() {
+        machine.device_tree_model = value;
+    }
+
+    if let Some(value) = parse_dmi("sys_vendor") {
+        machine.dmi_vendor = value;
+    }
+
+    if let Some(value) = parse_dmi("product_name") {
+        machine.dmi_product = value;
     }
 
+    machine.storage_total_bytes = storage_total_bytes("/sys");
+
     if let Some(value) = parse_mem_size() {
         machine.physical_mem_total_kb = value;
     }
@@ -113,6 +200,15 @@ fn parse_hostname() -> Option<String> {
     None
 }
 
+/// First 64 bits of the 128 bit machine id below root, dbus keeps a copy on systems without systemd.
+pub fn machine_id(root: impl AsRef<Path>) -> Option<u64> {
+    ["etc/machine-id", "var/lib/dbus/machine-id"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(root.as_ref().join(path)).ok())
+        .find_map(|id| u64::from_str_radix(id.trim().get(..16)?, 16).ok())
+        .filter(|id| *id != 0)
+}
+
 fn parse_version() -> Option<String> {
     if let Ok(lines) = read_lines("/proc/version") {
         return parse_lines(lines, vec![], true).pop();
@@ -121,12 +217,86 @@ fn parse_version() -> Option<String> {
     None
 }
 
-fn parse_cpuinfo() -> Option<String> {
+fn parse_cpuinfo() -> Option<CpuInfo> {
     if let Ok(lines) = read_lines("/proc/cpuinfo") {
-        let elements = vec![("model name", false)];
-        let lines = parse_lines(lines, elements, true);
+        return Some(parse_cpuinfo_lines(lines));
+    }
+
+    None
+}
+
+fn parse_cpuinfo_lines(lines: Vec<String>) -> CpuInfo {
+    let mut info = CpuInfo::default();
+    let mut cores = HashSet::new();
+    let mut physical_id = "";
+    let mut arm_part = None;
+    let mut old_arm_model = None;
+
+    for line in &lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        let value = value.trim();
+        match key.trim() {
+            "processor" => info.threads += 1,
+            "model name" if info.model_name.is_empty() => info.model_name = value.to_string(),
+            // Older ARM kernels.
+            "Processor" => old_arm_model = Some(value.to_string()),
+            "CPU part" => arm_part = Some(value.to_string()),
+            "physical id" => physical_id = value,
+            "core id" => {
+                cores.insert((physical_id, value));
+            }
+            "flags" | "Features" if info.features.is_empty() => info.features = value.to_string(),
+            "Hardware" => info.hardware = value.to_string(),
+            "Revision" => info.revision = value.to_string(),
+            "Serial" => info.serial = value.to_string(),
+            _ => {}
+        }
+    }
 
-        return Some(lines[0].clone());
+    if info.model_name.is_empty() {
+        if let Some(model) = old_arm_model {
+            info.model_name = model;
+        } else if let Some(part) = arm_part {
+            let name = ARM_CPU_PARTS.iter().find(|(id, _)| *id == part);
+            info.model_name = name.map_or(format!("ARM part {}", part), |(_, name)| {
+                format!("ARM {}", name)
+            });
+        }
+    }
+
+    // Only x86 reports core ids, assume no SMT elsewhere.
+    info.cores = if cores.is_empty() {
+        info.threads
+    } else {
+        cores.len() as u32
+    };
+
+    info
+}
+
+fn parse_architecture() -> Option<String> {
+    // Safe, uname fills the struct and the fields are NUL terminated strings.
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+
+    let machine = unsafe { CStr::from_ptr(name.machine.as_ptr()) };
+    Some(machine.to_string_lossy().into_owned())
+}
+
+fn parse_device_tree_model() -> Option<String> {
+    // NUL terminated string, not a text file.
+    let model = fs::read_to_string("/proc/device-tree/model").ok()?;
+    Some(model.trim_end_matches('\0').to_string())
+}
+
+fn parse_dmi(name: &str) -> Option<String> {
+    if let Ok(lines) = read_lines(format!("/sys/class/dmi/id/{}", name)) {
+        return parse_lines(lines, vec![], false).pop();
     }
 
     None
@@ -246,6 +416,64 @@ mod tests {
         assert_eq!(info[0], "ARMv7 Processor rev 10 (v7l)");
     }
 
+    #[test]
+    fn cpuinfo_armv7() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/cpuinfo");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = CpuInfo {
+            model_name: "ARMv7 Processor rev 10 (v7l)".to_string(),
+            cores: 1,
+            threads: 1,
+            features: "half thumb fastmult vfp edsp thumbee neon vfpv3 tls vfpd32".to_string(),
+            hardware: "Generic AM43 (Flattened Device Tree)".to_string(),
+            revision: "0000".to_string(),
+            serial: "0000000000000000".to_string(),
+        };
+
+        assert_eq!(parse_cpuinfo_lines(lines), expected);
+    }
+
+    #[test]
+    fn cpuinfo_arm64() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/cpuinfo_arm64");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = CpuInfo {
+            model_name: "ARM Cortex-A72".to_string(),
+            cores: 4,
+            threads: 4,
+            features: "fp asimd evtstrm crc32 cpuid".to_string(),
+            hardware: "".to_string(),
+            revision: "c03114".to_string(),
+            serial: "10000000a3b4c5d6".to_string(),
+        };
+
+        assert_eq!(parse_cpuinfo_lines(lines), expected);
+    }
+
+    #[test]
+    fn cpuinfo_x86() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/cpuinfo_x86");
+
+        let info = parse_cpuinfo_lines(read_lines(path).expect("Could not read"));
+
+        assert_eq!(info.model_name, "Intel(R) Core(TM) i5-8250U CPU @ 1.60GHz");
+        assert_eq!(info.cores, 2);
+        assert_eq!(info.threads, 4);
+        assert_eq!(info.features, "fpu vme de pse tsc msr sse sse2 ht");
+    }
+
+    #[test]
+    fn architecture() {
+        assert!(parse_architecture().is_some_and(|arch| !arch.is_empty()));
+    }
+
     #[test]
     fn mem_size() {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -265,6 +493,14 @@ mod tests {
         assert!(LinuxMachine::new().is_ok());
     }
 
+    #[test]
+    fn machine_id_ok() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test");
+
+        assert_eq!(machine_id(&root), Some(0x3f1c_6a7e_2b9d_4e5f));
+        assert_eq!(machine_id("/tmp/dontexists"), None);
+    }
+
     /*
     #[test]
     fn parse_network_info_ok() {
@@ -0,0 +1,32 @@
+use super::cgroup::CgroupCollector;
+use super::disk::{filesystems, DiskCollector};
+use super::load::{load_average, memory, pressure, uptime};
+use super::thermal::{cpu_frequencies, sensors, thermal_zones, throttled};
+use common::pw::messages::Metrics;
+
+/// Collects the periodic metrics sample, keeps state needed to calculate rates.
+#[derive(Default)]
+pub struct MetricsCollector {
+    disk: DiskCollector,
+    cgroups: CgroupCollector,
+}
+
+impl MetricsCollector {
+    pub fn sample(&mut self) -> Metrics {
+        Metrics {
+            filesystems: filesystems(),
+            block_devices: self.disk.block_devices(),
+            uptime: uptime().unwrap_or_default(),
+            load_average: load_average(),
+            cpu_pressure: pressure("cpu"),
+            memory_pressure: pressure("memory"),
+            io_pressure: pressure("io"),
+            thermal_zones: thermal_zones("/sys"),
+            cpu_frequencies: cpu_frequencies("/sys"),
+            sensors: sensors("/sys"),
+            throttled: throttled("/sys"),
+            cgroups: self.cgroups.usage("/sys"),
+            memory: memory(),
+        }
+    }
+}
@@ -1,4 +1,19 @@
+pub mod cgroup;
+pub mod control;
+pub mod disk;
 pub mod error;
+pub mod events;
+pub mod kmsg;
+pub mod load;
 pub mod machine;
+pub mod metrics;
+pub mod net;
+pub mod os;
+pub mod overhead;
+pub mod process;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod thermal;
+pub mod watchdog;
 
 mod utils;
@@ -0,0 +1,275 @@
+use super::process::socket_owners;
+use super::utils::read_lines;
+use common::pw::messages::socket::{Sockproto, Sockstate};
+use common::pw::messages::{Connection, Connections, Socket};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+/// Tables in /proc/net holding inet sockets.
+const INET_TABLES: [(&str, Sockproto); 4] = [
+    ("tcp", Sockproto::Tcp),
+    ("tcp6", Sockproto::Tcp6),
+    ("udp", Sockproto::Udp),
+    ("udp6", Sockproto::Udp6),
+];
+
+/// Flag set on listening unix sockets (__SO_ACCEPTCON).
+const UNIX_ACCEPTCON: u32 = 0x10000;
+
+/// Unix socket state SS_CONNECTED.
+const UNIX_CONNECTED: u32 = 3;
+
+/// Read all socket tables in proc_net (/proc/net or /proc/<pid>/net for the namespace of a process).
+pub fn load_sockets(proc_net: impl AsRef<Path>) -> Vec<Socket> {
+    let proc_net = proc_net.as_ref();
+    let mut sockets = vec![];
+
+    for (table, proto) in INET_TABLES {
+        // Missing tables are fine, e.g. kernels without IPv6.
+        if let Ok(lines) = read_lines(proc_net.join(table)) {
+            sockets.append(&mut parse_inet_table(lines, proto));
+        }
+    }
+
+    if let Ok(lines) = read_lines(proc_net.join("unix")) {
+        sockets.append(&mut parse_unix_table(lines));
+    }
+
+    sockets
+}
+
+/// Same as load_sockets, but keyed by inode for lookup of "socket:[<inode>]" file descriptors.
+pub fn load_sockets_by_inode(proc_net: impl AsRef<Path>) -> HashMap<u64, Socket> {
+    load_sockets(proc_net)
+        .into_iter()
+        // Sockets in e.g. TIME_WAIT are no longer owned by anyone and have inode 0.
+        .filter(|socket| socket.inode != 0)
+        .map(|socket| (socket.inode, socket))
+        .collect()
+}
+
+/// Listening and established inet sockets of the machine, with the process owning them.
+pub fn load_connections() -> Connections {
+    let owners = socket_owners();
+
+    let connections = load_sockets("/proc/net")
+        .into_iter()
+        .filter(|socket| socket.proto() != Sockproto::Unix)
+        .filter(|socket| {
+            socket.state() == Sockstate::Listen || socket.state() == Sockstate::Established
+        })
+        .map(|socket| {
+            let (pid, process_name) = owners.get(&socket.inode).cloned().unwrap_or_default();
+            Connection {
+                socket: Some(socket),
+                pid,
+                process_name,
+            }
+        })
+        .collect();
+
+    Connections { connections }
+}
+
+/// Parse /proc/net/{tcp,tcp6,udp,udp6}, first line is a header.
+pub fn parse_inet_table(lines: Vec<String>, proto: Sockproto) -> Vec<Socket> {
+    let mut sockets = vec![];
+
+    for line in lines.iter().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 10 {
+            continue;
+        }
+
+        let (Some(local), Some(remote)) = (parse_address(words[1]), parse_address(words[2])) else {
+            continue;
+        };
+
+        let mut socket = Socket {
+            proto: proto.into(),
+            inode: words[9].parse().unwrap_or_default(),
+            local_address: local.to_string(),
+            remote_address: remote.to_string(),
+            uid: words[7].parse().unwrap_or_default(),
+            ..Default::default()
+        };
+
+        let state = u32::from_str_radix(words[3], 16).unwrap_or_default();
+        let state = Sockstate::try_from(state as i32).unwrap_or(Sockstate::Unspecified);
+        let is_udp = proto == Sockproto::Udp || proto == Sockproto::Udp6;
+
+        if is_udp && state == Sockstate::Close && remote.port() == 0 {
+            // Unconnected UDP sockets are bound and receiving, list them the way netstat does.
+            socket.set_state(Sockstate::Listen);
+        } else {
+            socket.set_state(state);
+        }
+
+        sockets.push(socket);
+    }
+
+    sockets
+}
+
+/// Parse /proc/net/unix, first line is a header.
+pub fn parse_unix_table(lines: Vec<String>) -> Vec<Socket> {
+    let mut sockets = vec![];
+
+    for line in lines.iter().skip(1) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 7 {
+            continue;
+        }
+
+        let flags = u32::from_str_radix(words[3], 16).unwrap_or_default();
+        let state = u32::from_str_radix(words[5], 16).unwrap_or_default();
+
+        let mut socket = Socket {
+            proto: Sockproto::Unix.into(),
+            inode: words[6].parse().unwrap_or_default(),
+            local_address: unix_path(line).to_string(),
+            ..Default::default()
+        };
+
+        if flags & UNIX_ACCEPTCON != 0 {
+            socket.set_state(Sockstate::Listen);
+        } else if state == UNIX_CONNECTED {
+            socket.set_state(Sockstate::Established);
+        } else {
+            socket.set_state(Sockstate::Close);
+        }
+
+        sockets.push(socket);
+    }
+
+    sockets
+}
+
+/// The path is everything after the inode (the 7th column), it may contain spaces.
+fn unix_path(line: &str) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..7 {
+        rest = rest
+            .find(char::is_whitespace)
+            .map_or("", |end| rest[end..].trim_start());
+    }
+    rest.trim_end()
+}
+
+/// Parse "0100007F:0016" (IPv4) or "00000000000000000000000001000000:0016" (IPv6).
+/// The kernel prints the address as 32 bit words in host byte order, the port in hex.
+fn parse_address(input: &str) -> Option<SocketAddr> {
+    let (address, port) = input.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+
+    let mut bytes = vec![];
+    for i in (0..address.len()).step_by(8) {
+        let word = u32::from_str_radix(address.get(i..i + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+
+    match bytes.len() {
+        4 => {
+            let octets: [u8; 4] = bytes.try_into().ok()?;
+            Some(SocketAddr::from((Ipv4Addr::from(octets), port)))
+        }
+        16 => {
+            let octets: [u8; 16] = bytes.try_into().ok()?;
+            Some(SocketAddr::from((Ipv6Addr::from(octets), port)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn tcp_table() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/net/tcp");
+
+        let lines = read_lines(path).expect("Could not read");
+        let sockets = parse_inet_table(lines, Sockproto::Tcp);
+
+        assert_eq!(sockets.len(), 3);
+        assert_eq!(sockets[0].local_address, "0.0.0.0:22");
+        assert_eq!(sockets[0].state(), Sockstate::Listen);
+        assert_eq!(sockets[0].inode, 11842);
+        assert_eq!(sockets[1].local_address, "127.0.0.1:7455");
+        assert_eq!(sockets[2].local_address, "192.168.42.105:22");
+        assert_eq!(sockets[2].remote_address, "192.168.42.114:54242");
+        assert_eq!(sockets[2].state(), Sockstate::Established);
+    }
+
+    #[test]
+    fn tcp6_table() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/net/tcp6");
+
+        let lines = read_lines(path).expect("Could not read");
+        let sockets = parse_inet_table(lines, Sockproto::Tcp6);
+
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[0].local_address, "[::]:22");
+        assert_eq!(sockets[1].local_address, "[::1]:7455");
+        assert_eq!(sockets[1].remote_address, "[::1]:41668");
+        assert_eq!(sockets[1].state(), Sockstate::TimeWait);
+    }
+
+    #[test]
+    fn udp_table() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/net/udp");
+
+        let lines = read_lines(path).expect("Could not read");
+        let sockets = parse_inet_table(lines, Sockproto::Udp);
+
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[0].local_address, "0.0.0.0:68");
+        assert_eq!(sockets[0].state(), Sockstate::Listen);
+        assert_eq!(sockets[1].remote_address, "192.168.1.1:53");
+        assert_eq!(sockets[1].state(), Sockstate::Established);
+        assert_eq!(sockets[1].uid, 999);
+    }
+
+    #[test]
+    fn unix_table() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/net/unix");
+
+        let lines = read_lines(path).expect("Could not read");
+        let sockets = parse_unix_table(lines);
+
+        assert_eq!(sockets.len(), 5);
+        assert_eq!(sockets[0].local_address, "/run/systemd/private");
+        assert_eq!(sockets[0].state(), Sockstate::Listen);
+        assert_eq!(sockets[1].state(), Sockstate::Established);
+        assert_eq!(sockets[2].local_address, "");
+        assert_eq!(sockets[3].state(), Sockstate::Close);
+        assert_eq!(
+            sockets[4].local_address,
+            "/run/user/1000/my app/control socket"
+        );
+    }
+
+    #[test]
+    fn sockets_by_inode() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/net");
+
+        let sockets = load_sockets_by_inode(path);
+
+        // 13 sockets in total, the TIME_WAIT socket has no inode.
+        assert_eq!(sockets.len(), 12);
+        assert_eq!(sockets[&27410].proto(), Sockproto::Tcp);
+        assert_eq!(sockets[&14112].local_address, "[::]:5353");
+        assert_eq!(sockets[&10419].local_address, "@/org/kernel/udev/udevd");
+    }
+}
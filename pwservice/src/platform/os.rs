@@ -0,0 +1,281 @@
+use super::utils::{parse_lines, read_lines};
+use common::pw::messages::{Kernel, OsRelease};
+use std::{collections::HashMap, process::Command};
+
+/// Locations of os-release in order of precedence, see os-release(5).
+const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+/// Architectures distributions append to the kernel release, e.g. "5.14.0-362.el9.x86_64" or "6.1.0-13-amd64".
+const RELEASE_ARCHES: [&str; 13] = [
+    "x86_64",
+    "aarch64",
+    "armv7hl",
+    "i686",
+    "ppc64le",
+    "s390x",
+    "riscv64",
+    "amd64",
+    "arm64",
+    "armmp",
+    "armmp-lpae",
+    "686-pae",
+    "686",
+];
+
+/// Distribution of the machine, from os-release or the Android build properties.
+pub fn os_release() -> Option<OsRelease> {
+    for path in OS_RELEASE {
+        if let Ok(lines) = read_lines(path) {
+            return Some(parse_os_release(lines));
+        }
+    }
+
+    // Android has no os-release, and /system/build.prop is not readable for everyone.
+    let output = Command::new("getprop").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let properties = parse_getprop(stdout.lines().map(String::from).collect());
+    android_release(&properties)
+}
+
+pub fn kernel() -> Option<Kernel> {
+    let lines = read_lines("/proc/version").ok()?;
+    parse_kernel(parse_lines(lines, vec![], false).pop()?.as_str())
+}
+
+/// Parse os-release(5), shell style KEY=value lines with optionally quoted values.
+fn parse_os_release(lines: Vec<String>) -> OsRelease {
+    let mut release = OsRelease::default();
+
+    for line in &lines {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let value = unquote(value);
+        match key {
+            "ID" => release.id = value,
+            "VERSION_ID" => release.version_id = value,
+            "PRETTY_NAME" => release.pretty_name = value,
+            "BUILD_ID" => release.build_id = value,
+            _ => {}
+        }
+    }
+
+    release
+}
+
+/// Remove single or double quotes, in double quotes \", \\, \$ and \` are escaped.
+fn unquote(value: &str) -> String {
+    if let Some(value) = value.strip_prefix('\'') {
+        return value.strip_suffix('\'').unwrap_or(value).to_string();
+    }
+
+    let Some(value) = value.strip_prefix('"') else {
+        return value.to_string();
+    };
+    let value = value.strip_suffix('"').unwrap_or(value);
+
+    let mut output = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\' | '$' | '`')) => output.push(escaped),
+                Some(other) => {
+                    output.push('\\');
+                    output.push(other);
+                }
+                None => output.push('\\'),
+            },
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Parse the output of getprop, "[ro.build.id]: [QKQ1.190716.003]".
+fn parse_getprop(lines: Vec<String>) -> HashMap<String, String> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once("]: [")?;
+            let key = key.strip_prefix('[')?;
+            let value = value.strip_suffix(']')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn android_release(properties: &HashMap<String, String>) -> Option<OsRelease> {
+    let version = properties.get("ro.build.version.release")?;
+    let property = |key: &str| properties.get(key).cloned().unwrap_or_default();
+
+    Some(OsRelease {
+        id: "android".to_string(),
+        version_id: version.clone(),
+        pretty_name: format!("Android {}", version),
+        build_id: property("ro.build.id"),
+        fingerprint: property("ro.build.fingerprint"),
+    })
+}
+
+/// Parse "Linux version <release> (<builder>) (<compiler>) <build>".
+/// The compiler part has nested parentheses, e.g. "(gcc version 4.7 (GCC))".
+fn parse_kernel(version: &str) -> Option<Kernel> {
+    let rest = version.strip_prefix("Linux version ")?;
+    let (release, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    let mut groups = vec![];
+    while groups.len() < 2 {
+        let Some(group) = rest.trim_start().strip_prefix('(') else {
+            break;
+        };
+
+        let mut depth = 1;
+        let end = group.find(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })?;
+
+        groups.push(group[..end].to_string());
+        rest = &group[end + 1..];
+    }
+
+    let mut groups = groups.into_iter();
+
+    Some(Kernel {
+        release: release.to_string(),
+        builder: groups.next().unwrap_or_default(),
+        compiler: groups.next().unwrap_or_default(),
+        build: rest.trim().to_string(),
+        arch: release_arch(release).unwrap_or_default().to_string(),
+    })
+}
+
+/// The architecture at the end of the release, after a '.' or '-'.
+fn release_arch(release: &str) -> Option<&str> {
+    RELEASE_ARCHES.into_iter().find(|arch| {
+        release
+            .strip_suffix(arch)
+            .is_some_and(|rest| rest.ends_with(['.', '-']))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn os_release_ok() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/etc/os-release");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = OsRelease {
+            id: "raspbian".to_string(),
+            version_id: "11".to_string(),
+            pretty_name: "Raspbian GNU/Linux 11 (bullseye)".to_string(),
+            build_id: "2023-05-03".to_string(),
+            fingerprint: "".to_string(),
+        };
+
+        assert_eq!(parse_os_release(lines), expected);
+    }
+
+    #[test]
+    fn unquote_ok() {
+        assert_eq!(unquote("debian"), "debian");
+        assert_eq!(unquote("\"Debian GNU/Linux\""), "Debian GNU/Linux");
+        assert_eq!(unquote("'single $quoted'"), "single $quoted");
+        assert_eq!(unquote("\"Edition \\\"Lite\\\"\""), "Edition \"Lite\"");
+        assert_eq!(unquote("\"back\\\\slash \\n\""), "back\\slash \\n");
+    }
+
+    #[test]
+    fn android() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/getprop");
+
+        let properties = parse_getprop(read_lines(path).expect("Could not read"));
+        assert_eq!(properties["ro.build.version.sdk"], "29");
+
+        let expected = OsRelease {
+            id: "android".to_string(),
+            version_id: "10".to_string(),
+            pretty_name: "Android 10".to_string(),
+            build_id: "QKQ1.190716.003".to_string(),
+            fingerprint:
+                "OnePlus/OnePlus6/OnePlus6:10/QKQ1.190716.003/2006171400:user/release-keys"
+                    .to_string(),
+        };
+
+        assert_eq!(android_release(&properties), Some(expected));
+        assert_eq!(android_release(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn kernel_old() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/version");
+
+        let lines = read_lines(path).expect("Could not read");
+        let version = parse_lines(lines, vec![], false).pop().unwrap();
+
+        let expected = Kernel {
+            release: "4.14.44-gafd0c90dd7be".to_string(),
+            builder: "jenkins@miro".to_string(),
+            compiler: "gcc version 4.7 (GCC)".to_string(),
+            build: "#1 SMP Wed Jul 19 11:56:13 CEST 2023".to_string(),
+            arch: "".to_string(),
+        };
+
+        assert_eq!(parse_kernel(&version), Some(expected));
+    }
+
+    #[test]
+    fn kernel_new() {
+        let version = "Linux version 6.1.21-v7+ (dom@buildbot) (arm-linux-gnueabihf-gcc-8 (Ubuntu/Linaro 8.4.0-3ubuntu1) 8.4.0, GNU ld (GNU Binutils for Ubuntu) 2.34) #1642 SMP Mon Apr  3 17:20:52 BST 2023";
+
+        let kernel = parse_kernel(version).expect("Could not parse");
+
+        assert_eq!(kernel.release, "6.1.21-v7+");
+        assert_eq!(kernel.builder, "dom@buildbot");
+        assert_eq!(kernel.compiler, "arm-linux-gnueabihf-gcc-8 (Ubuntu/Linaro 8.4.0-3ubuntu1) 8.4.0, GNU ld (GNU Binutils for Ubuntu) 2.34");
+        assert_eq!(kernel.build, "#1642 SMP Mon Apr  3 17:20:52 BST 2023");
+        assert_eq!(kernel.arch, "");
+    }
+
+    #[test]
+    fn kernel_arch() {
+        assert_eq!(release_arch("5.14.0-362.el9.x86_64"), Some("x86_64"));
+        assert_eq!(release_arch("6.1.0-13-amd64"), Some("amd64"));
+        assert_eq!(release_arch("6.1.0-13-armmp-lpae"), Some("armmp-lpae"));
+        assert_eq!(release_arch("6.1.0-13-686-pae"), Some("686-pae"));
+        assert_eq!(release_arch("6.1.21-v7+"), None);
+        assert_eq!(release_arch("6.8.0-45-generic"), None);
+
+        let version = "Linux version 6.1.0-13-arm64 (debian-kernel@lists.debian.org) (gcc-12 (Debian 12.2.0-14) 12.2.0) #1 SMP Debian 6.1.55-1 (2023-09-29)";
+        assert_eq!(
+            parse_kernel(version).expect("Could not parse").arch,
+            "arm64"
+        );
+    }
+
+    #[test]
+    fn kernel_invalid() {
+        assert_eq!(parse_kernel("FreeBSD 14.0"), None);
+    }
+}
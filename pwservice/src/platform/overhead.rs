@@ -0,0 +1,110 @@
+use super::process::own_stat;
+use super::utils::{clock_ticks, page_kb};
+use common::pw::messages::ServiceOverhead;
+use std::time::{Duration, Instant};
+
+/// The scan interval grows to at most this many times the configured interval.
+const MAX_BACKOFF: u32 = 8;
+
+/// Measures what pwservice costs and backs the process scan interval off while it uses more cpu than the budget.
+pub struct ScanBudget {
+    base: Duration,
+    interval: Duration,
+    /// Percent of one CPU.
+    budget: Option<f32>,
+    /// Time and our cpu ticks at the previous update.
+    previous: Option<(Instant, u64)>,
+}
+
+impl ScanBudget {
+    pub fn new(base: Duration, budget: Option<f32>) -> Self {
+        Self {
+            base,
+            interval: base,
+            budget,
+            previous: None,
+        }
+    }
+
+    /// Interval until the next scan.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Our usage since the previous update, called after each scan, scan is how long it took.
+    pub fn update(&mut self, scan: Duration) -> ServiceOverhead {
+        let now = Instant::now();
+        let stat = own_stat().unwrap_or_default();
+
+        let cpu_percent = self.previous.map_or(0.0, |(time, ticks)| {
+            let elapsed = now.duration_since(time).as_secs_f32() * clock_ticks() as f32;
+            if elapsed > 0.0 {
+                stat.ticks.saturating_sub(ticks) as f32 * 100.0 / elapsed
+            } else {
+                0.0
+            }
+        });
+        self.previous = Some((now, stat.ticks));
+
+        if let Some(budget) = self.budget {
+            self.interval = next_interval(self.base, self.interval, cpu_percent, budget);
+        }
+
+        ServiceOverhead {
+            cpu_percent,
+            rss_kb: stat.rss_pages * page_kb(),
+            scan_msec: scan.as_millis() as u32,
+            interval_msec: self.interval.as_millis() as u32,
+            budget_percent: self.budget.unwrap_or_default(),
+        }
+    }
+}
+
+/// Doubles the interval above the budget, halves it back towards base below half of the budget.
+fn next_interval(base: Duration, current: Duration, cpu_percent: f32, budget: f32) -> Duration {
+    if cpu_percent > budget {
+        (current * 2).min(base * MAX_BACKOFF)
+    } else if cpu_percent < budget / 2.0 {
+        (current / 2).max(base)
+    } else {
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let base = Duration::from_secs(10);
+
+        assert_eq!(next_interval(base, base, 6.0, 5.0), Duration::from_secs(20));
+        assert_eq!(
+            next_interval(base, Duration::from_secs(80), 50.0, 5.0),
+            Duration::from_secs(80)
+        );
+        assert_eq!(
+            next_interval(base, Duration::from_secs(40), 4.0, 5.0),
+            Duration::from_secs(40)
+        );
+        assert_eq!(
+            next_interval(base, Duration::from_secs(40), 1.0, 5.0),
+            Duration::from_secs(20)
+        );
+        assert_eq!(next_interval(base, base, 0.0, 5.0), base);
+    }
+
+    #[test]
+    fn update_self() {
+        let mut budget = ScanBudget::new(Duration::from_secs(10), None);
+
+        budget.update(Duration::ZERO);
+        let overhead = budget.update(Duration::from_millis(12));
+
+        assert!(overhead.rss_kb > 0);
+        assert_eq!(overhead.scan_msec, 12);
+        assert_eq!(overhead.interval_msec, 10_000);
+        assert_eq!(budget.interval(), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,1042 @@
+use super::cgroup::{cgroups, container, namespaces, primary_cgroup, unit};
+use super::error::{machine_error, MachineError};
+use super::net::load_sockets_by_inode;
+use super::utils::{clock_ticks, page_kb, parse_lines, parse_number, read_lines};
+use common::pw::messages::{
+    Cgroup, EnvironmentVariable, FileDescriptor, OpenFiles, Process, ProcessDetail, ProcessRates,
+    ProcessStatus, Processes, ResourceLimit,
+};
+use regex::RegexSet;
+use serde::Deserialize;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+/// Open file descriptors of a process, sockets are resolved in the network namespace of the process.
+pub fn open_files(pid: i32) -> Result<OpenFiles, MachineError> {
+    let proc_pid = PathBuf::from(format!("/proc/{}", pid));
+
+    let entries = fs::read_dir(proc_pid.join("fd"))
+        .map_err(|e| machine_error!("Could not read file descriptors of {} ({})", pid, e))?;
+
+    let mut open_files = OpenFiles {
+        pid,
+        ..Default::default()
+    };
+
+    if let Ok(lines) = read_lines(proc_pid.join("limits")) {
+        if let Some((soft, hard)) = parse_open_files_limit(lines) {
+            open_files.soft_limit = soft;
+            open_files.hard_limit = hard;
+        }
+    }
+
+    let sockets = load_sockets_by_inode(proc_pid.join("net"));
+
+    for entry in entries.flatten() {
+        let Ok(fd) = entry.file_name().to_string_lossy().parse::<i32>() else {
+            continue;
+        };
+
+        // The fd may be closed while we iterate, just report an empty target.
+        let target = fs::read_link(entry.path())
+            .map(|target| target.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let socket = socket_inode(&target).and_then(|inode| sockets.get(&inode).cloned());
+
+        open_files.fds.push(FileDescriptor { fd, target, socket });
+    }
+
+    open_files.fds.sort_by_key(|fd| fd.fd);
+
+    Ok(open_files)
+}
+
+/// Pids of all running processes.
+pub fn pids() -> Vec<i32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_string_lossy().parse::<i32>().ok())
+        .collect()
+}
+
+/// A running process as the watchdog sees it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Running {
+    pub pid: i32,
+    pub name: String,
+    pub cmdline: String,
+    /// Clock ticks after boot, tells a restarted process from one that kept running when the pid is reused.
+    pub start_ticks: u64,
+}
+
+/// Name, command line and start time of all running processes, much cheaper than a scan.
+pub fn running() -> Vec<Running> {
+    pids()
+        .into_iter()
+        .filter_map(|pid| {
+            let proc_pid = PathBuf::from(format!("/proc/{}", pid));
+            let stat = read_lines(proc_pid.join("stat")).ok()?;
+            let line = stat.first()?;
+
+            Some(Running {
+                pid,
+                name: parse_stat(line)?.name,
+                cmdline: read_cmdline(&proc_pid),
+                start_ticks: parse_start_ticks(line)?,
+            })
+        })
+        .collect()
+}
+
+/// Short name of a process from /proc/<pid>/comm.
+pub fn process_name(pid: i32) -> Option<String> {
+    let lines = read_lines(format!("/proc/{}/comm", pid)).ok()?;
+    parse_lines(lines, vec![], false).pop()
+}
+
+/// Process rules, a process matches if it matches any of them.
+#[derive(Default)]
+pub struct ProcessRules {
+    names: RegexSet,
+    uids: Vec<i32>,
+    cgroups: RegexSet,
+    pids: Vec<i32>,
+}
+
+impl ProcessRules {
+    /// names and cgroups are regular expressions matching the process name and cgroup path.
+    pub fn new(
+        names: &[String],
+        uids: &[i32],
+        cgroups: &[String],
+        pids: &[i32],
+    ) -> Result<Self, MachineError> {
+        Ok(Self {
+            names: RegexSet::new(names)
+                .map_err(|e| machine_error!("Invalid name pattern ({})", e))?,
+            uids: uids.to_vec(),
+            cgroups: RegexSet::new(cgroups)
+                .map_err(|e| machine_error!("Invalid cgroup pattern ({})", e))?,
+            pids: pids.to_vec(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.uids.is_empty()
+            && self.cgroups.is_empty()
+            && self.pids.is_empty()
+    }
+
+    fn matches(&self, candidate: &Candidate) -> bool {
+        self.pids.contains(&candidate.pid)
+            || self.uids.contains(&candidate.uid)
+            || self.names.is_match(&candidate.stat.name)
+            || self.cgroups.is_match(&candidate.cgroup)
+    }
+}
+
+/// Which processes are published, all if include is empty, none matching exclude.
+#[derive(Default)]
+pub struct ProcessFilter {
+    include: ProcessRules,
+    exclude: ProcessRules,
+}
+
+impl ProcessFilter {
+    pub fn new(include: ProcessRules, exclude: ProcessRules) -> Self {
+        Self { include, exclude }
+    }
+
+    fn matches(&self, candidate: &Candidate) -> bool {
+        (self.include.is_empty() || self.include.matches(candidate))
+            && !self.exclude.matches(candidate)
+    }
+}
+
+/// What the top processes are ranked by.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TopBy {
+    #[default]
+    Cpu,
+    Memory,
+}
+
+/// Publishes the process list, remembers the counters of the previous scan for the rates.
+pub struct ProcessCollector {
+    filter: ProcessFilter,
+    /// Only the count processes using the most cpu or memory.
+    top: Option<(usize, TopBy)>,
+    previous: HashMap<i32, Counters>,
+    last_scan: Option<Instant>,
+}
+
+impl ProcessCollector {
+    pub fn new(filter: ProcessFilter, top: Option<(usize, TopBy)>) -> Self {
+        Self {
+            filter,
+            top,
+            previous: HashMap::new(),
+            last_scan: None,
+        }
+    }
+
+    /// Change the filter and top, the previous sample is kept so rates continue.
+    pub fn configure(&mut self, filter: ProcessFilter, top: Option<(usize, TopBy)>) {
+        self.filter = filter;
+        self.top = top;
+    }
+
+    /// Running processes passing the filter with their cgroups, container, systemd unit and namespaces.
+    pub fn scan(&mut self) -> Processes {
+        let now = Instant::now();
+        let seconds = self
+            .last_scan
+            .map(|last| now.duration_since(last).as_secs_f32())
+            .filter(|seconds| *seconds > 0.0);
+
+        let pids = pids();
+        let scanned = pids.len() as u32;
+
+        // Everything is filtered and ranked before the rest is read, that is where the time goes.
+        let mut candidates: Vec<(Candidate, f32)> = pids
+            .into_iter()
+            .filter_map(|pid| candidate(pid, &self.filter))
+            .map(|candidate| {
+                let cpu_percent = match (seconds, self.previous.get(&candidate.pid)) {
+                    (Some(seconds), Some(previous)) => {
+                        candidate.counters.ticks.saturating_sub(previous.ticks) as f32 * 100.0
+                            / (seconds * clock_ticks() as f32)
+                    }
+                    _ => 0.0,
+                };
+                (candidate, cpu_percent)
+            })
+            .collect();
+        let matched = candidates.len() as u32;
+
+        let mut unpublished = vec![];
+        if let Some((count, by)) = self.top {
+            match by {
+                TopBy::Cpu => candidates.sort_by(|a, b| b.1.total_cmp(&a.1)),
+                TopBy::Memory => {
+                    candidates.sort_by_key(|(candidate, _)| Reverse(candidate.stat.rss_pages))
+                }
+            }
+            unpublished = candidates.split_off(count.min(candidates.len()));
+        }
+
+        let mut previous: HashMap<i32, Counters> = unpublished
+            .iter()
+            .map(|(candidate, _)| (candidate.pid, candidate.counters))
+            .collect();
+
+        let mut processes: Vec<Process> = candidates
+            .into_iter()
+            .map(|(mut candidate, cpu_percent)| {
+                // Only for published processes, it is one more file per process.
+                candidate.counters.io =
+                    read_lines(candidate.proc_pid.join("io")).ok().map(parse_io);
+
+                let rates = seconds
+                    .zip(self.previous.get(&candidate.pid))
+                    .map(|(seconds, before)| rates(&candidate.counters, before, seconds));
+                previous.insert(candidate.pid, candidate.counters);
+
+                Process {
+                    cpu_percent,
+                    rates,
+                    ..load_rest(candidate)
+                }
+            })
+            .collect();
+        processes.sort_by_key(|process| process.pid);
+
+        self.previous = previous;
+        self.last_scan = Some(now);
+
+        Processes {
+            processes,
+            scanned,
+            matched,
+            overhead: None,
+        }
+    }
+}
+
+/// Cumulative counters of a process, the rates are the difference between two scans.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Counters {
+    ticks: u64,
+    minor_faults: u64,
+    major_faults: u64,
+    voluntary_ctxt_switches: u64,
+    nonvoluntary_ctxt_switches: u64,
+    io: Option<IoCounters>,
+}
+
+/// Storage I/O of /proc/<pid>/io.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct IoCounters {
+    read_bytes: u64,
+    write_bytes: u64,
+    read_syscalls: u64,
+    write_syscalls: u64,
+}
+
+/// Per second, counters that went backwards (a reused pid) give 0.
+fn rates(current: &Counters, previous: &Counters, seconds: f32) -> ProcessRates {
+    let rate = |current: u64, previous: u64| current.saturating_sub(previous) as f32 / seconds;
+    let io = current.io.zip(previous.io);
+    let io_rate = |counter: fn(&IoCounters) -> u64| {
+        io.map_or(0.0, |(current, previous)| {
+            rate(counter(&current), counter(&previous))
+        })
+    };
+
+    ProcessRates {
+        read_bytes: io_rate(|io| io.read_bytes),
+        write_bytes: io_rate(|io| io.write_bytes),
+        read_syscalls: io_rate(|io| io.read_syscalls),
+        write_syscalls: io_rate(|io| io.write_syscalls),
+        voluntary_ctxt_switches: rate(
+            current.voluntary_ctxt_switches,
+            previous.voluntary_ctxt_switches,
+        ),
+        nonvoluntary_ctxt_switches: rate(
+            current.nonvoluntary_ctxt_switches,
+            previous.nonvoluntary_ctxt_switches,
+        ),
+        minor_faults: rate(current.minor_faults, previous.minor_faults),
+        major_faults: rate(current.major_faults, previous.major_faults),
+    }
+}
+
+/// What processes are filtered and ranked by, cheap to read.
+struct Candidate {
+    pid: i32,
+    proc_pid: PathBuf,
+    stat: ProcessStat,
+    counters: Counters,
+    uid: i32,
+    gid: i32,
+    cgroups: Vec<Cgroup>,
+    cgroup: String,
+}
+
+/// None if the process exited while we read it or does not pass filter.
+fn candidate(pid: i32, filter: &ProcessFilter) -> Option<Candidate> {
+    let proc_pid = PathBuf::from(format!("/proc/{}", pid));
+
+    let stat = read_lines(proc_pid.join("stat")).ok()?;
+    let stat = parse_stat(stat.first()?)?;
+
+    let status = read_lines(proc_pid.join("status")).unwrap_or_default();
+    let (voluntary_ctxt_switches, nonvoluntary_ctxt_switches) = parse_ctxt_switches(&status);
+    let (uid, gid) = parse_ids(status).unwrap_or_default();
+
+    let cgroups = cgroups(&proc_pid);
+    let cgroup = primary_cgroup(&cgroups);
+
+    let counters = Counters {
+        ticks: stat.ticks,
+        minor_faults: stat.minor_faults,
+        major_faults: stat.major_faults,
+        voluntary_ctxt_switches,
+        nonvoluntary_ctxt_switches,
+        io: None,
+    };
+
+    let candidate = Candidate {
+        pid,
+        proc_pid,
+        stat,
+        counters,
+        uid,
+        gid,
+        cgroups,
+        cgroup,
+    };
+
+    filter.matches(&candidate).then_some(candidate)
+}
+
+fn load_rest(candidate: Candidate) -> Process {
+    let proc_pid = &candidate.proc_pid;
+
+    // Not readable for processes of other users unless we run as root.
+    let cwd = fs::read_link(proc_pid.join("cwd"))
+        .map(|cwd| cwd.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Process {
+        name: candidate.stat.name,
+        tgid: candidate.pid,
+        pid: candidate.pid,
+        ppid: candidate.stat.ppid,
+        uid: candidate.uid,
+        gid: candidate.gid,
+        cmdline: read_cmdline(proc_pid),
+        cwd,
+        container: container(&candidate.cgroups),
+        unit: unit(&candidate.cgroup),
+        cgroup: candidate.cgroup,
+        cgroups: candidate.cgroups,
+        namespaces: Some(namespaces(proc_pid)),
+        cpu_percent: 0.0,
+        rss_kb: candidate.stat.rss_pages * page_kb(),
+        rates: None,
+    }
+}
+
+/// None if the process exited while we read it.
+fn load_process(pid: i32) -> Option<Process> {
+    candidate(pid, &ProcessFilter::default()).map(load_rest)
+}
+
+/// The fields of /proc/<pid>/stat we use.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProcessStat {
+    pub name: String,
+    pub ppid: i32,
+    /// User and system time in clock ticks.
+    pub ticks: u64,
+    pub rss_pages: u64,
+    pub minor_faults: u64,
+    /// Faults that needed I/O.
+    pub major_faults: u64,
+}
+
+/// Our own stat, for the overhead of pwservice.
+pub fn own_stat() -> Option<ProcessStat> {
+    let lines = read_lines("/proc/self/stat").ok()?;
+    parse_stat(lines.first()?)
+}
+
+/// "822 (my app) S 1 822 ...", the name may contain spaces and parentheses.
+/// Fields after the name are counted from the state, field 3 of stat(5).
+fn parse_stat(line: &str) -> Option<ProcessStat> {
+    let start = line.find('(')?;
+    let end = line.rfind(')')?;
+    let name = line.get(start + 1..end)?;
+
+    let fields: Vec<&str> = line[end + 1..].split_whitespace().collect();
+    let number = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<u64>().ok())
+            .unwrap_or_default()
+    };
+
+    Some(ProcessStat {
+        name: name.to_string(),
+        ppid: fields.get(1)?.parse().ok()?,
+        ticks: number(11) + number(12),
+        rss_pages: number(21),
+        minor_faults: number(7),
+        major_faults: number(9),
+    })
+}
+
+/// Voluntary and involuntary context switches from /proc/<pid>/status.
+fn parse_ctxt_switches(lines: &[String]) -> (u64, u64) {
+    let mut switches = (0, 0);
+
+    for line in lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().parse().unwrap_or_default();
+
+        match key {
+            "voluntary_ctxt_switches" => switches.0 = value,
+            "nonvoluntary_ctxt_switches" => switches.1 = value,
+            _ => {}
+        }
+    }
+
+    switches
+}
+
+/// "read_bytes: 40960" lines of /proc/<pid>/io, bytes that went to storage and the read and write calls.
+fn parse_io(lines: Vec<String>) -> IoCounters {
+    let mut io = IoCounters::default();
+
+    for line in &lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().parse().unwrap_or_default();
+
+        match key {
+            "read_bytes" => io.read_bytes = value,
+            "write_bytes" => io.write_bytes = value,
+            "syscr" => io.read_syscalls = value,
+            "syscw" => io.write_syscalls = value,
+            _ => {}
+        }
+    }
+
+    io
+}
+
+/// Real uid and gid from the "Uid:" and "Gid:" lines of /proc/<pid>/status.
+fn parse_ids(lines: Vec<String>) -> Option<(i32, i32)> {
+    let elements = vec![("Uid:", false), ("Gid:", false)];
+    let lines = parse_lines(lines, elements, false);
+    let real = |line: &String| line.split_whitespace().nth(1)?.parse().ok();
+
+    Some((real(lines.first()?)?, real(lines.get(1)?)?))
+}
+
+/// Arguments are NUL separated, kernel threads have none.
+fn read_cmdline(proc_pid: &Path) -> String {
+    let Ok(cmdline) = fs::read(proc_pid.join("cmdline")) else {
+        return String::new();
+    };
+
+    cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything we know about one process, environment variables with a name matching redact are hidden.
+/// boottime (seconds since the epoch) converts the start time to wall clock.
+pub fn process_detail(
+    pid: i32,
+    redact: &RegexSet,
+    boottime: u64,
+) -> Result<ProcessDetail, MachineError> {
+    let process = load_process(pid).ok_or_else(|| machine_error!("No process {}", pid))?;
+    let proc_pid = PathBuf::from(format!("/proc/{}", pid));
+
+    // environ and the links are only readable for our own processes unless we run as root.
+    let link = |name: &str| {
+        fs::read_link(proc_pid.join(name))
+            .map(|target| target.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let start_ticks = read_lines(proc_pid.join("stat"))
+        .ok()
+        .and_then(|lines| parse_start_ticks(lines.first()?));
+
+    Ok(ProcessDetail {
+        process: Some(process),
+        status: read_lines(proc_pid.join("status")).ok().map(parse_status),
+        environ: fs::read(proc_pid.join("environ"))
+            .map(|environ| parse_environ(&environ, redact))
+            .unwrap_or_default(),
+        limits: read_lines(proc_pid.join("limits"))
+            .map(parse_limits)
+            .unwrap_or_default(),
+        exe: link("exe"),
+        root: link("root"),
+        start_time: start_ticks.map_or(0, |ticks| boottime + ticks / clock_ticks()),
+    })
+}
+
+/// Typed fields of /proc/<pid>/status, "Key:\tvalue" lines.
+fn parse_status(lines: Vec<String>) -> ProcessStatus {
+    let mut status = ProcessStatus::default();
+
+    for line in &lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let number = || parse_number::<u64>(value).unwrap_or_default();
+        let hex = || u64::from_str_radix(value, 16).unwrap_or_default();
+        // Real, effective, saved set and filesystem id.
+        let effective = || {
+            value
+                .split_whitespace()
+                .nth(1)
+                .and_then(|id| id.parse().ok())
+                .unwrap_or_default()
+        };
+
+        match key {
+            "State" => status.state = value.split_whitespace().next().unwrap_or_default().into(),
+            "Threads" => status.threads = number() as u32,
+            "voluntary_ctxt_switches" => status.voluntary_ctxt_switches = number(),
+            "nonvoluntary_ctxt_switches" => status.nonvoluntary_ctxt_switches = number(),
+            "CapInh" => status.cap_inh = hex(),
+            "CapPrm" => status.cap_prm = hex(),
+            "CapEff" => status.cap_eff = hex(),
+            "CapBnd" => status.cap_bnd = hex(),
+            "CapAmb" => status.cap_amb = hex(),
+            "Seccomp" => status.seccomp = number() as u32,
+            "NoNewPrivs" => status.no_new_privs = number() == 1,
+            "Umask" => status.umask = u32::from_str_radix(value, 8).unwrap_or_default(),
+            "Uid" => status.euid = effective(),
+            "Gid" => status.egid = effective(),
+            "VmSize" => status.vm_size_kb = number(),
+            "VmRSS" => status.vm_rss_kb = number(),
+            "VmSwap" => status.vm_swap_kb = number(),
+            _ => {}
+        }
+    }
+
+    status
+}
+
+/// "NAME=value" entries separated by NUL, values of names matching redact are dropped.
+fn parse_environ(environ: &[u8], redact: &RegexSet) -> Vec<EnvironmentVariable> {
+    environ
+        .split(|b| *b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (name, value) = entry.split_once('=').unwrap_or((&entry, ""));
+            let redacted = redact.is_match(name);
+
+            EnvironmentVariable {
+                name: name.to_string(),
+                value: if redacted {
+                    String::new()
+                } else {
+                    value.to_string()
+                },
+                redacted,
+            }
+        })
+        .collect()
+}
+
+/// /proc/<pid>/limits has fixed width columns, the names contain spaces and some units are missing.
+fn parse_limits(lines: Vec<String>) -> Vec<ResourceLimit> {
+    lines
+        .iter()
+        .skip(1)
+        .filter_map(|line| {
+            // Trailing spaces may be gone.
+            let column = |start: usize, end: usize| {
+                line.get(start..end.min(line.len()))
+                    .unwrap_or_default()
+                    .trim()
+            };
+
+            Some(ResourceLimit {
+                name: column(0, 26).to_string(),
+                soft: parse_limit(column(26, 47))?,
+                hard: parse_limit(column(47, 68))?,
+                units: column(68, line.len()).to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Start time in clock ticks after boot, field 22 of /proc/<pid>/stat.
+fn parse_start_ticks(line: &str) -> Option<u64> {
+    let end = line.rfind(')')?;
+    line[end + 1..].split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Map socket inodes to the pid and name of the process owning them.
+/// Processes we are not allowed to inspect are silently skipped.
+pub fn socket_owners() -> HashMap<u64, (i32, String)> {
+    let mut owners = HashMap::new();
+
+    for pid in pids() {
+        let Ok(entries) = fs::read_dir(format!("/proc/{}/fd", pid)) else {
+            continue;
+        };
+
+        let mut name = None;
+
+        for entry in entries.flatten() {
+            let Ok(target) = fs::read_link(entry.path()) else {
+                continue;
+            };
+
+            if let Some(inode) = socket_inode(&target.to_string_lossy()) {
+                let name = name.get_or_insert_with(|| process_name(pid).unwrap_or_default());
+                owners.insert(inode, (pid, name.clone()));
+            }
+        }
+    }
+
+    owners
+}
+
+/// Soft and hard RLIMIT_NOFILE from /proc/<pid>/limits.
+fn parse_open_files_limit(lines: Vec<String>) -> Option<(u64, u64)> {
+    let elements = vec![("Max open files", false)];
+    let lines = parse_lines(lines, elements, false);
+
+    // "Max open files 1024 4096 files"
+    let words: Vec<&str> = lines.first()?.split_whitespace().collect();
+    if words.len() < 5 {
+        return None;
+    }
+
+    Some((parse_limit(words[3])?, parse_limit(words[4])?))
+}
+
+fn parse_limit(input: &str) -> Option<u64> {
+    if input == "unlimited" {
+        return Some(u64::MAX);
+    }
+
+    input.parse().ok()
+}
+
+/// Inode of a "socket:[12345]" link target.
+fn socket_inode(target: &str) -> Option<u64> {
+    target
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn open_files_limit() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/limits");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_open_files_limit(lines), Some((1024, 4096)));
+    }
+
+    #[test]
+    fn limit_unlimited() {
+        assert_eq!(parse_limit("unlimited"), Some(u64::MAX));
+        assert_eq!(parse_limit("8388608"), Some(8388608));
+        assert_eq!(parse_limit("-"), None);
+    }
+
+    #[test]
+    fn socket_inode_ok() {
+        assert_eq!(socket_inode("socket:[27410]"), Some(27410));
+        assert_eq!(socket_inode("pipe:[27410]"), None);
+        assert_eq!(socket_inode("/var/log/syslog"), None);
+    }
+
+    #[test]
+    fn open_files_self() {
+        let pid = std::process::id() as i32;
+        let open_files = open_files(pid).expect("Could not read own file descriptors");
+
+        assert_eq!(open_files.pid, pid);
+        assert!(!open_files.fds.is_empty());
+        assert!(open_files.soft_limit > 0);
+    }
+
+    #[test]
+    fn process_name_self() {
+        let pid = std::process::id() as i32;
+
+        assert!(pids().contains(&pid));
+        assert!(process_name(pid).is_some_and(|name| !name.is_empty()));
+    }
+
+    #[test]
+    fn stat() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/stat");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = ProcessStat {
+            name: "my app".to_string(),
+            ppid: 1,
+            ticks: 152 + 37,
+            rss_pages: 4567,
+            minor_faults: 1042,
+            major_faults: 3,
+        };
+        assert_eq!(parse_stat(&lines[0]), Some(expected));
+
+        let expected = ProcessStat {
+            name: "a) b)".to_string(),
+            ppid: 7,
+            ..Default::default()
+        };
+        assert_eq!(parse_stat("42 (a) b)) R 7 42"), Some(expected));
+        assert_eq!(parse_stat("42 broken"), None);
+    }
+
+    #[test]
+    fn cmdline() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822");
+
+        assert_eq!(
+            read_cmdline(&path),
+            "/usr/bin/my-app --config /etc/my app.conf"
+        );
+        assert_eq!(read_cmdline(Path::new("/tmp/dontexists")), "");
+    }
+
+    #[test]
+    fn ids() {
+        let lines = vec![
+            "Name:\tbash".to_string(),
+            "Uid:\t1000\t1000\t1000\t1000".to_string(),
+            "Gid:\t100\t100\t100\t100".to_string(),
+        ];
+
+        assert_eq!(parse_ids(lines), Some((1000, 100)));
+        assert_eq!(parse_ids(vec![]), None);
+    }
+
+    #[test]
+    fn status() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/status");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        let expected = ProcessStatus {
+            state: "S".to_string(),
+            threads: 20,
+            voluntary_ctxt_switches: 296,
+            nonvoluntary_ctxt_switches: 3559,
+            seccomp: 0,
+            no_new_privs: true,
+            umask: 0o077,
+            euid: 10031,
+            egid: 10031,
+            vm_size_kb: 565_316,
+            vm_rss_kb: 100_824,
+            ..Default::default()
+        };
+
+        assert_eq!(parse_status(lines), expected);
+
+        let lines = vec![
+            "CapEff:\t00000000a80425fb".to_string(),
+            "Seccomp:\t2".to_string(),
+        ];
+        let status = parse_status(lines);
+        assert_eq!(status.cap_eff, 0xa804_25fb);
+        assert_eq!(status.seccomp, 2);
+    }
+
+    #[test]
+    fn environ() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/environ");
+
+        let environ = fs::read(path).expect("Could not read");
+        let redact = RegexSet::new(["(?i)password", "(?i)token"]).unwrap();
+        let environ = parse_environ(&environ, &redact);
+
+        let names: Vec<&str> = environ.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["PATH", "HOME", "DB_PASSWORD", "API_TOKEN", "LANG"]);
+        assert_eq!(environ[0].value, "/usr/bin:/bin");
+        assert!(environ[2].redacted && environ[2].value.is_empty());
+        assert!(environ[3].redacted);
+        assert!(!environ[4].redacted);
+    }
+
+    #[test]
+    fn limits() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/limits");
+
+        let limits = parse_limits(read_lines(path).expect("Could not read"));
+
+        assert_eq!(limits.len(), 16);
+        assert_eq!(
+            limits[7],
+            ResourceLimit {
+                name: "Max open files".to_string(),
+                soft: 1024,
+                hard: 4096,
+                units: "files".to_string(),
+            }
+        );
+        assert_eq!(limits[13].units, "");
+        assert_eq!(limits[0].soft, u64::MAX);
+    }
+
+    #[test]
+    fn start_ticks() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822/stat");
+
+        let lines = read_lines(path).expect("Could not read");
+
+        assert_eq!(parse_start_ticks(&lines[0]), Some(1417));
+    }
+
+    #[test]
+    fn detail_self() {
+        let pid = std::process::id() as i32;
+        let detail = process_detail(pid, &RegexSet::empty(), 0).expect("Could not read own detail");
+
+        assert!(detail.status.is_some_and(|status| status.threads > 0));
+        assert!(!detail.environ.is_empty());
+        assert!(!detail.exe.is_empty());
+        assert_eq!(detail.root, "/");
+        assert!(process_detail(-1, &RegexSet::empty(), 0).is_err());
+    }
+
+    #[test]
+    fn processes_self() {
+        let pid = std::process::id() as i32;
+        let mut collector = ProcessCollector::new(ProcessFilter::default(), None);
+        let processes = collector.scan();
+        let process = processes.processes.iter().find(|p| p.pid == pid);
+
+        assert!(process
+            .is_some_and(|p| !p.cmdline.is_empty() && p.namespaces.is_some() && p.rss_kb > 0));
+        assert!(processes.matched == processes.scanned && processes.scanned > 0);
+    }
+
+    #[test]
+    fn io() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/proc/822");
+
+        let expected = IoCounters {
+            read_bytes: 40960,
+            write_bytes: 8_388_608,
+            read_syscalls: 1200,
+            write_syscalls: 340,
+        };
+        assert_eq!(
+            parse_io(read_lines(path.join("io")).expect("Could not read")),
+            expected
+        );
+
+        let status = read_lines(path.join("status")).expect("Could not read");
+        assert_eq!(parse_ctxt_switches(&status), (296, 3559));
+    }
+
+    #[test]
+    fn process_rates() {
+        let io = |read_bytes: u64, write_bytes: u64| IoCounters {
+            read_bytes,
+            write_bytes,
+            read_syscalls: read_bytes / 4096,
+            write_syscalls: write_bytes / 4096,
+        };
+        let previous = Counters {
+            ticks: 100,
+            minor_faults: 1000,
+            major_faults: 10,
+            voluntary_ctxt_switches: 50,
+            nonvoluntary_ctxt_switches: 5,
+            io: Some(io(4096, 8192)),
+        };
+        let current = Counters {
+            ticks: 300,
+            minor_faults: 1200,
+            major_faults: 30,
+            voluntary_ctxt_switches: 250,
+            nonvoluntary_ctxt_switches: 3,
+            io: Some(io(4096 * 21, 8192 + 4096 * 4)),
+        };
+
+        let expected = ProcessRates {
+            read_bytes: 4096.0 * 10.0,
+            write_bytes: 4096.0 * 2.0,
+            read_syscalls: 10.0,
+            write_syscalls: 2.0,
+            voluntary_ctxt_switches: 100.0,
+            nonvoluntary_ctxt_switches: 0.0,
+            minor_faults: 100.0,
+            major_faults: 10.0,
+        };
+        assert_eq!(rates(&current, &previous, 2.0), expected);
+
+        // Not readable in one of the scans.
+        let current = Counters {
+            io: None,
+            ..current
+        };
+        assert_eq!(rates(&current, &previous, 2.0).read_bytes, 0.0);
+    }
+
+    #[test]
+    fn processes_top() {
+        let pid = std::process::id() as i32;
+        let include = ProcessRules::new(&[], &[], &[], &[pid, 1]).unwrap();
+        let mut collector = ProcessCollector::new(
+            ProcessFilter::new(include, ProcessRules::default()),
+            Some((1, TopBy::Memory)),
+        );
+
+        collector.scan();
+        let processes = collector.scan();
+
+        assert_eq!(processes.matched, 2);
+        assert_eq!(processes.processes.len(), 1);
+        assert!(processes.processes[0].rates.is_some());
+        assert!(own_stat().is_some_and(|stat| stat.rss_pages > 0));
+    }
+
+    #[test]
+    fn filter() {
+        let patterns =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let candidate = |pid: i32, name: &str, uid: i32, cgroup: &str| Candidate {
+            pid,
+            proc_pid: PathBuf::new(),
+            stat: ProcessStat {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            counters: Counters::default(),
+            uid,
+            gid: uid,
+            cgroups: vec![],
+            cgroup: cgroup.to_string(),
+        };
+
+        let exclude = ProcessRules::new(
+            &patterns(&["^kworker/", "^ksoftirqd"]),
+            &[],
+            &patterns(&["^/system.slice/docker-"]),
+            &[42],
+        )
+        .unwrap();
+        let filter = ProcessFilter::new(ProcessRules::default(), exclude);
+        assert!(filter.matches(&candidate(7, "sshd", 0, "/system.slice/ssh.service")));
+        assert!(!filter.matches(&candidate(8, "kworker/0:1", 0, "/")));
+        assert!(!filter.matches(&candidate(9, "nginx", 0, "/system.slice/docker-3f2a.scope")));
+        assert!(!filter.matches(&candidate(42, "sshd", 0, "/")));
+
+        let include = ProcessRules::new(&patterns(&["^nginx$"]), &[1000], &[], &[]).unwrap();
+        let exclude = ProcessRules::new(&patterns(&["app$"]), &[], &[], &[]).unwrap();
+        let filter = ProcessFilter::new(include, exclude);
+        assert!(filter.matches(&candidate(7, "nginx", 33, "/")));
+        assert!(filter.matches(&candidate(8, "bash", 1000, "/")));
+        assert!(!filter.matches(&candidate(9, "my app", 1000, "/")));
+        assert!(!filter.matches(&candidate(10, "sshd", 0, "/")));
+
+        assert!(ProcessFilter::default().matches(&candidate(7, "anything", 0, "/")));
+        assert!(ProcessRules::new(&patterns(&["("]), &[], &[], &[]).is_err());
+        assert!(ProcessRules::new(&[], &[], &patterns(&["["]), &[]).is_err());
+    }
+
+    #[test]
+    fn open_files_missing_process() {
+        assert!(open_files(-1).is_err());
+    }
+}
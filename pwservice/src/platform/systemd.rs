@@ -0,0 +1,295 @@
+use common::pw::messages::{Unit, Units};
+use zbus::{proxy::CacheProperties, zvariant::OwnedObjectPath, Connection};
+
+/// Name, description, load state, active state, sub state, followed unit, object path,
+/// job id, job type and job object path, as returned by ListUnits.
+type UnitStatus = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    OwnedObjectPath,
+    u32,
+    String,
+    OwnedObjectPath,
+);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait Manager {
+    fn list_units(&self) -> zbus::Result<Vec<UnitStatus>>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.systemd1.Unit",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait UnitProperties {
+    #[zbus(property)]
+    fn state_change_timestamp(&self) -> zbus::Result<u64>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.systemd1.Service",
+    default_service = "org.freedesktop.systemd1"
+)]
+trait ServiceProperties {
+    #[zbus(property, name = "MainPID")]
+    fn main_pid(&self) -> zbus::Result<u32>;
+
+    #[zbus(property, name = "NRestarts")]
+    fn n_restarts(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn result(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn exec_main_status(&self) -> zbus::Result<i32>;
+}
+
+/// All loaded services known to the systemd instance on connection.
+pub async fn units(connection: &Connection) -> zbus::Result<Units> {
+    let manager = ManagerProxy::new(connection).await?;
+
+    let mut units = vec![];
+    for status in manager.list_units().await? {
+        let (name, description, load_state, active_state, sub_state, _, path, _, _, _) = status;
+        if !name.ends_with(".service") || load_state != "loaded" {
+            continue;
+        }
+
+        let mut unit = Unit {
+            name,
+            description,
+            load_state,
+            active_state,
+            sub_state,
+            ..Default::default()
+        };
+
+        // The unit may be gone by now, keep what ListUnits told us.
+        let _ = service_properties(connection, path, &mut unit).await;
+
+        units.push(unit);
+    }
+
+    units.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Units { units })
+}
+
+async fn service_properties(
+    connection: &Connection,
+    path: OwnedObjectPath,
+    unit: &mut Unit,
+) -> zbus::Result<()> {
+    // Caching would subscribe to PropertiesChanged of every unit, we only read once per sample.
+    let service = ServicePropertiesProxy::builder(connection)
+        .path(path.clone())?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+    let properties = UnitPropertiesProxy::builder(connection)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await?;
+
+    unit.main_pid = service.main_pid().await?;
+    unit.restarts = service.n_restarts().await?;
+    unit.result = service.result().await?;
+    unit.exit_status = service.exec_main_status().await?;
+    unit.state_change_time = properties.state_change_timestamp().await? / 1_000_000;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    struct MockManager;
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Manager")]
+    impl MockManager {
+        fn list_units(&self) -> Vec<UnitStatus> {
+            let unit = |name: &str, load: &str, active: &str, sub: &str, path: &str| {
+                let path = OwnedObjectPath::try_from(path).unwrap();
+                let no_job = OwnedObjectPath::try_from("/").unwrap();
+                (
+                    name.to_string(),
+                    format!("Mock {}", name),
+                    load.to_string(),
+                    active.to_string(),
+                    sub.to_string(),
+                    String::new(),
+                    path,
+                    0,
+                    String::new(),
+                    no_job,
+                )
+            };
+
+            vec![
+                unit("ssh.service", "loaded", "active", "running", SSH),
+                unit("app.service", "loaded", "failed", "failed", APP),
+                unit("gone.service", "not-found", "inactive", "dead", "/gone"),
+                unit("dev-sda.device", "loaded", "active", "plugged", "/sda"),
+            ]
+        }
+    }
+
+    struct MockService {
+        main_pid: u32,
+        restarts: u32,
+        result: String,
+        exit_status: i32,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Service")]
+    impl MockService {
+        #[zbus(property, name = "MainPID")]
+        fn main_pid(&self) -> u32 {
+            self.main_pid
+        }
+
+        #[zbus(property, name = "NRestarts")]
+        fn n_restarts(&self) -> u32 {
+            self.restarts
+        }
+
+        #[zbus(property)]
+        fn result(&self) -> String {
+            self.result.clone()
+        }
+
+        #[zbus(property)]
+        fn exec_main_status(&self) -> i32 {
+            self.exit_status
+        }
+    }
+
+    struct MockUnit;
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Unit")]
+    impl MockUnit {
+        #[zbus(property)]
+        fn state_change_timestamp(&self) -> u64 {
+            1_731_155_405_123_456
+        }
+    }
+
+    const SSH: &str = "/org/freedesktop/systemd1/unit/ssh_2eservice";
+    const APP: &str = "/org/freedesktop/systemd1/unit/app_2eservice";
+
+    /// Private bus, killed when dropped.
+    struct DbusDaemon(Child);
+
+    impl Drop for DbusDaemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn start_dbus_daemon() -> Option<(DbusDaemon, String)> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(child.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+
+        Some((DbusDaemon(child), address.trim().to_string()))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon"]
+    async fn units_mock_systemd() {
+        let (_daemon, address) = start_dbus_daemon().expect("dbus-daemon not available");
+
+        let _systemd = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.systemd1")
+            .unwrap()
+            .serve_at("/org/freedesktop/systemd1", MockManager)
+            .unwrap()
+            .serve_at(SSH, MockUnit)
+            .unwrap()
+            .serve_at(
+                SSH,
+                MockService {
+                    main_pid: 612,
+                    restarts: 0,
+                    result: "success".to_string(),
+                    exit_status: 0,
+                },
+            )
+            .unwrap()
+            .serve_at(APP, MockUnit)
+            .unwrap()
+            .serve_at(
+                APP,
+                MockService {
+                    main_pid: 0,
+                    restarts: 5,
+                    result: "exit-code".to_string(),
+                    exit_status: 1,
+                },
+            )
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let connection = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let units = units(&connection).await.unwrap().units;
+
+        let expected = vec![
+            Unit {
+                name: "app.service".to_string(),
+                description: "Mock app.service".to_string(),
+                load_state: "loaded".to_string(),
+                active_state: "failed".to_string(),
+                sub_state: "failed".to_string(),
+                main_pid: 0,
+                restarts: 5,
+                result: "exit-code".to_string(),
+                exit_status: 1,
+                state_change_time: 1731155405,
+            },
+            Unit {
+                name: "ssh.service".to_string(),
+                description: "Mock ssh.service".to_string(),
+                load_state: "loaded".to_string(),
+                active_state: "active".to_string(),
+                sub_state: "running".to_string(),
+                main_pid: 612,
+                restarts: 0,
+                result: "success".to_string(),
+                exit_status: 0,
+                state_change_time: 1731155405,
+            },
+        ];
+
+        assert_eq!(units, expected);
+    }
+}
@@ -0,0 +1,220 @@
+use super::utils::{parse_lines, read_lines};
+use common::pw::messages::sensor::Sensorkind;
+use common::pw::messages::{CpuFrequency, Sensor, ThermalZone};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Raspberry Pi firmware exposes the same flags as "vcgencmd get_throttled" here.
+const RPI_THROTTLED: &str = "devices/platform/soc/soc:firmware/get_throttled";
+
+/// hwmon file prefixes and how to convert the raw value into the unit of the sensor.
+const HWMON_SENSORS: [(&str, Sensorkind, f64); 5] = [
+    ("temp", Sensorkind::Temperature, 1000.0),
+    ("in", Sensorkind::Voltage, 1000.0),
+    ("curr", Sensorkind::Current, 1000.0),
+    ("power", Sensorkind::Power, 1_000_000.0),
+    ("fan", Sensorkind::Fan, 1.0),
+];
+
+/// First line of a sysfs attribute.
+fn read_attribute(path: impl AsRef<Path>) -> Option<String> {
+    let lines = read_lines(path).ok()?;
+    parse_lines(lines, vec![], false).pop()
+}
+
+fn read_number<T: std::str::FromStr>(path: impl AsRef<Path>) -> Option<T> {
+    read_attribute(path)?.parse().ok()
+}
+
+/// Entries of directory with a name starting with prefix followed by a number, sorted by that number.
+/// Anything after the number is ignored, "trip_point_" matches both "trip_point_0_temp" and "trip_point_0_type"
+/// which is returned once.
+fn numbered_entries(directory: impl AsRef<Path>, prefix: &str) -> Vec<(u32, PathBuf)> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+
+    let mut numbered: Vec<(u32, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rest = name.strip_prefix(prefix)?;
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let number = rest[..digits].parse().ok()?;
+            Some((number, entry.path()))
+        })
+        .collect();
+
+    numbered.sort();
+    numbered.dedup_by_key(|(number, _)| *number);
+    numbered
+}
+
+/// Thermal zones from <sys>/class/thermal/thermal_zone*.
+pub fn thermal_zones(sys: impl AsRef<Path>) -> Vec<ThermalZone> {
+    let mut zones = vec![];
+
+    for (_, path) in numbered_entries(sys.as_ref().join("class/thermal"), "thermal_zone") {
+        // Reading temp fails for disabled zones.
+        let Some(temperature) = read_number(path.join("temp")) else {
+            continue;
+        };
+
+        let mut zone = ThermalZone {
+            name: read_attribute(path.join("type")).unwrap_or_default(),
+            temperature,
+            ..Default::default()
+        };
+
+        for (i, _) in numbered_entries(&path, "trip_point_") {
+            let Some(trip) = read_number::<i32>(path.join(format!("trip_point_{}_temp", i))) else {
+                continue;
+            };
+
+            match read_attribute(path.join(format!("trip_point_{}_type", i))).as_deref() {
+                Some("passive") if zone.passive_trip == 0 || trip < zone.passive_trip => {
+                    zone.passive_trip = trip
+                }
+                Some("critical") => zone.critical_trip = trip,
+                _ => {}
+            }
+        }
+
+        zones.push(zone);
+    }
+
+    zones
+}
+
+/// Frequencies of all cores with cpufreq support from <sys>/devices/system/cpu/cpu*/cpufreq.
+pub fn cpu_frequencies(sys: impl AsRef<Path>) -> Vec<CpuFrequency> {
+    let mut frequencies = vec![];
+
+    for (cpu, path) in numbered_entries(sys.as_ref().join("devices/system/cpu"), "cpu") {
+        let path = path.join("cpufreq");
+        let Some(current_khz) = read_number(path.join("scaling_cur_freq")) else {
+            continue;
+        };
+
+        frequencies.push(CpuFrequency {
+            cpu,
+            current_khz,
+            min_khz: read_number(path.join("scaling_min_freq")).unwrap_or_default(),
+            max_khz: read_number(path.join("scaling_max_freq")).unwrap_or_default(),
+            hardware_max_khz: read_number(path.join("cpuinfo_max_freq")).unwrap_or_default(),
+        });
+    }
+
+    frequencies
+}
+
+/// Sensors of all chips in <sys>/class/hwmon.
+pub fn sensors(sys: impl AsRef<Path>) -> Vec<Sensor> {
+    let mut sensors = vec![];
+
+    for (_, path) in numbered_entries(sys.as_ref().join("class/hwmon"), "hwmon") {
+        let chip = read_attribute(path.join("name")).unwrap_or_default();
+
+        for (prefix, kind, divisor) in HWMON_SENSORS {
+            for (i, _) in numbered_entries(&path, prefix) {
+                let channel = format!("{}{}", prefix, i);
+
+                // The directory also holds limits and alarms, e.g. "in0_lcrit_alarm".
+                let Some(raw) = read_number::<f64>(path.join(format!("{}_input", channel))) else {
+                    continue;
+                };
+
+                let label = read_attribute(path.join(format!("{}_label", channel)));
+
+                let mut sensor = Sensor {
+                    chip: chip.clone(),
+                    label: label.unwrap_or(channel),
+                    value: raw / divisor,
+                    ..Default::default()
+                };
+                sensor.set_kind(kind);
+
+                sensors.push(sensor);
+            }
+        }
+    }
+
+    sensors
+}
+
+/// Raspberry Pi firmware throttling flags, 0 if not available.
+pub fn throttled(sys: impl AsRef<Path>) -> u32 {
+    read_attribute(sys.as_ref().join(RPI_THROTTLED))
+        .and_then(|value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sys() -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/test/sys");
+        path
+    }
+
+    #[test]
+    fn thermal_zones_ok() {
+        let expected = vec![ThermalZone {
+            name: "cpu-thermal".to_string(),
+            temperature: 72530,
+            passive_trip: 85000,
+            critical_trip: 90000,
+        }];
+
+        assert_eq!(thermal_zones(sys()), expected);
+    }
+
+    #[test]
+    fn cpu_frequencies_ok() {
+        let frequencies = cpu_frequencies(sys());
+
+        assert_eq!(frequencies.len(), 4);
+        assert_eq!(frequencies[3].cpu, 3);
+
+        let expected = CpuFrequency {
+            cpu: 0,
+            current_khz: 1200000,
+            min_khz: 600000,
+            max_khz: 1200000,
+            hardware_max_khz: 1500000,
+        };
+        assert_eq!(frequencies[0], expected);
+    }
+
+    #[test]
+    fn sensors_ok() {
+        let sensors = sensors(sys());
+
+        let values: Vec<(&str, &str, Sensorkind, f64)> = sensors
+            .iter()
+            .map(|s| (s.chip.as_str(), s.label.as_str(), s.kind(), s.value))
+            .collect();
+
+        let expected = vec![
+            ("cpu_thermal", "temp1", Sensorkind::Temperature, 72.53),
+            ("ina219", "VBUS", Sensorkind::Voltage, 5.08),
+            ("ina219", "curr1", Sensorkind::Current, 0.612),
+            ("ina219", "power1", Sensorkind::Power, 3.1),
+            ("ina219", "fan1", Sensorkind::Fan, 2900.0),
+        ];
+
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn throttled_ok() {
+        assert_eq!(throttled(sys()), 0x50005);
+        assert_eq!(throttled("/tmp/dontexists"), 0);
+    }
+}
@@ -184,6 +184,18 @@ pub fn get_ip_address_info(dev: &str) -> Option<Vec<String>> {
     None
 }
 
+// Clock ticks per second, the unit of the cpu times in /proc/<pid>/stat.
+pub fn clock_ticks() -> u64 {
+    // Safe, sysconf has no side effects.
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64
+}
+
+// Page size in kB, the unit of the memory sizes in /proc/<pid>/stat.
+pub fn page_kb() -> u64 {
+    // Safe, sysconf has no side effects.
+    (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1024) / 1024) as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,283 @@
+use super::error::{machine_error, MachineError};
+use super::process::Running;
+use common::pw::messages::{
+    health::Status, kernel_event::Event, process_watch::Kind, Health, KernelEvent, ProcessWatch,
+    RequiredProcess,
+};
+use regex::Regex;
+
+/// A process that must be running, matched by its name, a pattern on its command line or both.
+#[derive(Debug)]
+pub struct Requirement {
+    label: String,
+    name: Option<String>,
+    cmdline: Option<Regex>,
+    min: u32,
+}
+
+impl Requirement {
+    /// name is the exact name (comm), cmdline a regular expression matching the command line.
+    pub fn new(name: Option<&str>, cmdline: Option<&str>, min: u32) -> Result<Self, MachineError> {
+        let label = match (name, cmdline) {
+            (Some(name), Some(cmdline)) => format!("{} ({})", name, cmdline),
+            (Some(label), None) | (None, Some(label)) => label.to_string(),
+            (None, None) => return Err(machine_error!("Neither a name nor a cmdline pattern")),
+        };
+
+        Ok(Self {
+            label,
+            name: name.map(String::from),
+            cmdline: cmdline
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| machine_error!("Invalid cmdline pattern ({})", e))?,
+            min,
+        })
+    }
+
+    fn matches(&self, process: &Running) -> bool {
+        self.name.as_ref().is_none_or(|name| *name == process.name)
+            && self
+                .cmdline
+                .as_ref()
+                .is_none_or(|cmdline| cmdline.is_match(&process.cmdline))
+    }
+}
+
+/// What we know about the processes of one requirement from the previous checks.
+#[derive(Debug)]
+struct Watched {
+    requirement: Requirement,
+    /// Pid and start time of the matching processes, sorted.
+    running: Vec<(i32, u64)>,
+    missing: bool,
+    restarts: u32,
+    since_usec: u64,
+}
+
+/// Checks that the required processes are running, independent of any client.
+#[derive(Debug, Default)]
+pub struct Watchdog {
+    watched: Vec<Watched>,
+    checked: bool,
+}
+
+impl Watchdog {
+    pub fn new(requirements: Vec<Requirement>) -> Self {
+        Self {
+            watched: requirements
+                .into_iter()
+                .map(|requirement| Watched {
+                    requirement,
+                    running: vec![],
+                    missing: false,
+                    restarts: 0,
+                    since_usec: 0,
+                })
+                .collect(),
+            checked: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watched.is_empty()
+    }
+
+    /// The health given the processes listed by [`running`](super::process::running) and an event for each
+    /// requirement that went missing, recovered or was restarted since the previous check. Missing processes
+    /// are reported at the first check too.
+    pub fn update(&mut self, processes: &[Running], now_usec: u64) -> (Health, Vec<KernelEvent>) {
+        let mut events = vec![];
+
+        for watched in &mut self.watched {
+            let mut current: Vec<(i32, u64)> = processes
+                .iter()
+                .filter(|process| watched.requirement.matches(process))
+                .map(|process| (process.pid, process.start_ticks))
+                .collect();
+            current.sort_unstable();
+
+            let count = current.len() as u32;
+            let ok = count >= watched.requirement.min;
+
+            let kind = if !self.checked {
+                watched.since_usec = now_usec;
+                (!ok).then_some(Kind::Missing)
+            } else if !ok && !watched.missing {
+                Some(Kind::Missing)
+            } else if ok && watched.missing {
+                watched.restarts += 1;
+                Some(Kind::Recovered)
+            } else if ok
+                && current.iter().any(|p| !watched.running.contains(p))
+                && watched.running.iter().any(|p| !current.contains(p))
+            {
+                // Some exited and others were started in between, e.g. by systemd.
+                watched.restarts += 1;
+                Some(Kind::Restarted)
+            } else {
+                None
+            };
+
+            if current != watched.running {
+                watched.since_usec = now_usec;
+            }
+            watched.running = current;
+            watched.missing = !ok;
+
+            if let Some(kind) = kind {
+                let what = match kind {
+                    Kind::Missing => "missing",
+                    Kind::Recovered => "recovered",
+                    Kind::Restarted => "restarted",
+                };
+                events.push(KernelEvent {
+                    time_usec: now_usec,
+                    message: format!(
+                        "Required process {} {}, {} of {} running",
+                        watched.requirement.label, what, count, watched.requirement.min
+                    ),
+                    event: Some(Event::ProcessWatch(ProcessWatch {
+                        label: watched.requirement.label.clone(),
+                        what: kind as i32,
+                        count,
+                        min: watched.requirement.min,
+                    })),
+                });
+            }
+        }
+        self.checked = true;
+
+        (self.health(now_usec), events)
+    }
+
+    fn health(&self, now_usec: u64) -> Health {
+        let failing = self.watched.iter().any(|watched| watched.missing);
+
+        Health {
+            state: if failing { Status::Failing } else { Status::Ok } as i32,
+            processes: self
+                .watched
+                .iter()
+                .map(|watched| RequiredProcess {
+                    label: watched.requirement.label.clone(),
+                    min: watched.requirement.min,
+                    pids: watched.running.iter().map(|(pid, _)| *pid).collect(),
+                    restarts: watched.restarts,
+                    since_usec: watched.since_usec,
+                })
+                .collect(),
+            time_usec: now_usec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: i32, name: &str, cmdline: &str, start_ticks: u64) -> Running {
+        Running {
+            pid,
+            name: name.to_string(),
+            cmdline: cmdline.to_string(),
+            start_ticks,
+        }
+    }
+
+    fn kinds(events: &[KernelEvent]) -> Vec<(String, Kind)> {
+        events
+            .iter()
+            .filter_map(|event| match &event.event {
+                Some(Event::ProcessWatch(watch)) => {
+                    Some((watch.label.clone(), Kind::try_from(watch.what).ok()?))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn requirement() {
+        let nginx = process(10, "nginx", "nginx: worker process", 100);
+        let app = process(11, "python3", "/usr/bin/python3 /opt/app.py --port 80", 100);
+
+        let by_name = Requirement::new(Some("nginx"), None, 1).unwrap();
+        assert!(by_name.matches(&nginx));
+        assert!(!by_name.matches(&app));
+
+        let by_cmdline = Requirement::new(None, Some("app\\.py"), 1).unwrap();
+        assert!(by_cmdline.matches(&app));
+        assert!(!by_cmdline.matches(&nginx));
+
+        // Both must match.
+        let both = Requirement::new(Some("python3"), Some("other\\.py"), 1).unwrap();
+        assert_eq!(both.label, "python3 (other\\.py)");
+        assert!(!both.matches(&app));
+
+        assert!(Requirement::new(None, None, 1).is_err());
+        assert!(Requirement::new(None, Some("("), 1).is_err());
+    }
+
+    #[test]
+    fn missing_recovered_restarted() {
+        let mut watchdog = Watchdog::new(vec![
+            Requirement::new(Some("nginx"), None, 2).unwrap(),
+            Requirement::new(None, Some("app\\.py"), 1).unwrap(),
+        ]);
+        let nginx = |pid, start| process(pid, "nginx", "nginx: worker process", start);
+        let app = |pid, start| process(pid, "python3", "python3 app.py", start);
+
+        // Missing at startup is reported.
+        let (health, events) = watchdog.update(&[nginx(10, 100), app(20, 100)], 1_000_000);
+        assert_eq!(health.state, Status::Failing as i32);
+        assert_eq!(kinds(&events), vec![("nginx".to_string(), Kind::Missing)]);
+        assert_eq!(health.processes[0].pids, vec![10]);
+        assert_eq!(health.processes[1].since_usec, 1_000_000);
+
+        // Still missing, no new event.
+        let (_, events) = watchdog.update(&[nginx(10, 100), app(20, 100)], 2_000_000);
+        assert!(events.is_empty());
+
+        let (health, events) =
+            watchdog.update(&[nginx(10, 100), nginx(11, 200), app(20, 100)], 3_000_000);
+        assert_eq!(health.state, Status::Ok as i32);
+        assert_eq!(kinds(&events), vec![("nginx".to_string(), Kind::Recovered)]);
+        assert_eq!(health.processes[0].restarts, 1);
+        assert_eq!(health.processes[0].since_usec, 3_000_000);
+
+        // The pid was reused by a new process between two checks.
+        let (health, events) =
+            watchdog.update(&[nginx(10, 100), nginx(11, 200), app(20, 300)], 4_000_000);
+        assert_eq!(
+            kinds(&events),
+            vec![("app\\.py".to_string(), Kind::Restarted)]
+        );
+        assert_eq!(health.processes[1].restarts, 1);
+        assert_eq!(health.processes[1].since_usec, 4_000_000);
+        assert_eq!(health.processes[0].since_usec, 3_000_000);
+
+        let (health, events) = watchdog.update(&[nginx(10, 100), nginx(11, 200)], 5_000_000);
+        assert_eq!(health.state, Status::Failing as i32);
+        assert_eq!(
+            kinds(&events),
+            vec![("app\\.py".to_string(), Kind::Missing)]
+        );
+        assert_eq!(
+            events[0].message,
+            "Required process app\\.py missing, 0 of 1 running"
+        );
+        assert!(health.processes[1].pids.is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let mut watchdog = Watchdog::default();
+
+        let (health, events) = watchdog.update(&[process(1, "init", "/sbin/init", 1)], 1);
+        assert!(watchdog.is_empty());
+        assert_eq!(health.state, Status::Ok as i32);
+        assert!(health.processes.is_empty());
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::platform::error::{machine_error, MachineError};
+use crate::platform::kmsg::{parse_record, KmsgFilter, KmsgReader};
+use common::{deserialize, protocol, pw, serialize};
+use log::{info, warn};
+use std::{collections::HashMap, time::Duration};
+use tokio::task::JoinHandle;
+
+/// Streams are tasks, limit how many clients can start at the same time.
+const MAX_STREAMS: usize = 8;
+
+/// How often /dev/kmsg is checked for new records.
+const KMSG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A stream nobody subscribes to anymore (client gone without stopping it) is stopped after this.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Streams started by clients, each publishes on its own key expression until stopped.
+pub struct Streams {
+    session: zenoh::Session,
+    key_expr_kmsg: String,
+    boottime: u64,
+    tasks: HashMap<u64, JoinHandle<()>>,
+}
+
+impl Streams {
+    pub fn new(session: zenoh::Session, key_expr_kmsg: String, boottime: u64) -> Self {
+        Self {
+            session,
+            key_expr_kmsg,
+            boottime,
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Start streaming kernel log records, the reply is empty.
+    pub fn start_kmsg(&mut self, payload: &[u8]) -> Result<Vec<u8>, MachineError> {
+        let request: pw::messages::KmsgRequest =
+            deserialize(payload).map_err(|e| machine_error!("Could not parse request ({})", e))?;
+
+        self.tasks.retain(|_, task| !task.is_finished());
+        if self.tasks.contains_key(&request.stream) {
+            return Err(machine_error!("Stream {} already running", request.stream));
+        }
+        if self.tasks.len() >= MAX_STREAMS {
+            return Err(machine_error!("Too many streams ({})", MAX_STREAMS));
+        }
+
+        let filter = KmsgFilter::new(request.level, &request.pattern)?;
+        let reader = KmsgReader::open(request.from_start)?;
+        let key_expr = format!("{}/{}", self.key_expr_kmsg, request.stream);
+
+        info!("Streaming kernel log on '{}'", key_expr);
+
+        let task = tokio::spawn(stream_kmsg(
+            self.session.clone(),
+            key_expr,
+            reader,
+            filter,
+            self.boottime,
+        ));
+        self.tasks.insert(request.stream, task);
+
+        Ok(serialize(&pw::messages::StreamRequest {
+            stream: request.stream,
+        }))
+    }
+
+    /// Stop a stream, the reply is the stopped stream.
+    pub fn stop(&mut self, payload: &[u8]) -> Result<Vec<u8>, MachineError> {
+        let request: pw::messages::StreamRequest =
+            deserialize(payload).map_err(|e| machine_error!("Could not parse request ({})", e))?;
+
+        let task = self
+            .tasks
+            .remove(&request.stream)
+            .ok_or_else(|| machine_error!("Unknown stream {}", request.stream))?;
+        task.abort();
+
+        info!("Stopped stream {}", request.stream);
+
+        Ok(serialize(&request))
+    }
+}
+
+impl Drop for Streams {
+    fn drop(&mut self) {
+        for task in self.tasks.values() {
+            task.abort();
+        }
+    }
+}
+
+async fn stream_kmsg(
+    session: zenoh::Session,
+    key_expr: String,
+    mut reader: KmsgReader,
+    filter: KmsgFilter,
+    boottime: u64,
+) {
+    let publisher = match session.declare_publisher(&key_expr).await {
+        Ok(publisher) => publisher,
+        Err(err) => {
+            warn!("Could not declare publisher on '{}' ({})", key_expr, err);
+            return;
+        }
+    };
+
+    let mut idle = Duration::ZERO;
+
+    loop {
+        while let Some(data) = reader.next_record() {
+            let Some(record) = parse_record(&data, boottime) else {
+                continue;
+            };
+
+            if filter.matches(&record) {
+                if let Err(err) = publisher
+                    .put(serialize(&record))
+                    .attachment(serialize(&protocol()))
+                    .await
+                {
+                    warn!("Could not publish kernel log record ({})", err);
+                }
+            }
+        }
+
+        let matching = publisher
+            .matching_status()
+            .await
+            .is_ok_and(|status| status.matching());
+        idle = if matching {
+            Duration::ZERO
+        } else {
+            idle + KMSG_POLL_INTERVAL
+        };
+
+        if idle >= IDLE_TIMEOUT {
+            info!("No subscribers on '{}', stopping", key_expr);
+            return;
+        }
+
+        tokio::time::sleep(KMSG_POLL_INTERVAL).await;
+    }
+}